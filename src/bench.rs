@@ -0,0 +1,89 @@
+//Bench: deterministic search of a fixed set of positions.
+//The total node count is the signature of the search, it changes only if the search
+//or the evaluation behave differently, so unintended changes can be detected.
+use std::sync::Arc;
+use std::time::Instant;
+use eval_params::EvalParams;
+use nnue::Network;
+use fen::parse_fen;
+use search::{SearchEngine, SearchLimits};
+use uci::move_to_uci;
+
+pub static DEFAULT_BENCH_DEPTH: usize = 10;
+
+static BENCH_POSITIONS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+    "1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/p1p5/1p5p/1P5p/8/PPP2K1p/4R1rk w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1"
+];
+
+//Returns the total number of nodes, every position is searched by a new engine in deterministic mode.
+//Report is called with the position index, the fen, the best move and the node count.
+pub fn bench(depth: usize, params: &EvalParams, network: Option<&Network>,
+             report: &mut dyn FnMut(usize, &str, String, u64)) -> u64 {
+    let params = Arc::new(params.clone());
+    let network = network.map(|n| Arc::new(n.clone()));
+    let mut total = 0;
+    for (i, &fen) in BENCH_POSITIONS.iter().enumerate() {
+        let pos = parse_fen(fen).unwrap();
+        let mut engine = SearchEngine::new();
+        engine.options.deterministic = true;
+        engine.set_eval_params(params.clone());
+        engine.set_network(network.clone());
+        let result = engine.search_with_limits(&pos, &SearchLimits::depth(depth), &mut |_| {});
+        let best_move = match result.best_move {
+            Some(mv) => move_to_uci(&mv, &pos, false).to_string(),
+            None => "(none)".to_string()
+        };
+        report(i, fen, best_move, result.nodes);
+        total += result.nodes;
+    }
+    total
+}
+
+//Entry point for: rchess bench [depth]
+pub fn run_cli(args: &[String], params: &EvalParams, network: Option<&Network>) -> Result<(), String> {
+    let depth = match args.first() {
+        Some(d) => match d.parse() {
+            Ok(d) if d > 0 => d,
+            _ => return Err(format!("Invalid bench depth: {}\nusage: rchess bench [depth]", d))
+        },
+        None => DEFAULT_BENCH_DEPTH
+    };
+
+    let start = Instant::now();
+    let total = bench(depth, params, network, &mut |i, fen, best_move, nodes| {
+        println!("Position {}/{}: {}", i + 1, BENCH_POSITIONS.len(), fen);
+        println!("bestmove {} nodes {}", best_move, nodes);
+    });
+    let elapsed = start.elapsed();
+    let msc = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+    println!();
+    println!("Time: {} ms", msc);
+    println!("Nodes/second: {}", total * 1000 / ::std::cmp::max(1, msc));
+    println!("Signature: {}", total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+use bench::*;
+
+#[test]
+fn bench_signature_test() {
+    ::tables::init_tables();
+    let params = EvalParams::default();
+    let mut first_run = Vec::new();
+    let signature = bench(5, &params, None, &mut |_, _, best_move, nodes| first_run.push((best_move, nodes)));
+    let mut second_run = Vec::new();
+    assert_eq!(bench(5, &params, None, &mut |_, _, best_move, nodes| second_run.push((best_move, nodes))), signature);
+    assert_eq!(first_run, second_run);
+    assert_eq!(first_run.iter().map(|&(_, n)| n).sum::<u64>(), signature);
+}
+
+}
@@ -0,0 +1,484 @@
+//Specialized endgame evaluation
+//Some endings can't be evaluated well by general terms: the lone king has to be driven
+//to the right corner to be mated, and some material advantages are not enough to win.
+//Endgame functions are registered by the material signature of the position.
+//Evaluation functions replace the general evaluation, scale factors reduce its endgame part.
+//More: https://www.chessprogramming.org/Endgame
+//      https://www.chessprogramming.org/KPK
+use std::collections::HashMap;
+use std::sync::Once;
+use eval::Score;
+use pawns::distance;
+use tables::{get_king_moves_mask, get_white_pawn_attacks_mask};
+use types::*;
+
+//score of a position that is won, but not a mate yet
+pub static KNOWN_WIN: Score = 10000;
+
+//scale factors of the endgame score, SCALE_NORMAL doesn't change it, SCALE_DRAW makes it 0
+pub static SCALE_NORMAL: Score = 64;
+pub static SCALE_DRAW: Score = 0;
+//opposite colored bishops with pawns only, the weaker side can blockade pawns on the other color
+static SCALE_OPPOSITE_BISHOPS: Score = 16;
+//rook against a minor piece is usually a draw
+static SCALE_ROOK_VS_MINOR: Score = 16;
+
+//fixed piece values, they only make wins with more material preferred,
+//so they don't depend on the evaluation parameters
+static PIECE_VALUES: [Score; 6] = [120, 320, 300, 540, 950, 20000];
+
+//Both functions get the stronger side, evaluation returns the score from its point of view.
+pub type EndgameEval = fn(&Position, Color) -> Score;
+pub type EndgameScale = fn(&Position, Color) -> Score;
+
+//Material signature: number of pawns, bishops, knights, rooks and queens of each color, 4 bits each.
+pub fn material_key(board: &Board) -> u64 {
+    let mut key = 0;
+    let mut shift = 0;
+    for &color in [White, Black].iter() {
+        for &kind in [Pawn, Bishop, Knight, Rook, Queen].iter() {
+            let count = ::std::cmp::min(board.get_pieces(kind, color).count(), 15) as u64;
+            key |= count << shift;
+            shift += 4;
+        }
+    }
+    key
+}
+
+//Material signature of an ending written as "KBNK": pieces of the stronger side and then of the weaker side.
+fn material_key_from_code(code: &str, strong: Color) -> u64 {
+    let weak_start = code[1..].find('K').expect("Invalid endgame code") + 1;
+    let mut key = 0;
+    for (pieces, color) in [(&code[1..weak_start], strong), (&code[weak_start + 1..], strong.inverse())].iter() {
+        for c in pieces.chars() {
+            let kind = match c {
+                'P' => Pawn,
+                'B' => Bishop,
+                'N' => Knight,
+                'R' => Rook,
+                'Q' => Queen,
+                _ => panic!("Invalid endgame code {}", code)
+            };
+            key += 1 << (4 * (*color as u64 * 5 + kind as u64));
+        }
+    }
+    key
+}
+
+#[derive(Clone)]
+pub struct EndgameRegistry {
+    evals: HashMap<u64, (EndgameEval, Color)>,
+    scales: HashMap<u64, (EndgameScale, Color)>,
+    //number of pieces including kings in the largest registered ending,
+    //positions with more pieces are not looked up
+    max_pieces: usize
+}
+
+impl EndgameRegistry {
+    pub fn new() -> EndgameRegistry {
+        let mut registry = EndgameRegistry { evals: HashMap::new(), scales: HashMap::new(), max_pieces: 0 };
+
+        registry.add_eval("KQK", eval_kxk);
+        registry.add_eval("KRK", eval_kxk);
+        registry.add_eval("KBNK", eval_kbnk);
+        registry.add_eval("KPK", eval_kpk);
+        //insufficient material
+        registry.add_eval("KK", eval_draw);
+        registry.add_eval("KBK", eval_draw);
+        registry.add_eval("KNK", eval_draw);
+        registry.add_eval("KNNK", eval_draw);
+
+        registry.add_scale("KRKB", scale_rook_vs_minor);
+        registry.add_scale("KRKN", scale_rook_vs_minor);
+        registry
+    }
+
+    //the ending is registered for both colors of the stronger side
+    pub fn add_eval(&mut self, code: &str, eval: EndgameEval) {
+        self.max_pieces = ::std::cmp::max(self.max_pieces, code.len());
+        for &color in [White, Black].iter() {
+            self.evals.insert(material_key_from_code(code, color), (eval, color));
+        }
+    }
+
+    pub fn add_scale(&mut self, code: &str, scale: EndgameScale) {
+        self.max_pieces = ::std::cmp::max(self.max_pieces, code.len());
+        for &color in [White, Black].iter() {
+            self.scales.insert(material_key_from_code(code, color), (scale, color));
+        }
+    }
+
+    #[inline]
+    fn material_key(&self, board: &Board) -> Option<u64> {
+        if board.occupied().count() <= self.max_pieces {
+            Some(material_key(board))
+        } else {
+            None
+        }
+    }
+
+    //Score from the white point of view if the position has a specialized evaluation.
+    pub fn probe_eval(&self, position: &Position) -> Option<Score> {
+        let key = self.material_key(&position.board)?;
+        self.evals.get(&key).map(|&(eval, strong)| {
+            let score = eval(position, strong);
+            if strong == White { score } else { -score }
+        })
+    }
+
+    //Scale factor of the endgame score, strong is the side that the score favors.
+    pub fn scale_factor(&self, position: &Position, strong: Color) -> Score {
+        let key = self.material_key(&position.board);
+        if let Some(&(scale, color)) = key.and_then(|key| self.scales.get(&key)) {
+            if color == strong {
+                return scale(position, strong);
+            }
+        }
+        //rules that apply to any number of pawns
+        if let Some(scale) = scale_wrong_bishop(&position.board, strong) {
+            return scale;
+        }
+        if let Some(scale) = scale_opposite_bishops(&position.board) {
+            return scale;
+        }
+        SCALE_NORMAL
+    }
+}
+
+//bonus for the lone king being close to the edge
+#[inline]
+fn push_to_edge(sq: Square) -> Score {
+    let file = sq.file() as Score;
+    let rank = sq.rank() as Score;
+    20 * (::std::cmp::max(3 - file, file - 4) + ::std::cmp::max(3 - rank, rank - 4))
+}
+
+//bonus for the kings being close to each other
+#[inline]
+fn push_close(a: Square, b: Square) -> Score {
+    140 - 20 * distance(a, b)
+}
+
+#[inline]
+fn king_square(board: &Board, color: Color) -> Square {
+    board.get_pieces(King, color).next().expect("No king on the board")
+}
+
+#[inline]
+fn is_dark_square(sq: Square) -> bool {
+    (sq.file() + sq.rank()) & 1 == 0
+}
+
+fn eval_draw(_: &Position, _: Color) -> Score {
+    0
+}
+
+//king and a major piece against the lone king: drive the king to the edge
+fn eval_kxk(position: &Position, strong: Color) -> Score {
+    let board = &position.board;
+    let strong_king = king_square(board, strong);
+    let weak_king = king_square(board, strong.inverse());
+    let material: Score = [Rook, Queen].iter()
+        .map(|&kind| board.get_pieces(kind, strong).count() as Score * PIECE_VALUES[kind as usize])
+        .sum();
+    KNOWN_WIN + material + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+//king, bishop and knight against the lone king: the mate is possible only in a corner of the bishop's color
+fn eval_kbnk(position: &Position, strong: Color) -> Score {
+    let board = &position.board;
+    let strong_king = king_square(board, strong);
+    let weak_king = king_square(board, strong.inverse());
+    let bishop = board.get_pieces(Bishop, strong).next().expect("No bishop on the board");
+    let corners = if is_dark_square(bishop) {
+        [Square::new(0, 0), Square::new(7, 7)]
+    } else {
+        [Square::new(0, 7), Square::new(7, 0)]
+    };
+    let corner_distance = ::std::cmp::min(distance(weak_king, corners[0]), distance(weak_king, corners[1]));
+    KNOWN_WIN + PIECE_VALUES[Bishop as usize] + PIECE_VALUES[Knight as usize] +
+        40 * (7 - corner_distance) + push_close(strong_king, weak_king)
+}
+
+//king and pawn against king: exact result from the bitbase
+fn eval_kpk(position: &Position, strong: Color) -> Score {
+    let board = &position.board;
+    let pawn = board.get_pieces(Pawn, strong).next().expect("No pawn on the board");
+    //normalize, so that the pawn is white and it is on files a-d
+    let normalize = |sq: Square| {
+        let sq = if strong == White { sq } else { Square::new(sq.file(), 7 - sq.rank()) };
+        if pawn.file() > 3 { Square::new(7 - sq.file(), sq.rank()) } else { sq }
+    };
+    let psq = normalize(pawn);
+    let win = kpk_probe(position.next_to_move == strong,
+                        normalize(king_square(board, strong)),
+                        normalize(king_square(board, strong.inverse())),
+                        psq);
+    if win {
+        KNOWN_WIN + PIECE_VALUES[Pawn as usize] + 20 * psq.rank() as Score
+    } else {
+        0
+    }
+}
+
+fn scale_rook_vs_minor(_: &Position, _: Color) -> Score {
+    SCALE_ROOK_VS_MINOR
+}
+
+//Each side has one bishop, bishops are on different colors and there are no other pieces except pawns.
+fn scale_opposite_bishops(board: &Board) -> Option<Score> {
+    if !(board.knights | board.rooks | board.queens).is_empty() {
+        return None;
+    }
+    let mut white_bishops = board.get_pieces(Bishop, White);
+    let mut black_bishops = board.get_pieces(Bishop, Black);
+    match (white_bishops.next(), black_bishops.next(), white_bishops.next(), black_bishops.next()) {
+        (Some(w), Some(b), None, None) if is_dark_square(w) != is_dark_square(b) => Some(SCALE_OPPOSITE_BISHOPS),
+        _ => None
+    }
+}
+
+//Bishop and pawns on a rook file, when the bishop doesn't control the promotion square
+//and the weak king stands in front of the pawns, is a draw.
+fn scale_wrong_bishop(board: &Board, strong: Color) -> Option<Score> {
+    let strong_pieces = board.get_color_bitset(strong);
+    if !((board.knights | board.rooks | board.queens) & strong_pieces).is_empty() {
+        return None;
+    }
+    let mut bishops = board.get_pieces(Bishop, strong);
+    let bishop = match (bishops.next(), bishops.next()) {
+        (Some(bishop), None) => bishop,
+        _ => return None
+    };
+    let pawns = board.get_pieces(Pawn, strong).collect::<Vec<_>>();
+    let file = match pawns.first() {
+        Some(sq) if sq.file() == 0 || sq.file() == 7 => sq.file(),
+        _ => return None
+    };
+    if pawns.iter().any(|sq| sq.file() != file) {
+        return None;
+    }
+    let promotion = Square::new(file, if strong == White { 7 } else { 0 });
+    let weak_king = king_square(board, strong.inverse());
+    if is_dark_square(bishop) != is_dark_square(promotion) && distance(weak_king, promotion) <= 1 {
+        Some(SCALE_DRAW)
+    } else {
+        None
+    }
+}
+
+//KPK bitbase: one bit for every position with the white pawn on files a-d,
+//set if the position is won for white.
+//Index: side to move in bit 0, black king in bits 1-6, white king in bits 7-12,
+//pawn file in bits 13-14 and (7th rank - pawn rank) in bits 15-17.
+const KPK_SIZE: usize = 2 * 64 * 64 * 4 * 6;
+static mut KPK_BITBASE: [u64; KPK_SIZE / 64] = [0; KPK_SIZE / 64];
+static KPK_INIT: Once = Once::new();
+
+const KPK_INVALID: u8 = 0;
+const KPK_UNKNOWN: u8 = 1;
+const KPK_DRAW: u8 = 2;
+const KPK_WIN: u8 = 4;
+
+#[inline]
+fn kpk_index(white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> usize {
+    (if white_to_move { 0 } else { 1 }) |
+        (black_king.file_and_rank() as usize) << 1 |
+        (white_king.file_and_rank() as usize) << 7 |
+        (pawn.file() as usize) << 13 |
+        (6 - pawn.rank() as usize) << 15
+}
+
+#[inline]
+fn kpk_decode(index: usize) -> (bool, Square, Square, Square) {
+    (index & 1 == 0,
+     Square((index >> 7 & 63) as u8),
+     Square((index >> 1 & 63) as u8),
+     Square::new((index >> 13 & 3) as u8, (6 - (index >> 15 & 7)) as u8))
+}
+
+//positions that can be classified without looking at the moves
+fn kpk_initial(index: usize) -> u8 {
+    let (white_to_move, white_king, black_king, pawn) = kpk_decode(index);
+    let white_king_moves = get_king_moves_mask(white_king);
+    let black_king_moves = get_king_moves_mask(black_king);
+    let pawn_attacks = get_white_pawn_attacks_mask(pawn);
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn ||
+        (white_to_move && pawn_attacks.get(black_king)) {
+        return KPK_INVALID;
+    }
+    if white_to_move && pawn.rank() == 6 {
+        //pawn promotes and the queen can't be captured
+        let promotion = Square(pawn.file_and_rank() + 8);
+        if white_king != promotion && (distance(black_king, promotion) > 1 || distance(white_king, promotion) == 1) {
+            return KPK_WIN;
+        }
+    }
+    if !white_to_move {
+        let stalemate = (black_king_moves & !(white_king_moves | pawn_attacks)).is_empty();
+        let pawn_captured = (black_king_moves & !white_king_moves).get(pawn);
+        if stalemate || pawn_captured {
+            return KPK_DRAW;
+        }
+    }
+    KPK_UNKNOWN
+}
+
+//result of an unknown position from the results of positions after each move
+fn kpk_classify(db: &[u8], index: usize) -> u8 {
+    let (white_to_move, white_king, black_king, pawn) = kpk_decode(index);
+    let mut results = KPK_INVALID;
+    if white_to_move {
+        for sq in get_king_moves_mask(white_king).iter() {
+            results |= db[kpk_index(false, sq, black_king, pawn)];
+        }
+        if pawn.rank() < 6 {
+            let push = Square(pawn.file_and_rank() + 8);
+            if push != white_king && push != black_king {
+                results |= db[kpk_index(false, white_king, black_king, push)];
+                let double_push = Square(push.file_and_rank() + 8);
+                if pawn.rank() == 1 && double_push != white_king && double_push != black_king {
+                    results |= db[kpk_index(false, white_king, black_king, double_push)];
+                }
+            }
+        }
+        if results & KPK_WIN != 0 { KPK_WIN } else if results & KPK_UNKNOWN != 0 { KPK_UNKNOWN } else { KPK_DRAW }
+    } else {
+        for sq in get_king_moves_mask(black_king).iter() {
+            results |= db[kpk_index(true, white_king, sq, pawn)];
+        }
+        if results & KPK_DRAW != 0 { KPK_DRAW } else if results & KPK_UNKNOWN != 0 { KPK_UNKNOWN } else { KPK_WIN }
+    }
+}
+
+//Computes the bitbase once, it requires move tables to be initialized.
+pub fn init_kpk_bitbase() {
+    KPK_INIT.call_once(|| {
+        let mut db: Vec<u8> = (0..KPK_SIZE).map(kpk_initial).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..KPK_SIZE {
+                if db[index] == KPK_UNKNOWN {
+                    let result = kpk_classify(&db, index);
+                    if result != KPK_UNKNOWN {
+                        db[index] = result;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        //positions that are still unknown can't be won
+        unsafe {
+            for (index, &result) in db.iter().enumerate() {
+                if result == KPK_WIN {
+                    KPK_BITBASE[index / 64] |= 1 << (index % 64);
+                }
+            }
+        }
+    });
+}
+
+//Position has the white pawn on files a-d. Returns true if white wins.
+pub fn kpk_probe(white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> bool {
+    debug_assert!(pawn.file() <= 3, "pawn is not normalized");
+    let index = kpk_index(white_to_move, white_king, black_king, pawn);
+    unsafe {
+        KPK_BITBASE[index / 64] & 1 << (index % 64) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use endgame::*;
+use fen::parse_fen;
+
+fn eval(registry: &EndgameRegistry, fen: &str) -> Option<Score> {
+    registry.probe_eval(&parse_fen(fen).unwrap())
+}
+
+#[test]
+fn material_key_test() {
+    let position = parse_fen("4k3/8/8/8/8/8/8/1N2KB2 w - - 0 1").unwrap();
+    assert_eq!(material_key(&position.board), material_key_from_code("KBNK", White));
+    assert!(material_key(&position.board) != material_key_from_code("KBNK", Black));
+    let position = parse_fen("4k3/pp6/8/8/8/8/8/4K2r w - - 0 1").unwrap();
+    assert_eq!(material_key(&position.board), material_key_from_code("KRPPK", Black));
+    assert_eq!(material_key_from_code("KK", White), 0);
+}
+
+#[test]
+fn kpk_test() {
+    ::tables::init_tables();
+    let registry = EndgameRegistry::new();
+    //king in front of the pawn on the 6th rank wins with any side to move
+    assert!(eval(&registry, "4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap() > KNOWN_WIN);
+    assert!(eval(&registry, "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").unwrap() > KNOWN_WIN);
+    //the defending king takes the opposition
+    assert_eq!(eval(&registry, "4k3/8/4P3/4K3/8/8/8/8 w - - 0 1"), Some(0));
+    //rook pawn with the defending king in the corner
+    assert_eq!(eval(&registry, "k7/8/K7/P7/8/8/8/8 w - - 0 1"), Some(0));
+    //the defending king is out of the square of the pawn
+    assert!(eval(&registry, "8/8/8/8/8/k7/7P/7K w - - 0 1").unwrap() > KNOWN_WIN);
+    //the same positions with colors swapped
+    assert!(eval(&registry, "8/8/8/8/4p3/4k3/8/4K3 w - - 0 1").unwrap() < -KNOWN_WIN);
+    assert_eq!(eval(&registry, "8/8/8/8/4k3/4p3/8/4K3 b - - 0 1"), Some(0));
+    assert!(eval(&registry, "7k/7p/K7/8/8/8/8/8 b - - 0 1").unwrap() < -KNOWN_WIN);
+}
+
+#[test]
+fn mating_patterns_test() {
+    ::tables::init_tables();
+    let registry = EndgameRegistry::new();
+    //lone king is driven to the edge
+    let center = eval(&registry, "8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+    let edge = eval(&registry, "4k3/8/4K3/8/8/8/8/R7 w - - 0 1").unwrap();
+    assert!(center > KNOWN_WIN);
+    assert!(edge > center);
+    assert!(eval(&registry, "4k3/8/8/8/8/8/8/Q3K3 w - - 0 1").unwrap() > edge);
+
+    //dark squared bishop mates in a1 or h8
+    let right_corner = eval(&registry, "8/8/8/8/8/2K5/8/k1B1N3 w - - 0 1").unwrap();
+    let wrong_corner = eval(&registry, "k2BN3/8/2K5/8/8/8/8/8 w - - 0 1").unwrap();
+    assert!(wrong_corner > KNOWN_WIN);
+    assert!(right_corner > wrong_corner);
+    //black pieces, light squared bishop mates in a8 or h1
+    let right_corner = eval(&registry, "8/8/8/8/8/8/5k2/1b2n2K b - - 0 1").unwrap();
+    let wrong_corner = eval(&registry, "8/8/8/8/8/8/2k5/Kb2n3 b - - 0 1").unwrap();
+    assert!(right_corner < wrong_corner);
+    assert!(wrong_corner < -KNOWN_WIN);
+
+    //insufficient material
+    assert_eq!(eval(&registry, "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1"), Some(0));
+    assert_eq!(eval(&registry, "4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"), Some(0));
+    assert_eq!(eval(&registry, "4kb2/8/8/8/8/8/8/4K3 w - - 0 1"), Some(0));
+    //not a registered ending
+    assert_eq!(eval(&registry, "4k3/8/8/8/8/8/8/1NR1K3 w - - 0 1"), None);
+}
+
+#[test]
+fn scale_factor_test() {
+    ::tables::init_tables();
+    let registry = EndgameRegistry::new();
+    let scale = |fen: &str, strong: Color| {
+        registry.scale_factor(&parse_fen(fen).unwrap(), strong)
+    };
+    assert_eq!(scale("4k3/8/8/8/8/8/8/2b1K2R w - - 0 1", White), SCALE_ROOK_VS_MINOR);
+    assert_eq!(scale("4k3/8/8/8/8/8/8/2n1K2R w - - 0 1", White), SCALE_ROOK_VS_MINOR);
+    assert_eq!(scale("4k3/8/8/8/8/8/8/2n1K2R w - - 0 1", Black), SCALE_NORMAL);
+
+    //opposite colored bishops
+    assert_eq!(scale("4k3/2b2ppp/8/8/8/8/4BPPP/4K3 w - - 0 1", White), SCALE_OPPOSITE_BISHOPS);
+    //bishops of the same color
+    assert_eq!(scale("4k3/5ppp/3b4/8/8/8/3B1PPP/4K3 w - - 0 1", White), SCALE_NORMAL);
+
+    //h8 is dark, light squared bishop can't drive the king out of the corner
+    assert_eq!(scale("7k/8/7P/7P/8/8/3B4/4K3 w - - 0 1", White), SCALE_NORMAL);
+    assert_eq!(scale("7k/8/7P/7P/8/3B4/8/4K3 w - - 0 1", White), SCALE_DRAW);
+    //the king is too far from the corner
+    assert_eq!(scale("8/8/4k2P/7P/8/3B4/8/4K3 w - - 0 1", White), SCALE_NORMAL);
+    //pawns on different files
+    assert_eq!(scale("7k/8/6P1/7P/8/3B4/8/4K3 w - - 0 1", White), SCALE_NORMAL);
+}
+
+}
@@ -1,5 +1,14 @@
 //Position evaluation
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::sync::Once;
 use types::*;
+use hash::calc_pawn_hash;
+use pawns::{PawnHashTable, eval_pawn_structure, eval_passed_pawns};
+use mobility::eval_pieces;
+use king_safety::eval_king_safety;
+use endgame::{EndgameRegistry, SCALE_NORMAL};
+use eval_params::EvalParams;
+use trace::{Trace, NoTrace, LinearTrace, EvalBreakdown, Term};
 pub use self::GameStage::*;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -17,246 +26,336 @@ pub trait Evaluator {
 
     //tells what stage of the game we are at
     fn classify(&self, position: &Position) -> GameStage;
-}
 
+    //Evaluation that may stop at a cheap estimate when it is far outside the window from alpha to beta
+    //(from the white point of view), so the exact score would not be inside the window either.
+    fn eval_lazy(&self, position: &Position, _alpha: Score, _beta: Score) -> Score {
+        self.eval(position)
+    }
 
-//very simple position evaluation based mostly on https://www.chessprogramming.org/Simplified_Evaluation_Function
-#[derive(Copy)]
-pub struct SimpleEvaluator {
-    //arrays that eastimate relative value of each a piece in each square
-    white_pawn_weights : [i8; 64],
-    black_pawn_weights : [i8; 64],
+    //The search reports the positions it visits, so evaluators with an incremental state can update it.
+    //Called when a search starts from a new root position.
+    fn set_root(&mut self, _position: &Position) {}
 
-    white_knight_weights : [i8; 64],
-    black_knight_weights : [i8; 64],
+    //called after a move is made, before the search of the new position
+    fn make_move(&mut self, _before: &Position, _after: &Position) {}
 
-    white_bishop_weights : [i8; 64],
-    black_bishop_weights : [i8; 64],
+    //called when the search returns to the position before the last move
+    fn unmake_move(&mut self) {}
+}
 
-    white_rook_weights : [i8; 64],
-    black_rook_weights : [i8; 64],
 
-    white_queen_weights : [i8; 64],
-    black_queen_weights : [i8; 64],
+//Evaluation is done separately for middlegame and endgame and then interpolated by the game phase,
+//so the score changes smoothly when pieces are traded.
+//More: https://www.chessprogramming.org/Tapered_Eval
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct TaperedScore {
+    pub mg: Score,
+    pub eg: Score
+}
 
-    white_king_weights : [i8; 64],
-    black_king_weights : [i8; 64],
+impl TaperedScore {
+    pub fn new(mg: Score, eg: Score) -> TaperedScore {
+        TaperedScore { mg: mg, eg: eg }
+    }
 
-    white_endgame_king_weights : [i8; 64],
-    black_endgame_king_weights : [i8; 64]
+    //phase is MAX_PHASE when all pieces are on the board and 0 when only kings and pawns are left
+    #[inline]
+    pub fn taper(self, phase: i32) -> Score {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
 
+impl Add for TaperedScore {
+    type Output = TaperedScore;
+    fn add(self, other: TaperedScore) -> TaperedScore {
+        TaperedScore::new(self.mg + other.mg, self.eg + other.eg)
+    }
 }
 
-impl Clone for SimpleEvaluator {
-    fn clone(&self) -> Self {
-            *self
+impl AddAssign for TaperedScore {
+    fn add_assign(&mut self, other: TaperedScore) {
+        self.mg += other.mg;
+        self.eg += other.eg;
     }
 }
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-impl SimpleEvaluator {
-    pub fn new() -> SimpleEvaluator {
-        let white_pawn_weights : [i8; 64] = mirror_weights_table(
-          &[0,  0,  0,  0,  0,  0,  0,  0,
-            50, 50, 50, 50, 50, 50, 50, 50,
-            10, 10, 20, 30, 30, 20, 10, 10,
-             5,  5, 10, 25, 25, 10,  5,  5,
-             0,  0,  0, 20, 20,  0,  0,  0,
-             5, -5,-10,  0,  0,-10, -5,  5,
-             5, 10, 10,-20,-20, 10, 10,  5,
-             0,  0,  0,  0,  0,  0,  0,  0]);
-
-        let white_knight_weights : [i8; 64] = mirror_weights_table(
-          &[-50,-40,-30,-30,-30,-30,-40,-50,
-            -40,-20,  0,  0,  0,  0,-20,-40,
-            -30,  0, 10, 15, 15, 10,  0,-30,
-            -30,  5, 15, 20, 20, 15,  5,-30,
-            -30,  0, 15, 20, 20, 15,  0,-30,
-            -30,  5, 10, 15, 15, 10,  5,-30,
-            -40,-20,  0,  5,  5,  0,-20,-40,
-            -50,-40,-30,-30,-30,-30,-40,-50]);
-
-        let white_bishop_weights : [i8; 64] = mirror_weights_table(
-          &[-20,-10,-10,-10,-10,-10,-10,-20,
-            -10,  0,  0,  0,  0,  0,  0,-10,
-            -10,  0,  5, 10, 10,  5,  0,-10,
-            -10,  5,  5, 10, 10,  5,  5,-10,
-            -10,  0, 10, 10, 10, 10,  0,-10,
-            -10, 10, 10, 10, 10, 10, 10,-10,
-            -10,  5,  0,  0,  0,  0,  5,-10,
-            -20,-10,-10,-10,-10,-10,-10,-20]);
-
-        let white_rook_weights : [i8; 64] = mirror_weights_table(
-           &[0,  0,  0,  0,  0,  0,  0,  0,
-             5, 10, 10, 10, 10, 10, 10,  5,
-            -5,  0,  0,  0,  0,  0,  0, -5,
-            -5,  0,  0,  0,  0,  0,  0, -5,
-            -5,  0,  0,  0,  0,  0,  0, -5,
-            -5,  0,  0,  0,  0,  0,  0, -5,
-            -5,  0,  0,  0,  0,  0,  0, -5,
-             0,  0,  0,  5,  5,  0,  0,  0]);
-
-        let white_queen_weights : [i8; 64] = mirror_weights_table(
-          &[-20,-10,-10, -5, -5,-10,-10,-20,
-            -10,  0,  0,  0,  0,  0,  0,-10,
-            -10,  0,  5,  5,  5,  5,  0,-10,
-             -5,  0,  5,  5,  5,  5,  0, -5,
-              0,  0,  5,  5,  5,  5,  0, -5,
-            -10,  5,  5,  5,  5,  5,  0,-10,
-            -10,  0,  5,  0,  0,  0,  0,-10,
-            -20,-10,-10, -5, -5,-10,-10,-20]);
-
-        let white_king_weights : [i8; 64] = mirror_weights_table(
-          &[-30,-40,-40,-50,-50,-40,-40,-30,
-            -30,-40,-40,-50,-50,-40,-40,-30,
-            -30,-40,-40,-50,-50,-40,-40,-30,
-            -30,-40,-40,-50,-50,-40,-40,-30,
-            -20,-30,-30,-40,-40,-30,-30,-20,
-            -10,-20,-20,-20,-20,-20,-20,-10,
-             20, 20,  0,  0,  0,  0, 20, 20,
-             20, 30, 10,  0,  0, 10, 30, 20]);
-
-        let white_endgame_king_weights : [i8; 64] = mirror_weights_table(
-          &[-50,-40,-30,-20,-20,-30,-40,-50,
-            -30,-20,-10,  0,  0,-10,-20,-30,
-            -30,-10, 20, 30, 30, 20,-10,-30,
-            -30,-10, 30, 40, 40, 30,-10,-30,
-            -30,-10, 30, 40, 40, 30,-10,-30,
-            -30,-10, 20, 30, 30, 20,-10,-30,
-            -30,-30,  0,  0,  0,  0,-30,-30,
-            -50,-30,-30,-30,-30,-30,-30,-50]);
+impl Sub for TaperedScore {
+    type Output = TaperedScore;
+    fn sub(self, other: TaperedScore) -> TaperedScore {
+        TaperedScore::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
 
-        SimpleEvaluator {
-            white_pawn_weights : white_pawn_weights,
-            black_pawn_weights : mirror_weights_table(&white_pawn_weights),
+impl SubAssign for TaperedScore {
+    fn sub_assign(&mut self, other: TaperedScore) {
+        self.mg -= other.mg;
+        self.eg -= other.eg;
+    }
+}
 
-            white_knight_weights : white_knight_weights,
-            black_knight_weights : mirror_weights_table(&white_knight_weights),
+impl Neg for TaperedScore {
+    type Output = TaperedScore;
+    fn neg(self) -> TaperedScore {
+        TaperedScore::new(-self.mg, -self.eg)
+    }
+}
 
-            white_bishop_weights : white_bishop_weights,
-            black_bishop_weights : mirror_weights_table(&white_bishop_weights),
+impl Mul<i32> for TaperedScore {
+    type Output = TaperedScore;
+    fn mul(self, n: i32) -> TaperedScore {
+        TaperedScore::new(self.mg * n, self.eg * n)
+    }
+}
 
-            white_rook_weights : white_rook_weights,
-            black_rook_weights : mirror_weights_table(&white_rook_weights),
+//Material and piece-square values of every piece on every square with the default parameters,
+//negative for black pieces, used to update Position::psq incrementally.
+//Evaluators with other material or piece-square values (EvalFile, tuned sets) can't use Position::psq,
+//see SimpleEvaluator::eval_psq.
+static mut PSQ_TABLE: [[[TaperedScore; 64]; 6]; 2] = [[[TaperedScore { mg: 0, eg: 0 }; 64]; 6]; 2];
+static PSQ_INIT: Once = Once::new();
 
-            white_queen_weights : white_queen_weights,
-            black_queen_weights : mirror_weights_table(&white_queen_weights),
+//positions with the estimate from material and piece-square tables further than this outside
+//the window are not evaluated further, the other terms rarely add up to more
+pub static LAZY_EVAL_MARGIN: Score = 500;
 
-            white_king_weights : white_king_weights,
-            black_king_weights : mirror_weights_table(&white_king_weights),
+pub fn init_psq_table() {
+    PSQ_INIT.call_once(|| {
+        let params = EvalParams::default();
+        for &color in [White, Black].iter() {
+            for &kind in [Pawn, Bishop, Knight, Rook, Queen, King].iter() {
+                for sq in 0..64 {
+                    let score = params.piece_values[kind as usize] + params.pst[kind as usize][pst_index(color, Square(sq))];
+                    unsafe {
+                        PSQ_TABLE[color as usize][kind as usize][sq as usize] = if color == White { score } else { -score };
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[inline]
+pub fn psq_value(Piece(kind, color): Piece, sq: Square) -> TaperedScore {
+    debug_assert!(PSQ_INIT.is_completed(), "tables::init_tables must be called before positions are updated");
+    unsafe {
+        PSQ_TABLE[color as usize][kind as usize][sq.0 as usize]
+    }
+}
 
-            white_endgame_king_weights : white_endgame_king_weights,
-            black_endgame_king_weights : mirror_weights_table(&white_endgame_king_weights),
+//Material and piece-square score of a board with the default parameters, white minus black.
+//Positions are created with it, so it fills the table if init_tables hasn't done it yet.
+pub fn calc_psq(board: &Board) -> TaperedScore {
+    init_psq_table();
+    let mut result = TaperedScore::default();
+    for &color in [White, Black].iter() {
+        for &kind in [Pawn, Bishop, Knight, Rook, Queen, King].iter() {
+            for sq in board.get_pieces(kind, color) {
+                result += psq_value(Piece(kind, color), sq);
+            }
         }
     }
+    result
+}
 
-    fn eval_material(&self, position: &Position) -> Score {
-        let board = &position.board;
-        let queens = (board.queens & board.whites).count() as i32 -
-                     (board.queens & board.blacks).count() as i32;
+//Game phase is computed from the remaining pieces: 1 for each knight and bishop, 2 for each rook, 4 for each queen.
+//It is capped at MAX_PHASE, which is the phase of the initial position.
+pub static MAX_PHASE: i32 = 24;
+static PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
 
-        let rooks =  (board.rooks & board.whites).count() as i32 -
-                     (board.rooks & board.blacks).count() as i32;
+//stage of the game by the material left on the board and the move number
+pub fn game_stage(position: &Position) -> GameStage {
+    let board = &position.board;
+    let no_queens = board.queens.is_empty();
+    let no_rooks = board.rooks.is_empty();
+    let minor_pieces_count = (board.bishops | board.knights).count();
 
-        let bishops =(board.bishops & board.whites).count() as i32 -
-                     (board.bishops & board.blacks).count() as i32;
+    if  no_queens || (no_rooks && minor_pieces_count <= 2) {
+        return Endgame;
+    }
 
-        let knights =(board.knights & board.whites).count() as i32 -
-                     (board.knights & board.blacks).count() as i32;
+    if position.full_moves < 8 {
+        return Opening;
+    }
 
-        let kings =  (board.kings & board.whites).count() as i32 -
-                     (board.kings & board.blacks).count() as i32;
+    Middlegame
+}
 
-        let pawns =  (board.pawns & board.whites).count() as i32 -
-                     (board.pawns & board.blacks).count() as i32;
+pub fn game_phase(position: &Position) -> i32 {
+    let board = &position.board;
+    let phase: i32 = [Bishop, Knight, Rook, Queen].iter()
+        .map(|&kind| board.get_piece_bitset(kind).count() as i32 * PHASE_WEIGHTS[kind as usize])
+        .sum();
+    ::std::cmp::min(phase, MAX_PHASE)
+}
 
-        (pawns * 100) + (knights * 320) + (bishops * 330) + (rooks * 500) + (queens * 900) + (kings * 20000)
+//very simple position evaluation based mostly on https://www.chessprogramming.org/Simplified_Evaluation_Function
+//extended with pawn structure, mobility, king safety and endgame knowledge
+#[derive(Clone)]
+pub struct SimpleEvaluator {
+    params : EvalParams,
+    //material and piece-square tables are the default ones, so Position::psq can be used,
+    //otherwise they are summed over all pieces on every evaluation
+    default_psq : bool,
+    pawn_table : PawnHashTable,
+    endgames : EndgameRegistry
+}
+
+impl SimpleEvaluator {
+    pub fn new() -> SimpleEvaluator {
+        SimpleEvaluator::with_params(EvalParams::default())
     }
 
-    fn eval_piece_positions(&self, position: &Position) -> Score {
-        let board = &position.board;
-        let stage = self.classify(position);
-        let mut result = 0i32;
-        for &color in [White, Black].iter() {
-            for &kind in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
-                for sq in board.get_pieces(kind, color) {
-                    result += self.eval_one_piece_position(Piece(kind, color), sq, stage);
-                }
-            }
+    pub fn with_params(params: EvalParams) -> SimpleEvaluator {
+        let defaults = EvalParams::default();
+        SimpleEvaluator {
+            default_psq : params.piece_values == defaults.piece_values && params.pst == defaults.pst,
+            params : params,
+            pawn_table : PawnHashTable::new(),
+            endgames : EndgameRegistry::new()
         }
-        result
     }
 
-    #[inline]
-    fn eval_one_piece_position(&self, piece: Piece, sq:Square, stage: GameStage) -> Score {
-        let table = match piece {
-            Piece(Pawn, White) => self.white_pawn_weights,
-            Piece(Pawn, Black) => self.black_pawn_weights,
-
-            Piece(Knight, White) => self.white_knight_weights,
-            Piece(Knight, Black) => self.black_knight_weights,
-
-            Piece(Bishop, White) => self.white_bishop_weights,
-            Piece(Bishop, Black) => self.black_bishop_weights,
-
-            Piece(Rook, White) => self.white_rook_weights,
-            Piece(Rook, Black) => self.black_rook_weights,
+    pub fn params(&self) -> &EvalParams {
+        &self.params
+    }
 
-            Piece(Queen, White) => self.white_queen_weights,
-            Piece(Queen, Black) => self.black_queen_weights,
+    //evaluation with the coefficients of all linear terms, used by the tuner
+    pub fn trace(&self, position: &Position) -> LinearTrace {
+        let mut trace = LinearTrace::new();
+        trace.endgame = self.endgames.probe_eval(position);
+        let score = self.eval_terms(position, &mut trace);
+        trace.scale = self.scale_factor(position, score);
+        trace.phase = game_phase(position);
+        trace
+    }
 
-            Piece(King, White) if stage == Endgame => self.white_endgame_king_weights,
-            Piece(King, White) => self.white_king_weights,
+    //evaluation split into terms for each side, used to see why a position is evaluated the way it is
+    pub fn breakdown(&self, position: &Position) -> EvalBreakdown {
+        let mut breakdown = EvalBreakdown::new(&self.params);
+        breakdown.endgame = self.endgames.probe_eval(position);
+        let score = self.eval_terms(position, &mut breakdown);
+        breakdown.scale = self.scale_factor(position, score);
+        breakdown.phase = game_phase(position);
+        breakdown.score = self.eval(position);
+        breakdown
+    }
 
-            Piece(King, Black) if stage == Endgame => self.black_endgame_king_weights,
-            Piece(King, Black) => self.black_king_weights,
+    //sum of all terms before the endgame scaling, from the white point of view
+    fn eval_terms<T: Trace>(&self, position: &Position, trace: &mut T) -> TaperedScore {
+        let psq = if T::ACTIVE {
+            self.eval_material(position, trace) + self.eval_piece_positions(position, trace)
+        } else {
+            self.eval_psq(position)
         };
+        psq + self.eval_positional(position, trace)
+    }
 
-        if piece.color() == White {
-            table[sq.file_and_rank() as usize] as Score
+    //material and piece-square tables, kept up to date by the position for the default parameters only
+    fn eval_psq(&self, position: &Position) -> TaperedScore {
+        if self.default_psq {
+            debug_assert_eq!(position.psq, calc_psq(&position.board), "{}", ::fen::render_fen(position));
+            position.psq
         } else {
-            -table[sq.file_and_rank() as usize] as Score
+            self.eval_material(position, &mut NoTrace) + self.eval_piece_positions(position, &mut NoTrace)
         }
     }
-}
 
-impl Evaluator for SimpleEvaluator {
-    fn eval(&self, position: &Position) -> Score {
-        self.eval_material(position) +
-        self.eval_piece_positions(position)
+    //the expensive terms
+    fn eval_positional<T: Trace>(&self, position: &Position, trace: &mut T) -> TaperedScore {
+        self.eval_pawns(position, trace) + eval_pieces(&position.board, &self.params, trace) +
+            eval_king_safety(&position.board, &self.params, trace)
     }
 
-    fn classify(&self, position: &Position) -> GameStage {
-        let board = &position.board;
-        let no_queens = board.queens.is_empty();
-        let no_rooks = board.rooks.is_empty();
-        let minor_pieces_count = (board.bishops | board.knights).count();
+    fn scale(&self, position: &Position, mut score: TaperedScore) -> TaperedScore {
+        score.eg = score.eg * self.scale_factor(position, score) / SCALE_NORMAL;
+        score
+    }
 
-        if  no_queens || (no_rooks && minor_pieces_count <= 2) {
-            return Endgame;
+    //drawish endings reduce the advantage of the stronger side
+    fn scale_factor(&self, position: &Position, score: TaperedScore) -> Score {
+        let strong = if score.eg >= 0 { White } else { Black };
+        self.endgames.scale_factor(position, strong)
+    }
+
+    fn eval_material<T: Trace>(&self, position: &Position, trace: &mut T) -> TaperedScore {
+        let board = &position.board;
+        let mut result = TaperedScore::default();
+        trace.begin(Term::Material);
+        for &kind in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
+            let whites = (board.get_piece_bitset(kind) & board.whites).count() as i32;
+            let blacks = (board.get_piece_bitset(kind) & board.blacks).count() as i32;
+            result += self.params.piece_values[kind as usize] * (whites - blacks);
+            trace.add(White, whites, |p| &mut p.piece_values[kind as usize]);
+            trace.add(Black, blacks, |p| &mut p.piece_values[kind as usize]);
         }
+        result
+    }
 
-        if position.full_moves < 8 {
-            return Opening;
+    fn eval_piece_positions<T: Trace>(&self, position: &Position, trace: &mut T) -> TaperedScore {
+        let board = &position.board;
+        let mut result = TaperedScore::default();
+        for &color in [White, Black].iter() {
+            for &kind in [Pawn, Knight, Bishop, Rook, Queen, King].iter() {
+                trace.begin(Term::PieceSquare(kind));
+                for sq in board.get_pieces(kind, color) {
+                    let index = pst_index(color, sq);
+                    let score = self.params.pst[kind as usize][index];
+                    result += if color == White { score } else { -score };
+                    trace.add(color, 1, |p| &mut p.pst[kind as usize][index]);
+                }
+            }
         }
+        result
+    }
+
+    //the pawn hash table is skipped when tracing, cached entries have no trace
+    fn eval_pawns<T: Trace>(&self, position: &Position, trace: &mut T) -> TaperedScore {
+        trace.begin(Term::PawnStructure);
+        let entry = if T::ACTIVE {
+            eval_pawn_structure(&position.board, calc_pawn_hash(position), &self.params, trace)
+        } else {
+            self.pawn_table.probe(position, &self.params)
+        };
+        entry.score + eval_passed_pawns(&position.board, &entry.passed, &self.params, trace)
+    }
+}
 
-        Middlegame
+//tables are in the diagram order from the white point of view, so for white pieces ranks are flipped
+#[inline]
+fn pst_index(color: Color, sq: Square) -> usize {
+    match color {
+        White => (sq.file_and_rank() ^ 56) as usize,
+        Black => sq.file_and_rank() as usize
     }
 }
 
+impl Evaluator for SimpleEvaluator {
+    fn eval(&self, position: &Position) -> Score {
+        self.eval_lazy(position, -INFINITY, INFINITY)
+    }
 
-fn mirror_weights_table(table: &[i8; 64]) -> [i8; 64] {
-    let mut result = [0i8; 64];
-    for rank in 0..8 {
-        for file in 0..8 {
-            let input_sq = Square::new(file, rank);
-            let output_sq = Square::new(file, 7 - rank);
-            result[output_sq.file_and_rank() as usize] = table[input_sq.file_and_rank() as usize];
+    //the estimate is material and piece-square tables, the endgame scaling applies to it too
+    fn eval_lazy(&self, position: &Position, alpha: Score, beta: Score) -> Score {
+        if let Some(score) = self.endgames.probe_eval(position) {
+            return score;
         }
+        let psq = self.eval_psq(position);
+        let phase = game_phase(position);
+        let estimate = self.scale(position, psq).taper(phase);
+        if estimate - LAZY_EVAL_MARGIN >= beta || estimate + LAZY_EVAL_MARGIN <= alpha {
+            return estimate;
+        }
+        let score = psq + self.eval_positional(position, &mut NoTrace);
+        self.scale(position, score).taper(phase)
+    }
+
+    fn classify(&self, position: &Position) -> GameStage {
+        game_stage(position)
     }
-    result
 }
 
+
 #[cfg(test)]
 mod tests {
 use fen::parse_fen;
@@ -264,20 +363,40 @@ use eval::*;
 
 #[test]
 fn simple_eval_test() {
+    ::tables::init_tables();
     let evaluator = SimpleEvaluator::new();
 
     //eval for whites
     let position = parse_fen("N7/1BR5/8/3Q4/4P3/8/8/8 w KQkq - 0 1").unwrap();
+    //middlegame:
     //score = (100 for a pawn) + (320 for a knight) + (330 for a bishop) + (500 for a rook) + (900 for a queen) = 2150
     //knight on a8 = -50
     //bishop on b7 = 0
     //rook on c7 = 10
     //queen on d5 = 5
     //pawn on e4 = 20
+    //pawn on e4 is isolated = -10 and passed on the 4th rank = 15 with a free path = 2
+    //mobility: knight 1 square (-3 * 4), bishop 3 (-3 * 5), rook 12 (5 * 2), queen 21 (8 * 1) = -9
+    //rook on the open c file = 25
+    //------------------
+    // 2158
+    //endgame:
+    //score = (120 for a pawn) + (300 for a knight) + (320 for a bishop) + (540 for a rook) + (950 for a queen) = 2230
+    //knight on a8 = -40
+    //bishop on b7 = -5
+    //rook on c7 = 10
+    //queen on d5 = 15
+    //pawn on e4 = 15
+    //pawn on e4 is isolated = -15 and passed on the 4th rank = 25 with a free path = 5
+    //mobility: knight (-3 * 4), bishop (-3 * 5), rook (5 * 4), queen (8 * 2) = 9
+    //rook on the open c file = 10
     //------------------
-    // 2135
+    // 2259
+    //phase = 1 (knight) + 1 (bishop) + 2 (rook) + 4 (queen) = 8
+    //result = (2158 * 8 + 2259 * (24 - 8)) / 24 = 2225
+    assert_eq!(game_phase(&position), 8);
     let score = evaluator.eval(&position);
-    assert_eq!(score, 2135);
+    assert_eq!(score, 2225);
 
     //initial position
     let position = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
@@ -289,23 +408,64 @@ fn simple_eval_test() {
     let stage = evaluator.classify(&position);
     assert_eq!(stage, Middlegame);
     let score = evaluator.eval(&position);
-    //middlegame position
+    //rooks and queens nullify each other, but white pieces are more mobile:
+    //white rook attacks 13 squares and black rook 12, white queen attacks 16 squares and black queen 10
+    //both kings are in the same danger, so king safety doesn't change the score
+    //middlegame:
     //white king on d5 = -50
     //black king in b8 = 30
-    //result = -50 - 30 = -80
-    //rooks and queens nullify each other
-    assert_eq!(score, -80);
+    //mobility = 1 * 2 (rooks) + 6 * 1 (queens) = 8
+    //-50 - 30 + 8 = -72
+    //endgame:
+    //white king on d5 = 40
+    //black king on b8 = -30
+    //mobility = 1 * 4 (rooks) + 6 * 2 (queens) = 16
+    //40 - (-30) + 16 = 86
+    //phase = 2 * 4 (queens) + 2 * 2 (rooks) = 12
+    //result = (-72 * 12 + 86 * 12) / 24 = 7
+    assert_eq!(game_phase(&position), 12);
+    assert_eq!(score, 7);
 
     //endgame position
     let position = parse_fen("k7/8/8/3K4/8/8/8/8 w - - 0 40").unwrap();
     let stage = evaluator.classify(&position);
     assert_eq!(stage, Endgame);
     let score = evaluator.eval(&position);
-    //endgame table is used
-    //white king in the center = 40
-    //black king in the corner = -50
-    //result = 40 - (-50) = 90
-    assert_eq!(score, 90);
+    //only kings are left, it is a draw whatever the king positions are
+    assert_eq!(score, 0);
+
+    //drawish ending: rook against bishop, the endgame score is scaled down
+    let position = parse_fen("k7/8/8/3K4/8/8/8/2b4R w - - 0 40").unwrap();
+    let without_scale = evaluator.eval_terms(&position, &mut NoTrace);
+    let phase = game_phase(&position);
+    assert_eq!(evaluator.eval(&position), TaperedScore::new(without_scale.mg, without_scale.eg / 4).taper(phase));
+}
+
+#[test]
+fn tapered_eval_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let startpos = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    assert_eq!(game_phase(&startpos), MAX_PHASE);
+
+    //Trading pieces one by one changes the phase gradually.
+    //With a symmetric position the score stays 0, king tables are mixed without jumps.
+    let fens = ["r1bqkb1r/pppppppp/8/8/8/8/PPPPPPPP/R1BQKB1R w KQkq - 0 1",
+                "r1b1kb1r/pppppppp/8/8/8/8/PPPPPPPP/R1B1KB1R w KQkq - 0 1",
+                "4kb1r/pppppppp/8/8/8/8/PPPPPPPP/4KB1R w Kk - 0 1",
+                "4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1"];
+    let phases: Vec<i32> = fens.iter().map(|fen| game_phase(&parse_fen(fen).unwrap())).collect();
+    assert_eq!(phases, vec![20, 12, 6, 0]);
+    for fen in fens.iter() {
+        assert_eq!(evaluator.eval(&parse_fen(fen).unwrap()), 0);
+    }
+
+    //White king on e3 is weak with queens and strong without them. When black queen is traded for a rook,
+    //score changes by the difference of material only, plus a small part of the king tables.
+    let with_queens = evaluator.eval(&parse_fen("3qk3/pppppppp/8/8/8/4K3/PPPPPPPP/3Q4 w - - 0 1").unwrap());
+    let queen_for_rook = evaluator.eval(&parse_fen("3rk3/pppppppp/8/8/8/4K3/PPPPPPPP/3Q4 w - - 0 1").unwrap());
+    let material = TaperedScore::new(900 - 500, 950 - 540).taper(6);
+    assert!((queen_for_rook - with_queens - material).abs() <= 25);
 }
 
 #[test]
@@ -331,4 +491,43 @@ fn classify_test() {
 
 }
 
+#[test]
+fn incremental_psq_test() {
+    ::tables::init_tables();
+    //castling, en passant, promotions and captures
+    for fen in ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1"].iter() {
+        let pos = parse_fen(fen).unwrap();
+        for mv in pos.gen_moves() {
+            let mut p1 = pos;
+            p1.apply_move(&mv);
+            assert_eq!(p1.psq, calc_psq(&p1.board), "{} {}", fen, mv);
+            for mv in p1.gen_moves() {
+                let mut p2 = p1;
+                p2.apply_move(&mv);
+                assert_eq!(p2.psq, calc_psq(&p2.board), "{} {}", fen, mv);
+            }
+        }
+    }
+}
+
+#[test]
+fn lazy_eval_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let position = parse_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+    let score = evaluator.eval(&position);
+    assert_eq!(evaluator.eval_lazy(&position, score - 1, score + 1), score);
+    assert_eq!(evaluator.eval_lazy(&position, -LAZY_EVAL_MARGIN, LAZY_EVAL_MARGIN), score);
+
+    //white is a queen up, the estimate is enough for a window around a draw
+    let position = parse_fen("r1b1kb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+    let score = evaluator.eval(&position);
+    let estimate = evaluator.eval_lazy(&position, -100, 100);
+    assert!(estimate >= 100 + LAZY_EVAL_MARGIN);
+    assert!((estimate - score).abs() < LAZY_EVAL_MARGIN);
+    assert_eq!(evaluator.eval_lazy(&position, score - 1, score + 1), score);
+}
+
 }
\ No newline at end of file
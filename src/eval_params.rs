@@ -0,0 +1,459 @@
+//Evaluation parameters
+//All weights of the evaluation are kept in one structure, so they can be saved to a text file,
+//changed by hand or by a tuner, and loaded back without recompiling.
+//
+//Text format: a parameter name followed by its values, separated by whitespace or new lines.
+//Everything after # on a line is a comment. Parameters missing in the file keep their default values.
+//Arrays indexed by Kind are in the order: pawn, bishop, knight, rook, queen, king.
+//Piece-square tables are written from the white point of view, rank 8 first, like a board diagram.
+use std::fs::File;
+use std::io::Read;
+use eval::{Score, TaperedScore};
+
+static KIND_NAMES: [&str; 6] = ["pawn", "bishop", "knight", "rook", "queen", "king"];
+
+//Parameters that scale other terms, the evaluation is not a weighted sum of them.
+//The evaluation trace has no coefficients for them, so the tuner changes them by local search.
+//king_danger_divisor is not tuned, it only sets the scale of the other king safety weights.
+pub static NONLINEAR_PARAMS: [&str; 10] = [
+    "passed_pawn_rank_factor", "mobility_base",
+    "king_zone_attack_weight", "king_attackers_scale", "king_safe_check_weight", "king_pawn_shield_weight",
+    "king_pawn_storm_weight", "king_semi_open_file_weight", "king_open_file_weight", "max_king_danger"
+];
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct EvalParams {
+    //material, indexed by Kind
+    pub piece_values: [TaperedScore; 6],
+    //piece-square tables indexed by Kind and by square in the diagram order (a8 = 0, h1 = 63)
+    pub pst: [[TaperedScore; 64]; 6],
+
+    //pawn structure, for every pawn
+    pub doubled_pawn: TaperedScore,
+    pub isolated_pawn: TaperedScore,
+    pub backward_pawn: TaperedScore,
+    //a pawn with a friendly pawn next to it on the same rank
+    pub connected_pawn: TaperedScore,
+    //a pawn defended by a friendly pawn
+    pub chained_pawn: TaperedScore,
+    //passed pawn bonuses indexed by the rank relative to the pawn's side
+    pub passed_pawn: [TaperedScore; 8],
+    //multipliers of the passed pawn terms that depend on other pieces
+    pub passed_pawn_rank_factor: [Score; 8],
+    //no piece stands in front of the passed pawn
+    pub free_passed_pawn: TaperedScore,
+    //endgame bonus per square of distance of the kings from the square in front of the passed pawn
+    pub passed_pawn_enemy_king_distance: Score,
+    pub passed_pawn_own_king_distance: Score,
+
+    //bonus for every square a piece can move to and the typical mobility that gets no bonus, indexed by Kind
+    pub mobility: [TaperedScore; 6],
+    pub mobility_base: [Score; 6],
+    pub bishop_pair: TaperedScore,
+    pub rook_open_file: TaperedScore,
+    pub rook_semi_open_file: TaperedScore,
+    pub rook_on_seventh: TaperedScore,
+    pub knight_outpost: TaperedScore,
+
+    //king safety in danger units, see king_safety.rs
+    pub king_zone_attack_weight: [Score; 6],
+    pub king_attackers_scale: [Score; 8],
+    pub king_safe_check_weight: [Score; 6],
+    pub king_pawn_shield_weight: [Score; 4],
+    pub king_pawn_storm_weight: [Score; 5],
+    pub king_semi_open_file_weight: Score,
+    pub king_open_file_weight: Score,
+    pub king_danger_divisor: Score,
+    pub max_king_danger: Score
+}
+
+fn tapered(mg: [Score; 6], eg: [Score; 6]) -> [TaperedScore; 6] {
+    let mut result = [TaperedScore::default(); 6];
+    for i in 0..6 {
+        result[i] = TaperedScore::new(mg[i], eg[i]);
+    }
+    result
+}
+
+fn tapered_table(mg: &[Score; 64], eg: &[Score; 64]) -> [TaperedScore; 64] {
+    let mut result = [TaperedScore::default(); 64];
+    for i in 0..64 {
+        result[i] = TaperedScore::new(mg[i], eg[i]);
+    }
+    result
+}
+
+impl Default for EvalParams {
+    //values are based mostly on https://www.chessprogramming.org/Simplified_Evaluation_Function
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    fn default() -> EvalParams {
+
+        let pawn_mg: [Score; 64] =
+           [0,  0,  0,  0,  0,  0,  0,  0,
+            50, 50, 50, 50, 50, 50, 50, 50,
+            10, 10, 20, 30, 30, 20, 10, 10,
+             5,  5, 10, 25, 25, 10,  5,  5,
+             0,  0,  0, 20, 20,  0,  0,  0,
+             5, -5,-10,  0,  0,-10, -5,  5,
+             5, 10, 10,-20,-20, 10, 10,  5,
+             0,  0,  0,  0,  0,  0,  0,  0];
+
+        //passed pawns are more dangerous in the endgame, center is not important anymore
+        let pawn_eg: [Score; 64] =
+           [0,  0,  0,  0,  0,  0,  0,  0,
+            80, 80, 80, 80, 80, 80, 80, 80,
+            50, 50, 50, 50, 50, 50, 50, 50,
+            30, 30, 30, 30, 30, 30, 30, 30,
+            15, 15, 15, 15, 15, 15, 15, 15,
+             5,  5,  5,  5,  5,  5,  5,  5,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0];
+
+        let knight_mg: [Score; 64] =
+           [-50,-40,-30,-30,-30,-30,-40,-50,
+            -40,-20,  0,  0,  0,  0,-20,-40,
+            -30,  0, 10, 15, 15, 10,  0,-30,
+            -30,  5, 15, 20, 20, 15,  5,-30,
+            -30,  0, 15, 20, 20, 15,  0,-30,
+            -30,  5, 10, 15, 15, 10,  5,-30,
+            -40,-20,  0,  5,  5,  0,-20,-40,
+            -50,-40,-30,-30,-30,-30,-40,-50];
+
+        let knight_eg: [Score; 64] =
+           [-40,-30,-20,-20,-20,-20,-30,-40,
+            -30,-15, -5,  0,  0, -5,-15,-30,
+            -20, -5, 10, 15, 15, 10, -5,-20,
+            -20,  0, 15, 20, 20, 15,  0,-20,
+            -20,  0, 15, 20, 20, 15,  0,-20,
+            -20, -5, 10, 15, 15, 10, -5,-20,
+            -30,-15, -5,  0,  0, -5,-15,-30,
+            -40,-30,-20,-20,-20,-20,-30,-40];
+
+        let bishop_mg: [Score; 64] =
+           [-20,-10,-10,-10,-10,-10,-10,-20,
+            -10,  0,  0,  0,  0,  0,  0,-10,
+            -10,  0,  5, 10, 10,  5,  0,-10,
+            -10,  5,  5, 10, 10,  5,  5,-10,
+            -10,  0, 10, 10, 10, 10,  0,-10,
+            -10, 10, 10, 10, 10, 10, 10,-10,
+            -10,  5,  0,  0,  0,  0,  5,-10,
+            -20,-10,-10,-10,-10,-10,-10,-20];
+
+        let bishop_eg: [Score; 64] =
+           [-15,-10,-10,-10,-10,-10,-10,-15,
+            -10, -5,  0,  0,  0,  0, -5,-10,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+            -10,  0,  5, 10, 10,  5,  0,-10,
+            -10,  0,  5, 10, 10,  5,  0,-10,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+            -10, -5,  0,  0,  0,  0, -5,-10,
+            -15,-10,-10,-10,-10,-10,-10,-15];
+
+        let rook_mg: [Score; 64] =
+           [0,  0,  0,  0,  0,  0,  0,  0,
+             5, 10, 10, 10, 10, 10, 10,  5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+             0,  0,  0,  5,  5,  0,  0,  0];
+
+        let rook_eg: [Score; 64] =
+           [5,  5,  5,  5,  5,  5,  5,  5,
+            10, 10, 10, 10, 10, 10, 10, 10,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0,
+             0,  0,  0,  0,  0,  0,  0,  0];
+
+        let queen_mg: [Score; 64] =
+           [-20,-10,-10, -5, -5,-10,-10,-20,
+            -10,  0,  0,  0,  0,  0,  0,-10,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+             -5,  0,  5,  5,  5,  5,  0, -5,
+              0,  0,  5,  5,  5,  5,  0, -5,
+            -10,  5,  5,  5,  5,  5,  0,-10,
+            -10,  0,  5,  0,  0,  0,  0,-10,
+            -20,-10,-10, -5, -5,-10,-10,-20];
+
+        let queen_eg: [Score; 64] =
+           [-20,-10,-10, -5, -5,-10,-10,-20,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+            -10,  5, 10, 10, 10, 10,  5,-10,
+             -5,  5, 10, 15, 15, 10,  5, -5,
+             -5,  5, 10, 15, 15, 10,  5, -5,
+            -10,  5, 10, 10, 10, 10,  5,-10,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+            -20,-10,-10, -5, -5,-10,-10,-20];
+
+        let king_mg: [Score; 64] =
+           [-30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -20,-30,-30,-40,-40,-30,-30,-20,
+            -10,-20,-20,-20,-20,-20,-20,-10,
+             20, 20,  0,  0,  0,  0, 20, 20,
+             20, 30, 10,  0,  0, 10, 30, 20];
+
+        let king_eg: [Score; 64] =
+           [-50,-40,-30,-20,-20,-30,-40,-50,
+            -30,-20,-10,  0,  0,-10,-20,-30,
+            -30,-10, 20, 30, 30, 20,-10,-30,
+            -30,-10, 30, 40, 40, 30,-10,-30,
+            -30,-10, 30, 40, 40, 30,-10,-30,
+            -30,-10, 20, 30, 30, 20,-10,-30,
+            -30,-30,  0,  0,  0,  0,-30,-30,
+            -50,-30,-30,-30,-30,-30,-30,-50];
+
+        EvalParams {
+            piece_values: tapered([100, 330, 320, 500, 900, 20000], [120, 320, 300, 540, 950, 20000]),
+            pst: [tapered_table(&pawn_mg, &pawn_eg), tapered_table(&bishop_mg, &bishop_eg),
+                  tapered_table(&knight_mg, &knight_eg), tapered_table(&rook_mg, &rook_eg),
+                  tapered_table(&queen_mg, &queen_eg), tapered_table(&king_mg, &king_eg)],
+
+            doubled_pawn: TaperedScore::new(-10, -20),
+            isolated_pawn: TaperedScore::new(-10, -15),
+            backward_pawn: TaperedScore::new(-8, -12),
+            connected_pawn: TaperedScore::new(6, 4),
+            chained_pawn: TaperedScore::new(10, 8),
+            passed_pawn: [TaperedScore::new(0, 0), TaperedScore::new(5, 10), TaperedScore::new(10, 15),
+                          TaperedScore::new(15, 25), TaperedScore::new(25, 45), TaperedScore::new(40, 70),
+                          TaperedScore::new(65, 110), TaperedScore::new(0, 0)],
+            passed_pawn_rank_factor: [0, 0, 0, 1, 3, 5, 8, 0],
+            free_passed_pawn: TaperedScore::new(2, 5),
+            passed_pawn_enemy_king_distance: 5,
+            passed_pawn_own_king_distance: 2,
+
+            mobility: tapered([0, 5, 4, 2, 1, 0], [0, 5, 4, 4, 2, 0]),
+            mobility_base: [0, 6, 4, 7, 13, 0],
+            bishop_pair: TaperedScore::new(30, 50),
+            rook_open_file: TaperedScore::new(25, 10),
+            rook_semi_open_file: TaperedScore::new(12, 6),
+            rook_on_seventh: TaperedScore::new(20, 30),
+            knight_outpost: TaperedScore::new(20, 10),
+
+            king_zone_attack_weight: [0, 4, 4, 6, 10, 0],
+            king_attackers_scale: [0, 0, 50, 75, 88, 94, 97, 99],
+            king_safe_check_weight: [0, 6, 8, 9, 10, 0],
+            king_pawn_shield_weight: [5, 0, 2, 4],
+            king_pawn_storm_weight: [0, 2, 4, 3, 1],
+            king_semi_open_file_weight: 3,
+            king_open_file_weight: 2,
+            king_danger_divisor: 16,
+            max_king_danger: 800
+        }
+    }
+}
+
+type Entry<'a> = (String, Vec<&'a mut Score>);
+
+fn add_tapered<'a>(entries: &mut Vec<Entry<'a>>, name: &str, value: &'a mut TaperedScore) {
+    entries.push((name.to_string(), vec![&mut value.mg, &mut value.eg]));
+}
+
+//arrays of tapered scores are split into separate arrays of middlegame and endgame values
+fn add_tapered_array<'a>(entries: &mut Vec<Entry<'a>>, name: &str, values: &'a mut [TaperedScore]) {
+    let (mg, eg): (Vec<&'a mut Score>, Vec<&'a mut Score>) = values.iter_mut().map(|s| (&mut s.mg, &mut s.eg)).unzip();
+    entries.push((format!("{}.mg", name), mg));
+    entries.push((format!("{}.eg", name), eg));
+}
+
+fn add_array<'a>(entries: &mut Vec<Entry<'a>>, name: &str, values: &'a mut [Score]) {
+    entries.push((name.to_string(), values.iter_mut().collect()));
+}
+
+fn add_score<'a>(entries: &mut Vec<Entry<'a>>, name: &str, value: &'a mut Score) {
+    entries.push((name.to_string(), vec![value]));
+}
+
+impl EvalParams {
+    //all parameters as names with references to their values, in the order of the text file
+    fn entries<'a>(&'a mut self) -> Vec<Entry<'a>> {
+        let mut entries = Vec::new();
+        add_tapered_array(&mut entries, "piece_values", &mut self.piece_values);
+        for (name, table) in KIND_NAMES.iter().zip(self.pst.iter_mut()) {
+            add_tapered_array(&mut entries, &format!("pst.{}", name), table);
+        }
+
+        add_tapered(&mut entries, "doubled_pawn", &mut self.doubled_pawn);
+        add_tapered(&mut entries, "isolated_pawn", &mut self.isolated_pawn);
+        add_tapered(&mut entries, "backward_pawn", &mut self.backward_pawn);
+        add_tapered(&mut entries, "connected_pawn", &mut self.connected_pawn);
+        add_tapered(&mut entries, "chained_pawn", &mut self.chained_pawn);
+        add_tapered_array(&mut entries, "passed_pawn", &mut self.passed_pawn);
+        add_array(&mut entries, "passed_pawn_rank_factor", &mut self.passed_pawn_rank_factor);
+        add_tapered(&mut entries, "free_passed_pawn", &mut self.free_passed_pawn);
+        add_score(&mut entries, "passed_pawn_enemy_king_distance", &mut self.passed_pawn_enemy_king_distance);
+        add_score(&mut entries, "passed_pawn_own_king_distance", &mut self.passed_pawn_own_king_distance);
+
+        add_tapered_array(&mut entries, "mobility", &mut self.mobility);
+        add_array(&mut entries, "mobility_base", &mut self.mobility_base);
+        add_tapered(&mut entries, "bishop_pair", &mut self.bishop_pair);
+        add_tapered(&mut entries, "rook_open_file", &mut self.rook_open_file);
+        add_tapered(&mut entries, "rook_semi_open_file", &mut self.rook_semi_open_file);
+        add_tapered(&mut entries, "rook_on_seventh", &mut self.rook_on_seventh);
+        add_tapered(&mut entries, "knight_outpost", &mut self.knight_outpost);
+
+        add_array(&mut entries, "king_zone_attack_weight", &mut self.king_zone_attack_weight);
+        add_array(&mut entries, "king_attackers_scale", &mut self.king_attackers_scale);
+        add_array(&mut entries, "king_safe_check_weight", &mut self.king_safe_check_weight);
+        add_array(&mut entries, "king_pawn_shield_weight", &mut self.king_pawn_shield_weight);
+        add_array(&mut entries, "king_pawn_storm_weight", &mut self.king_pawn_storm_weight);
+        add_score(&mut entries, "king_semi_open_file_weight", &mut self.king_semi_open_file_weight);
+        add_score(&mut entries, "king_open_file_weight", &mut self.king_open_file_weight);
+        add_score(&mut entries, "king_danger_divisor", &mut self.king_danger_divisor);
+        add_score(&mut entries, "max_king_danger", &mut self.max_king_danger);
+        entries
+    }
+
+    //all values as one vector in the order of the text file, used by the tuner
+    pub fn values(&self) -> Vec<Score> {
+        let mut params = self.clone();
+        let values = params.entries().into_iter().flat_map(|(_, values)| values.into_iter().map(|v| *v)).collect();
+        values
+    }
+
+    pub fn set_values(&mut self, values: &[Score]) {
+        let targets = self.entries().into_iter().flat_map(|(_, values)| values);
+        for (target, &value) in targets.zip(values) {
+            *target = value;
+        }
+    }
+
+    //parameter name of every value in values()
+    pub fn value_names(&self) -> Vec<String> {
+        let mut params = self.clone();
+        let names = params.entries().into_iter()
+            .flat_map(|(name, values)| vec![name; values.len()])
+            .collect();
+        names
+    }
+
+    //all values 0, the starting point of an evaluation trace
+    pub fn zero() -> EvalParams {
+        let mut params = EvalParams::default();
+        let count = params.values().len();
+        params.set_values(&vec![0; count]);
+        params
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut params = self.clone();
+        let mut text = String::new();
+        for (name, values) in params.entries() {
+            let values: Vec<String> = values.iter().map(|v| format!(" {:4}", v)).collect();
+            if values.len() == 64 {
+                //piece-square table, one rank per line
+                text += &format!("{}\n", name);
+                for rank in values.chunks(8) {
+                    text += &format!("  {}\n", rank.concat());
+                }
+            } else {
+                text += &format!("{}{}\n", name, values.concat());
+            }
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<EvalParams, String> {
+        let mut params = EvalParams::default();
+        {
+            let mut entries = params.entries();
+            let mut tokens = text.lines()
+                .flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace())
+                .peekable();
+            while let Some(name) = tokens.next() {
+                let entry = match entries.iter_mut().find(|e| e.0 == name) {
+                    Some(entry) => entry,
+                    None => return Err(format!("Unknown parameter {}", name))
+                };
+                let mut values = Vec::new();
+                while let Some(value) = tokens.peek().and_then(|t| t.parse::<Score>().ok()) {
+                    values.push(value);
+                    tokens.next();
+                }
+                if values.len() != entry.1.len() {
+                    return Err(format!("Parameter {} needs {} values, found {}", name, entry.1.len(), values.len()));
+                }
+                for (target, value) in entry.1.iter_mut().zip(values) {
+                    **target = value;
+                }
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn load(path: &str) -> Result<EvalParams, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Can't read {}: {}", path, e))?;
+        EvalParams::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use eval_params::*;
+use types::*;
+
+#[test]
+fn text_round_trip_test() {
+    let params = EvalParams::default();
+    let text = params.to_text();
+    assert!(text.starts_with("piece_values.mg  100 "));
+    assert_eq!(EvalParams::parse(&text), Ok(params));
+}
+
+#[test]
+fn parse_test() {
+    let text = "# tuned values\n\
+                bishop_pair 40 60   # stronger pair\n\
+                mobility.mg 0 6 5 3 1 0\n";
+    let params = EvalParams::parse(text).unwrap();
+    assert_eq!(params.bishop_pair, TaperedScore::new(40, 60));
+    assert_eq!(params.mobility[Bishop as usize], TaperedScore::new(6, 5));
+    //missing parameters keep default values
+    assert_eq!(params.rook_open_file, EvalParams::default().rook_open_file);
+
+    assert_eq!(EvalParams::parse(""), Ok(EvalParams::default()));
+    assert_eq!(EvalParams::parse("bishop_pair 40"), Err("Parameter bishop_pair needs 2 values, found 1".to_string()));
+    assert_eq!(EvalParams::parse("bishop_pair 40 60 70"), Err("Parameter bishop_pair needs 2 values, found 3".to_string()));
+    assert_eq!(EvalParams::parse("queen_pair 40 60"), Err("Unknown parameter queen_pair".to_string()));
+    assert!(EvalParams::load("no/such/file").is_err());
+}
+
+#[test]
+fn values_test() {
+    let mut params = EvalParams::default();
+    let mut values = params.values();
+    let names = params.value_names();
+    assert_eq!(values.len(), names.len());
+    assert_eq!(&values[..2], &[100, 330]);
+    assert_eq!(names[0], "piece_values.mg");
+    let index = names.iter().position(|n| n == "bishop_pair").unwrap();
+    values[index + 1] = 70;
+    params.set_values(&values);
+    assert_eq!(params.bishop_pair, TaperedScore::new(30, 70));
+    assert_eq!(params.values(), values);
+    assert!(NONLINEAR_PARAMS.iter().all(|p| names.iter().any(|n| n == p)));
+    assert!(EvalParams::zero().values().iter().all(|&v| v == 0));
+}
+
+#[test]
+fn load_test() {
+    let path = ::std::env::temp_dir().join("rchess_eval_params_test.txt");
+    let mut params = EvalParams::default();
+    params.pst[Knight as usize][0] = TaperedScore::new(-99, -98);
+    ::std::fs::write(&path, params.to_text()).unwrap();
+    assert_eq!(EvalParams::load(path.to_str().unwrap()), Ok(params));
+    ::std::fs::write(&path, "rook_on_seventh 20 30\nx 1").unwrap();
+    assert!(EvalParams::load(path.to_str().unwrap()).unwrap_err().ends_with("Unknown parameter x"));
+    ::std::fs::remove_file(&path).ok();
+}
+
+}
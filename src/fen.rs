@@ -24,8 +24,8 @@ pub fn render_fen(p:&Position) -> String {
     if p.white_castling == NoCastling && p.black_castling == NoCastling {
         result.push('-');
     } else {
-        render_castling(White, p.white_castling, &mut result);
-        render_castling(Black, p.black_castling, &mut result);
+        render_castling(p, White, &mut result);
+        render_castling(p, Black, &mut result);
     }
 
     //en passant square
@@ -46,17 +46,56 @@ pub fn render_fen(p:&Position) -> String {
     result
 }
 
-fn render_castling(color:Color, cr:CastlingRight, result: &mut String) {
-    let s = match (color, cr) {
-        (White, QueenCastling) => "Q",
-        (White, KingCastling)  => "K",
-        (White, BothCastling)  => "KQ",
-        (Black, QueenCastling) => "q",
-        (Black, KingCastling)  => "k",
-        (Black, BothCastling)  => "kq",
-        (_, NoCastling) => ""
+//Castling rights are rendered in X-FEN, which is the usual notation for standard chess:
+//K and Q stand for the outermost rook on the king and the queen side, the file of the rook
+//is given instead when there is another rook further out, like in HAha of Shredder-FEN
+fn render_castling(p:&Position, color:Color, result: &mut String) {
+    let (castling, files) = match color {
+        White => (p.white_castling, p.white_castling_files),
+        Black => (p.black_castling, p.black_castling_files)
     };
-    result.push_str(s)
+    let mut s = String::new();
+    if castling == KingCastling || castling == BothCastling {
+        if outer_rook_file(&p.board, color, true) == files.king_side {
+            s.push('K');
+        } else {
+            s.push((b'A' + files.king_side) as char);
+        }
+    }
+    if castling == QueenCastling || castling == BothCastling {
+        if outer_rook_file(&p.board, color, false) == files.queen_side {
+            s.push('Q');
+        } else {
+            s.push((b'A' + files.queen_side) as char);
+        }
+    }
+    if color == Black {
+        s = s.to_lowercase();
+    }
+    result.push_str(&s)
+}
+
+//the file of the rook that K or Q refers to, if there is no such rook the standard file is returned
+fn outer_rook_file(board:&Board, color:Color, king_side:bool) -> u8 {
+    let rank = if color == White { 0 } else { 7 };
+    let default = if king_side { STANDARD_CASTLING_FILES.king_side } else { STANDARD_CASTLING_FILES.queen_side };
+    let king_file = match king_file(board, color) {
+        Some(file) => file,
+        None => return default
+    };
+    let rook_files = board.get_pieces(Rook, color).filter(|sq| sq.rank() == rank).map(|sq| sq.file());
+    let outer_file = if king_side {
+        rook_files.filter(|&file| file > king_file).max()
+    } else {
+        rook_files.filter(|&file| file < king_file).min()
+    };
+    outer_file.unwrap_or(default)
+}
+
+//the file of the king if it is on its back rank
+fn king_file(board:&Board, color:Color) -> Option<u8> {
+    let rank = if color == White { 0 } else { 7 };
+    board.get_pieces(King, color).find(|sq| sq.rank() == rank).map(|sq| sq.file())
 }
 
 fn render_rank(b:&Board, rank:u8, result: &mut String) {
@@ -94,7 +133,8 @@ pub fn parse_fen(input:&str) -> Result<Position, String> {
     expect_char(&mut iter, ' ', "Space is expected after next to move color".to_string())?;
 
     //read castlings
-    let (white_castling, black_castling) = parse_castlings(&mut iter)?;
+    let [(white_castling, white_castling_files), (black_castling, black_castling_files)]
+        = parse_castlings(&mut iter, &board)?;
 
     //read en passant
     let en_passant = parse_en_passant(&mut iter)?;
@@ -113,7 +153,10 @@ pub fn parse_fen(input:&str) -> Result<Position, String> {
         full_moves : full_moves,
         next_to_move : next_to_move,
         white_castling : white_castling,
-        black_castling : black_castling
+        black_castling : black_castling,
+        white_castling_files : white_castling_files,
+        black_castling_files : black_castling_files,
+        psq : ::eval::calc_psq(&board)
     })
 }
 
@@ -147,20 +190,38 @@ fn parse_en_passant(iter: &mut Chars) -> Result< Option<Square> , String> {
     Ok(Some(Square::new(file as u8, rank)))
 }
 
-fn parse_castlings(iter: &mut Chars) -> Result<(CastlingRight, CastlingRight), String> {
-    let (mut white_king, mut white_queen, mut black_king, mut black_queen)
-        = (false, false, false, false);
+//Accepts X-FEN and Shredder-FEN: besides KQkq the file of the castling rook can be given,
+//for example HAha for the standard position. The letter is uppercase for white.
+//Rights and rook files are returned for white and black.
+fn parse_castlings(iter: &mut Chars, board: &Board) -> Result<[(CastlingRight, CastlingFiles); 2], String> {
+    //king side and queen side rights for white and black
+    let mut rights = [(false, false), (false, false)];
+    let mut files = [STANDARD_CASTLING_FILES, STANDARD_CASTLING_FILES];
     let mut n = 0;
     loop {
         match iter.next() {
-            Some('k') => black_king = true,
-            Some('K') => white_king = true,
-            Some('q') => black_queen = true,
-            Some('Q') => white_queen = true,
-            Some('-') if !(white_king && white_queen && black_queen && black_king) =>
+            Some(c@'K') | Some(c@'Q') | Some(c@'k') | Some(c@'q') | Some(c@'A'..='H') | Some(c@'a'..='h') => {
+                let color = if c.is_uppercase() { White } else { Black };
+                let (king_side, file) = match c.to_ascii_uppercase() {
+                    'K' => (true, outer_rook_file(board, color, true)),
+                    'Q' => (false, outer_rook_file(board, color, false)),
+                    f => {
+                        let file = f as u8 - b'A';
+                        (file > king_file(board, color).unwrap_or(4), file)
+                    }
+                };
+                if king_side {
+                    rights[color as usize].0 = true;
+                    files[color as usize].king_side = file;
+                } else {
+                    rights[color as usize].1 = true;
+                    files[color as usize].queen_side = file;
+                }
+            }
+            Some('-') if n == 0 =>
                 {
                     expect_char(iter, ' ', "Space is expected after next to castling".to_string())?;
-                    return Ok ((NoCastling, NoCastling))
+                    return Ok ([(NoCastling, files[0]), (NoCastling, files[1])])
                 }
             Some(' ') => break,
             c => { return Err(format!("Unexpected castling configuration {0:?}", c)) }
@@ -170,7 +231,7 @@ fn parse_castlings(iter: &mut Chars) -> Result<(CastlingRight, CastlingRight), S
             return Err("Castling configuration is too long".to_string());
         }
     }
-    let bools_to_castling = |king:bool, queen:bool| {
+    let bools_to_castling = |(king, queen):(bool, bool)| {
         match (king, queen) {
             (true, false)  => KingCastling,
             (false, true)  => QueenCastling,
@@ -179,9 +240,7 @@ fn parse_castlings(iter: &mut Chars) -> Result<(CastlingRight, CastlingRight), S
         }
     };
 
-    let white_castling = bools_to_castling(white_king, white_queen);
-    let black_castling = bools_to_castling(black_king, black_queen);
-    Ok ((white_castling, black_castling))
+    Ok ([(bools_to_castling(rights[0]), files[0]), (bools_to_castling(rights[1]), files[1])])
 }
 
 fn parse_board(iter: &mut Chars) -> Result<Board, String> {
@@ -269,7 +328,9 @@ fn parse_render_fens() {
         "r2q1rk1/1p1nbppp/p2pbn2/4p3/4P3/1NN1BP2/PPPQ2PP/2KR1B1R w - - 5 11",
         "8/8/8/8/8/8/8/8 w - - 200 999",
         "r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1",
-        "pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp b Qk a3 23 21"
+        "pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp/pppppppp b Qk a3 23 21",
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9",
+        "rr2k2r/8/8/8/8/8/8/RR2K2R w KBkb - 0 1"
     ];
 
     for &fen in test_fens.iter() {
@@ -282,4 +343,26 @@ fn parse_render_fens() {
     }
 }
 
+#[test]
+fn chess960_castling_test() {
+    use types::*;
+    //Shredder-FEN gives the files of the rooks
+    let shredder = parse_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9").unwrap();
+    assert_eq!(shredder.white_castling_files, CastlingFiles { king_side: 7, queen_side: 5 });
+    assert_eq!(shredder.black_castling_files, CastlingFiles { king_side: 7, queen_side: 5 });
+    assert_eq!(render_fen(&shredder), "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9");
+
+    let standard = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1").unwrap();
+    assert_eq!(standard, parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap());
+    assert_eq!(standard.white_castling_files, STANDARD_CASTLING_FILES);
+
+    //in X-FEN Q is the outermost rook, the inner one is given by its file
+    let xfen = parse_fen("rr2k2r/8/8/8/8/8/8/RR2K2R w KQkb - 0 1").unwrap();
+    assert_eq!(xfen.white_castling_files, CastlingFiles { king_side: 7, queen_side: 0 });
+    assert_eq!(xfen.black_castling_files, CastlingFiles { king_side: 7, queen_side: 1 });
+    assert_eq!(xfen.black_castling, BothCastling);
+
+    assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 w KX - 0 1").is_err());
+}
+
 }
@@ -0,0 +1,480 @@
+//Training data generation: the engine plays games against itself and records positions
+//with the search score, the best move and the result of the game.
+//Games start with random moves, so they are all different. Positions in check and positions
+//where the best move is a capture or a promotion are not recorded, they are not quiet.
+//
+//Text format, one position per line, it can be used by `rchess tune`:
+//  <fen> | <score> | <best move> | <result>
+//  score is in centipawns from the point of view of the side to move, the move is in UCI notation,
+//  result is 1-0, 0-1 or 1/2-1/2
+//
+//Binary format, 35 bytes per position, numbers are little endian:
+//  occupied squares  u64, bit 0 is a1, bit 63 is h8
+//  pieces            16 bytes, 4 bits for every occupied square from a1 to h8, the low bits first:
+//                    color * 6 + kind, white is 0, kinds are in the order pawn, bishop, knight, rook, queen, king
+//  side to move      u8, 0 white, 1 black
+//  castling rights   u8, bits: 0 white king side, 1 white queen side, 2 black king side, 3 black queen side,
+//                    the castling rooks are on the a and h files
+//  en passant square u8, a1 = 0 .. h8 = 63, 255 if there is none
+//  half move clock   u8
+//  full move number  u16
+//  score             i16, as in the text format
+//  best move         u16, from square | to square << 6 | promotion kind << 12 (0 if none),
+//                    castling is the move of the king
+//  result            i8, 1 white won, 0 draw, -1 black won
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use rand::isaac::Isaac64Rng;
+use rand::{RngCore, SeedableRng};
+use types::*;
+use eval::Score;
+use eval_params::EvalParams;
+use fen::{parse_fen, render_fen};
+use hash::calc_position_hash;
+use nnue::Network;
+use search::{SearchEngine, SearchLimits};
+use uci::move_to_uci;
+
+pub const RECORD_SIZE: usize = 35;
+//a game is over when one side is this much ahead, the rest of it has little to learn from
+static ADJUDICATION_SCORE: Score = 2000;
+static MAX_GAME_PLIES: usize = 400;
+static KINDS: [Kind; 6] = [Pawn, Bishop, Knight, Rook, Queen, King];
+static START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+static DEFAULT_GAMES: usize = 100;
+static DEFAULT_DEPTH: usize = 6;
+static DEFAULT_RANDOM_PLIES: usize = 8;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Sample {
+    pub position: Position,
+    //from the point of view of the side to move
+    pub score: Score,
+    pub best_move: Move,
+    //1 white won, 0 draw, -1 black won
+    pub result: i8
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Format {
+    Text, Binary
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GensfenOptions {
+    pub games: usize,
+    pub limits: SearchLimits,
+    pub threads: usize,
+    //random moves at the start of every game
+    pub random_plies: usize,
+    pub seed: u64
+}
+
+impl GensfenOptions {
+    pub fn new() -> GensfenOptions {
+        GensfenOptions {
+            games: DEFAULT_GAMES,
+            limits: SearchLimits::depth(DEFAULT_DEPTH),
+            threads: 1,
+            random_plies: DEFAULT_RANDOM_PLIES,
+            seed: 1
+        }
+    }
+}
+
+fn random_generator(seed: u64) -> Isaac64Rng {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    SeedableRng::from_seed(bytes)
+}
+
+//Result from the white point of view when the game is over: mate, stalemate, the fifty move rule,
+//threefold repetition or not enough material to mate.
+//History has the hashes of all positions of the game, the current one last.
+fn game_result(pos: &Position, history: &[u64]) -> Option<i8> {
+    if pos.gen_moves().next().is_none() {
+        return match (pos.is_check(), pos.next_to_move) {
+            (true, White) => Some(-1),
+            (true, Black) => Some(1),
+            (false, _) => Some(0)
+        };
+    }
+    if pos.half_moves_since_action >= 100 {
+        return Some(0);
+    }
+    let hash = history[history.len() - 1];
+    if history.iter().filter(|&&h| h == hash).count() >= 3 {
+        return Some(0);
+    }
+    let board = &pos.board;
+    if (board.pawns | board.rooks | board.queens).is_empty() && (board.bishops | board.knights).count() <= 1 {
+        return Some(0);
+    }
+    None
+}
+
+//Plays one game from a random opening and returns its quiet positions.
+//Nothing is returned if the game ends during the random moves.
+pub fn play_game(engine: &mut SearchEngine, limits: &SearchLimits, random_plies: usize,
+                 rng: &mut Isaac64Rng) -> Vec<Sample> {
+    let mut pos = parse_fen(START_FEN).unwrap();
+    for _ in 0..random_plies {
+        let moves: Vec<Move> = pos.gen_moves().collect();
+        if moves.is_empty() {
+            return Vec::new();
+        }
+        pos.apply_move(&moves[(rng.next_u64() % moves.len() as u64) as usize]);
+    }
+
+    let mut samples = Vec::new();
+    let mut history = vec![calc_position_hash(&pos)];
+    let result = loop {
+        if let Some(result) = game_result(&pos, &history) {
+            break result;
+        }
+        if history.len() > MAX_GAME_PLIES {
+            break 0;
+        }
+        let search = engine.search_with_limits(&pos, limits, &mut |_| {});
+        let best_move = match search.best_move {
+            Some(mv) => mv,
+            None => break 0
+        };
+        if search.score.abs() >= ADJUDICATION_SCORE {
+            let white_score = if pos.next_to_move == White { search.score } else { -search.score };
+            break white_score.signum() as i8;
+        }
+        if !pos.is_check() && pos.is_quiet(&best_move) {
+            samples.push(Sample { position: pos, score: search.score, best_move: best_move, result: 0 });
+        }
+        pos.apply_move(&best_move);
+        history.push(calc_position_hash(&pos));
+    };
+    for sample in samples.iter_mut() {
+        sample.result = result;
+    }
+    samples
+}
+
+//Plays games on several threads, output is called with the positions of every finished game.
+//Game number i uses the random generator seeded with seed + i and the search is deterministic,
+//so the same games are played with any number of threads, only their order may differ.
+pub fn generate(options: &GensfenOptions, params: &EvalParams, network: Option<&Network>,
+                output: &mut dyn FnMut(&[Sample])) {
+    let params = Arc::new(params.clone());
+    let network = network.map(|n| Arc::new(n.clone()));
+    let next_game = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = channel();
+    let mut threads = Vec::new();
+    for _ in 0..options.threads {
+        let mut engine = SearchEngine::new();
+        engine.options.deterministic = true;
+        engine.set_eval_params(params.clone());
+        engine.set_network(network.clone());
+        let options = *options;
+        let next_game = next_game.clone();
+        let sender = sender.clone();
+        threads.push(thread::spawn(move || {
+            loop {
+                let game = next_game.fetch_add(1, Ordering::Relaxed);
+                if game >= options.games {
+                    break;
+                }
+                let mut rng = random_generator(options.seed.wrapping_add(game as u64));
+                let samples = play_game(&mut engine, &options.limits, options.random_plies, &mut rng);
+                if sender.send(samples).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(sender);
+    for samples in receiver {
+        output(&samples);
+    }
+    for t in threads {
+        t.join().expect("gensfen thread panicked");
+    }
+}
+
+fn result_to_string(result: i8) -> &'static str {
+    match result {
+        1 => "1-0",
+        -1 => "0-1",
+        _ => "1/2-1/2"
+    }
+}
+
+pub fn to_text(sample: &Sample) -> String {
+    format!("{} | {} | {} | {}", render_fen(&sample.position), sample.score,
+            move_to_uci(&sample.best_move, &sample.position, false), result_to_string(sample.result))
+}
+
+fn castling_bits(right: CastlingRight) -> u8 {
+    match right {
+        NoCastling => 0,
+        KingCastling => 1,
+        QueenCastling => 2,
+        BothCastling => 3
+    }
+}
+
+fn castling_right(bits: u8) -> CastlingRight {
+    match bits & 3 {
+        0 => NoCastling,
+        1 => KingCastling,
+        2 => QueenCastling,
+        _ => BothCastling
+    }
+}
+
+fn encode_move(mv: &Move, position: &Position) -> u16 {
+    let uci_move = move_to_uci(mv, position, false);
+    let promotion = uci_move.promotion.map_or(0, |kind| kind as u16);
+    uci_move.from.0 as u16 | (uci_move.to.0 as u16) << 6 | promotion << 12
+}
+
+pub fn encode(sample: &Sample) -> [u8; RECORD_SIZE] {
+    let pos = &sample.position;
+    let board = &pos.board;
+    let mut record = [0; RECORD_SIZE];
+    record[0..8].copy_from_slice(&board.occupied().bits.to_le_bytes());
+    for (i, sq) in board.occupied().iter().enumerate() {
+        let Piece(kind, color) = board.get_piece(sq).unwrap();
+        let code = color as u8 * 6 + kind as u8;
+        record[8 + i / 2] |= code << (4 * (i & 1));
+    }
+    record[24] = pos.next_to_move as u8;
+    record[25] = castling_bits(pos.white_castling) | castling_bits(pos.black_castling) << 2;
+    record[26] = pos.en_passant.map_or(255, |sq| sq.0);
+    record[27] = pos.half_moves_since_action;
+    record[28..30].copy_from_slice(&pos.full_moves.to_le_bytes());
+    record[30..32].copy_from_slice(&(sample.score as i16).to_le_bytes());
+    record[32..34].copy_from_slice(&encode_move(&sample.best_move, pos).to_le_bytes());
+    record[34] = sample.result as u8;
+    record
+}
+
+pub fn decode(record: &[u8]) -> Result<Sample, String> {
+    if record.len() != RECORD_SIZE {
+        return Err(format!("A record must have {} bytes, found {}", RECORD_SIZE, record.len()));
+    }
+    let mut board = Board::empty();
+    let mut occupied = [0; 8];
+    occupied.copy_from_slice(&record[0..8]);
+    for (i, sq) in ::bitset::BitSet::new(u64::from_le_bytes(occupied)).iter().enumerate() {
+        if i >= 32 {
+            return Err("More than 32 pieces".to_string());
+        }
+        let code = (record[8 + i / 2] >> (4 * (i & 1))) & 15;
+        if code >= 12 {
+            return Err(format!("Invalid piece code {}", code));
+        }
+        let color = if code < 6 { White } else { Black };
+        board.set_piece(sq, Piece(KINDS[(code % 6) as usize], color));
+    }
+    let position = Position {
+        board: board,
+        full_moves: u16::from_le_bytes([record[28], record[29]]),
+        next_to_move: match record[24] {
+            0 => White,
+            1 => Black,
+            c => return Err(format!("Invalid side to move {}", c))
+        },
+        white_castling: castling_right(record[25]),
+        black_castling: castling_right(record[25] >> 2),
+        white_castling_files: STANDARD_CASTLING_FILES,
+        black_castling_files: STANDARD_CASTLING_FILES,
+        en_passant: match record[26] {
+            255 => None,
+            sq if sq < 64 => Some(Square(sq)),
+            sq => return Err(format!("Invalid en passant square {}", sq))
+        },
+        half_moves_since_action: record[27],
+        psq: ::eval::calc_psq(&board)
+    };
+    let score = i16::from_le_bytes([record[30], record[31]]) as Score;
+    let encoded_move = u16::from_le_bytes([record[32], record[33]]);
+    let best_move = match position.gen_moves().find(|mv| encode_move(mv, &position) == encoded_move) {
+        Some(mv) => mv,
+        None => return Err("The best move is not legal".to_string())
+    };
+    let result = match record[34] as i8 {
+        r @ -1..=1 => r,
+        r => return Err(format!("Invalid result {}", r))
+    };
+    Ok(Sample { position: position, score: score, best_move: best_move, result: result })
+}
+
+pub fn write_sample(output: &mut dyn Write, sample: &Sample, format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => writeln!(output, "{}", to_text(sample)),
+        Format::Binary => output.write_all(&encode(sample))
+    }
+}
+
+//Entry point for: rchess gensfen <output> [--games N] [--depth N] [--nodes N] [--threads N]
+//[--random-plies N] [--seed N] [--format text|binary]
+pub fn run_cli(args: &[String], params: &EvalParams, network: Option<&Network>) -> Result<(), String> {
+    let usage = "usage: rchess gensfen <output> [--games N] [--depth N] [--nodes N] [--threads N] \
+                 [--random-plies N] [--seed N] [--format text|binary]";
+    if args.is_empty() {
+        return Err(usage.to_string());
+    }
+    let mut options = GensfenOptions::new();
+    let mut format = Format::Text;
+    let mut option_args = args[1..].iter();
+    while let Some(option) = option_args.next() {
+        let value = option_args.next().ok_or_else(|| format!("Value expected after {}\n{}", option, usage))?;
+        let invalid = || format!("Invalid value of {}: {}\n{}", option, value, usage);
+        match option.as_str() {
+            "--games" => options.games = value.parse().map_err(|_| invalid())?,
+            "--depth" => match value.parse() {
+                Ok(d) if d > 0 => options.limits = SearchLimits::depth(d),
+                _ => return Err(invalid())
+            },
+            "--nodes" => match value.parse() {
+                Ok(n) if n > 0 => options.limits = SearchLimits::nodes(n),
+                _ => return Err(invalid())
+            },
+            "--threads" => match value.parse() {
+                Ok(t) if t > 0 => options.threads = t,
+                _ => return Err(invalid())
+            },
+            "--random-plies" => options.random_plies = value.parse().map_err(|_| invalid())?,
+            "--seed" => options.seed = value.parse().map_err(|_| invalid())?,
+            "--format" => format = match value.as_str() {
+                "text" => Format::Text,
+                "binary" => Format::Binary,
+                _ => return Err(invalid())
+            },
+            _ => return Err(format!("Unknown option {}\n{}", option, usage))
+        }
+    }
+
+    let path = &args[0];
+    let file = File::create(path).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    let mut output = BufWriter::new(file);
+    let start = Instant::now();
+    let mut games = 0;
+    let mut positions = 0;
+    let mut error = None;
+    generate(&options, params, network, &mut |samples| {
+        for sample in samples {
+            if let Err(e) = write_sample(&mut output, sample, format) {
+                error.get_or_insert(e);
+            }
+        }
+        games += 1;
+        positions += samples.len();
+        if games % 10 == 0 || games == options.games {
+            println!("Games: {}/{}, positions: {}, time: {} s", games, options.games, positions,
+                     start.elapsed().as_secs());
+        }
+    });
+    if let Some(e) = error.or_else(|| output.flush().err()) {
+        return Err(format!("Can't write {}: {}", path, e));
+    }
+    println!("Wrote {} positions from {} games to {}", positions, games, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+use gensfen::*;
+use tuner::parse_position;
+
+fn sample(fen: &str, uci_move: &str, score: Score, result: i8) -> Sample {
+    let position = parse_fen(fen).unwrap();
+    let best_move = position.gen_moves()
+        .find(|mv| move_to_uci(mv, &position, false).to_string() == uci_move)
+        .unwrap();
+    Sample { position: position, score: score, best_move: best_move, result: result }
+}
+
+#[test]
+fn binary_format_test() {
+    ::tables::init_tables();
+    let samples = [sample(START_FEN, "e2e4", 35, 0),
+                   sample("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "e1c1", -120, 1),
+                   sample("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 20", "e8c8", 1999, -1),
+                   sample("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6", -7, 0),
+                   sample("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", "g2g1n", -1999, 1)];
+    for s in samples.iter() {
+        let record = encode(s);
+        assert_eq!(decode(&record), Ok(*s));
+    }
+
+    let mut record = encode(&samples[0]);
+    assert_eq!(&record[0..8], &0xffff00000000ffffu64.to_le_bytes());
+    //white rook on a1 and knight on b1
+    assert_eq!(record[8], 3 | 2 << 4);
+    assert_eq!(record[24..28], [0, 15, 255, 0]);
+    assert!(decode(&record[1..]).is_err());
+    //e2e3 is legal, e2e5 is not
+    record[32..34].copy_from_slice(&(12u16 | 20 << 6).to_le_bytes());
+    assert!(decode(&record).is_ok());
+    record[32..34].copy_from_slice(&(12u16 | 36 << 6).to_le_bytes());
+    assert!(decode(&record).is_err());
+}
+
+#[test]
+fn text_format_test() {
+    ::tables::init_tables();
+    let s = sample("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5f6", -7, -1);
+    let text = to_text(&s);
+    assert_eq!(text, "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3 | -7 | e5f6 | 0-1");
+    assert_eq!(parse_position(&text), Ok((s.position, 0.0)));
+}
+
+#[test]
+fn game_result_test() {
+    ::tables::init_tables();
+    let mate = parse_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+    assert_eq!(game_result(&mate, &[calc_position_hash(&mate)]), Some(1));
+    let stalemate = parse_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+    assert_eq!(game_result(&stalemate, &[calc_position_hash(&stalemate)]), Some(0));
+    let bishop = parse_fen("7k/8/6K1/8/8/8/8/5B2 b - - 0 1").unwrap();
+    assert_eq!(game_result(&bishop, &[calc_position_hash(&bishop)]), Some(0));
+    let start = parse_fen(START_FEN).unwrap();
+    let hash = calc_position_hash(&start);
+    assert_eq!(game_result(&start, &[hash, 1, hash]), None);
+    assert_eq!(game_result(&start, &[hash, 1, hash, 2, hash]), Some(0));
+}
+
+#[test]
+fn generate_test() {
+    ::tables::init_tables();
+    let options = GensfenOptions { games: 4, limits: SearchLimits::nodes(300), threads: 2, random_plies: 8, seed: 5 };
+    let mut samples = Vec::new();
+    let mut games = 0;
+    generate(&options, &EvalParams::default(), None, &mut |s| {
+        games += 1;
+        samples.extend_from_slice(s);
+    });
+    assert_eq!(games, 4);
+    assert!(!samples.is_empty());
+    for s in samples.iter() {
+        assert!(!s.position.is_check());
+        assert!(s.position.is_quiet(&s.best_move));
+        assert!(s.score.abs() < ADJUDICATION_SCORE);
+        assert_eq!(decode(&encode(s)), Ok(*s));
+    }
+
+    //the games don't depend on the number of threads
+    let mut single_thread = Vec::new();
+    generate(&GensfenOptions { threads: 1, ..options }, &EvalParams::default(), None,
+             &mut |s| single_thread.extend(s.iter().map(to_text)));
+    let mut texts: Vec<String> = samples.iter().map(to_text).collect();
+    texts.sort();
+    single_thread.sort();
+    assert_eq!(texts, single_thread);
+}
+
+}
@@ -14,12 +14,26 @@ pub fn calc_position_hash(position: &Position) -> u64 {
         }
     }
     result ^= castling_hash(position.white_castling, position.black_castling);
+    result ^= castling_files_hash(White, position.white_castling, position.white_castling_files);
+    result ^= castling_files_hash(Black, position.black_castling, position.black_castling_files);
     result ^= en_passant_hash(position.en_passant);
     result ^= next_to_move_hash(position.next_to_move);
     result
 
 }
 
+//hash of pawns only, used as a key of the pawn hash table
+//pawns of the same color on the same squares give the same key regardless of other pieces
+pub fn calc_pawn_hash(position: &Position) -> u64 {
+    let mut result:u64 = 0;
+    for &color in [White, Black].iter() {
+        for sq in position.board.get_pieces(Pawn, color) {
+            result ^= piece_hash(sq, Piece(Pawn, color));
+        }
+    }
+    result
+}
+
 #[inline]
 fn piece_hash(sq:Square, piece:Piece) -> u64 {
     //this function returns a random number for each (square, piece) combination
@@ -38,6 +52,22 @@ fn castling_hash(white:CastlingRight, black:CastlingRight) -> u64 {
     get_random_number(index + 800)
 }
 
+#[inline]
+fn castling_files_hash(color:Color, right:CastlingRight, files:CastlingFiles) -> u64 {
+    //this function returns a random number for each castling right with a rook on a non-standard file,
+    //so Chess960 positions with the same rights but different castling rooks get different hashes
+    //it returns random numbers from #840 to #871
+    let index = 840 + ((color as usize) << 4);
+    let mut result = 0;
+    if (right == KingCastling || right == BothCastling) && files.king_side != STANDARD_CASTLING_FILES.king_side {
+        result ^= get_random_number(index + files.king_side as usize);
+    }
+    if (right == QueenCastling || right == BothCastling) && files.queen_side != STANDARD_CASTLING_FILES.queen_side {
+        result ^= get_random_number(index + 8 + files.queen_side as usize);
+    }
+    result
+}
+
 #[inline]
 fn en_passant_hash(sq:Option<Square>) -> u64 {
     //this function returns a random number for each en pasant file
@@ -74,8 +104,11 @@ fn one_piece_positions() {
         next_to_move : White,
         white_castling : BothCastling,
         black_castling : BothCastling,
+        white_castling_files : STANDARD_CASTLING_FILES,
+        black_castling_files : STANDARD_CASTLING_FILES,
         en_passant : None,
-        half_moves_since_action : 0
+        half_moves_since_action : 0,
+        psq : ::eval::TaperedScore::default()
     };
     for &w_castling in [BothCastling, QueenCastling, KingCastling, NoCastling].iter() {
         for &b_castling in [BothCastling, QueenCastling, KingCastling, NoCastling].iter() {
@@ -119,6 +152,50 @@ fn position_tree_hashes() {
 }
 
 
+#[test]
+fn pawn_hash_test() {
+    ::tables::init_tables();
+    let position = parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let pawn_hash = calc_pawn_hash(&position);
+    let mut pawn_moves = 0;
+    for mv in position.gen_moves() {
+        let mut p = position;
+        let captured = p.apply_move(&mv);
+        let pawns_changed = match mv {
+            OrdinaryMove(ref mi) => mi.kind == Pawn || captured.map(|c| c.kind()) == Some(Pawn),
+            _ => false
+        };
+        if pawns_changed {
+            pawn_moves += 1;
+            assert!(calc_pawn_hash(&p) != pawn_hash);
+        } else {
+            assert_eq!(calc_pawn_hash(&p), pawn_hash);
+        }
+    }
+    assert!(pawn_moves > 0);
+
+    //only pawns are hashed
+    let a = parse_fen("4k3/pp6/8/8/8/8/6PP/R3K3 w Q - 0 1").unwrap();
+    let b = parse_fen("3qk3/pp6/8/8/8/8/6PP/4K3 b - - 0 1").unwrap();
+    assert_eq!(calc_pawn_hash(&a), calc_pawn_hash(&b));
+    assert!(calc_pawn_hash(&a) != calc_position_hash(&a));
+}
+
+#[test]
+fn castling_files_hash_test() {
+    ::tables::init_tables();
+    //same castling rights, but the queen side rook is on a1 in one position and on b1 in the other
+    let a = parse_fen("4k3/8/8/8/8/8/8/RR2K2R w KQ - 0 1").unwrap();
+    let b = parse_fen("4k3/8/8/8/8/8/8/RR2K2R w KB - 0 1").unwrap();
+    assert_eq!(a.white_castling, b.white_castling);
+    assert!(calc_position_hash(&a) != calc_position_hash(&b));
+
+    //rooks on the standard files don't change the hash
+    let standard = parse_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let shredder = parse_fen("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1").unwrap();
+    assert_eq!(calc_position_hash(&standard), calc_position_hash(&shredder));
+}
+
 fn perft(p: &Position, depth:usize, positions: &mut Vec<String>){
     let hash = calc_position_hash(p);
     positions.push(format!("{:016x} - {}", hash, render_fen(p)));
@@ -0,0 +1,176 @@
+//King safety
+//Weaknesses around the king and enemy pieces attacking it are summed up in danger units.
+//The penalty grows with the square of the danger, so a single weakness or a lone attacker
+//costs little, while a combined attack on an exposed king is scored as decisive.
+//More: https://www.chessprogramming.org/King_Safety
+use std::cmp::{max, min};
+use bitset::BitSet;
+use eval::{Score, TaperedScore};
+use eval_params::EvalParams;
+use trace::{Trace, Term};
+use move_gen::{gen_bishop_moves, gen_knight_moves, gen_queen_moves, gen_rook_moves};
+use pawns::{FILE_A, relative_rank};
+use tables::get_king_moves_mask;
+use types::*;
+
+//king safety of both sides, from the white point of view
+//penalty is danger * danger / king_danger_divisor, capped at max_king_danger
+//it is applied only in the middlegame, in the endgame the king is an active piece
+//the penalty is not linear in the parameters, so it is traced as a constant
+pub fn eval_king_safety<T: Trace>(board: &Board, params: &EvalParams, trace: &mut T) -> TaperedScore {
+    let mut score = TaperedScore::default();
+    trace.begin(Term::KingSafety);
+    for &color in [White, Black].iter() {
+        let danger = king_danger(board, color, params);
+        let penalty = TaperedScore::new(min(danger * danger / params.king_danger_divisor, params.max_king_danger), 0);
+        trace.add_nonlinear(color, -penalty);
+        score += if color == White { -penalty } else { penalty };
+    }
+    score
+}
+
+//danger units of the king of the given color
+pub fn king_danger(board: &Board, color: Color, params: &EvalParams) -> Score {
+    let king_sq = match board.get_pieces(King, color).next() {
+        Some(sq) => sq,
+        None => return 0
+    };
+    let enemy = color.inverse();
+    let occupied = board.occupied();
+    let empty = BitSet::empty();
+    let zone = get_king_moves_mask(king_sq) | BitSet::from_one_square(king_sq);
+    let defended = board.attack_map(color);
+    let safe = !defended & !board.get_color_bitset(enemy);
+
+    //squares from which a piece of each kind gives a check
+    let knight_checks = gen_knight_moves(empty, king_sq);
+    let bishop_checks = gen_bishop_moves(occupied, empty, king_sq);
+    let rook_checks = gen_rook_moves(occupied, empty, king_sq);
+
+    let mut attackers = 0;
+    let mut attack_weight = 0;
+    let mut danger = 0;
+    for &kind in [Knight, Bishop, Rook, Queen].iter() {
+        for sq in board.get_pieces(kind, enemy) {
+            let (attacks, checks) = match kind {
+                Knight => (gen_knight_moves(empty, sq), knight_checks),
+                Bishop => (gen_bishop_moves(occupied, empty, sq), bishop_checks),
+                Rook => (gen_rook_moves(occupied, empty, sq), rook_checks),
+                _ => (gen_queen_moves(occupied, empty, sq), bishop_checks | rook_checks)
+            };
+            let zone_attacks = (attacks & zone).count() as Score;
+            if zone_attacks > 0 {
+                attackers += 1;
+                attack_weight += zone_attacks * params.king_zone_attack_weight[kind as usize];
+            }
+            if !(attacks & checks & safe).is_empty() {
+                danger += params.king_safe_check_weight[kind as usize];
+            }
+        }
+    }
+    danger += attack_weight * params.king_attackers_scale[min(attackers, 7)] / 100;
+
+    let own_pawns = (board.pawns & board.get_color_bitset(color)).bits;
+    let enemy_pawns = (board.pawns & board.get_color_bitset(enemy)).bits;
+    let king_rank = relative_rank(king_sq, color) as Score;
+    let king_file = king_sq.file() as i32;
+    for file in max(king_file - 1, 0)..min(king_file + 2, 8) {
+        let file_mask = FILE_A << file;
+        //the nearest pawn in front of the king on the file, distance 0 if there is none
+        let nearest = |pawns: u64| BitSet::new(pawns & file_mask).iter()
+            .map(|sq| relative_rank(sq, color) as Score - king_rank)
+            .filter(|&d| d > 0)
+            .min()
+            .unwrap_or(0);
+
+        let shield = nearest(own_pawns);
+        danger += params.king_pawn_shield_weight[min(shield, 3) as usize];
+        let storm = nearest(enemy_pawns);
+        if storm < params.king_pawn_storm_weight.len() as Score {
+            danger += params.king_pawn_storm_weight[storm as usize];
+        }
+        if own_pawns & file_mask == 0 {
+            danger += params.king_semi_open_file_weight;
+            if enemy_pawns & file_mask == 0 {
+                danger += params.king_open_file_weight;
+            }
+        }
+    }
+
+    //without the enemy queen an attack rarely succeeds
+    if board.get_pieces(Queen, enemy).next().is_none() {
+        danger /= 2;
+    }
+    danger
+}
+
+#[cfg(test)]
+mod tests {
+use king_safety::*;
+use fen::parse_fen;
+use trace::NoTrace;
+
+fn danger(fen: &str, color: Color) -> Score {
+    king_danger(&parse_fen(fen).unwrap().board, color, &EvalParams::default())
+}
+
+#[test]
+fn pawn_shield_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    //intact shield, enemy queen is locked in by its own pawns
+    let intact = danger("3qk3/3pp3/8/8/8/8/5PPP/6K1 w - - 0 1", White);
+    assert_eq!(intact, 0);
+    //g3 is 2 ranks in front of the king
+    let advanced = danger("3qk3/3pp3/8/8/8/6P1/5P1P/6K1 w - - 0 1", White);
+    assert_eq!(advanced, p.king_pawn_shield_weight[2]);
+    //no pawn on the g file, enemy pawns don't block it either
+    let missing = danger("3qk3/3pp3/8/8/8/8/5P1P/6K1 w - - 0 1", White);
+    assert_eq!(missing, p.king_pawn_shield_weight[0] + p.king_semi_open_file_weight + p.king_open_file_weight);
+    //black pawn on g4 is 3 ranks in front of the king, the g file is only semi-open
+    let storm = danger("3qk3/3pp3/8/8/6p1/8/5P1P/6K1 w - - 0 1", White);
+    assert_eq!(storm, p.king_pawn_shield_weight[0] + p.king_semi_open_file_weight + p.king_pawn_storm_weight[3]);
+    //without the enemy queen danger is halved
+    let no_queen = danger("4k3/3pp3/8/8/6p1/8/5P1P/6K1 w - - 0 1", White);
+    assert_eq!(no_queen, storm / 2);
+}
+
+#[test]
+fn king_attack_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    //queen on h5 attacks h2, it can check only from h2, which is defended
+    let one = danger("4k3/8/8/7q/8/8/5PPP/4R1K1 w - - 0 1", White);
+    //knight on g4 joins the attack, it attacks h2 and f2, but it can't check
+    let two = danger("4k3/8/8/7q/6n1/8/5PPP/4R1K1 w - - 0 1", White);
+    //one attacker alone doesn't count
+    assert_eq!(one, 0);
+    //queen attacks h2, knight attacks f2 and h2
+    assert_eq!(two, (p.king_zone_attack_weight[Queen as usize] + 2 * p.king_zone_attack_weight[Knight as usize]) *
+                    p.king_attackers_scale[2] / 100);
+
+    //bishop on c6 attacks g2, danger grows and the penalty grows faster
+    let board = parse_fen("4k3/8/8/7q/6n1/8/5PPP/4R1K1 w - - 0 1").unwrap().board;
+    let penalty = -eval_king_safety(&board, &p, &mut NoTrace).mg;
+    let three = parse_fen("4k3/8/2b5/7q/6n1/8/5PPP/4R1K1 w - - 0 1").unwrap().board;
+    assert!(king_danger(&three, White, &p) > two);
+    assert!(-eval_king_safety(&three, &p, &mut NoTrace).mg > 2 * penalty);
+}
+
+#[test]
+fn safe_check_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    //rook can check from a1, nothing defends it
+    let open = danger("4k3/8/8/8/8/r7/5PPP/6K1 w - - 0 1", White);
+    assert_eq!(open, p.king_safe_check_weight[Rook as usize] / 2);
+    //the same check square is defended by a white knight
+    let defended = danger("4k3/8/8/8/8/r7/2N2PPP/6K1 w - - 0 1", White);
+    assert_eq!(defended, 0);
+
+    //symmetric position has no king safety score
+    let board = parse_fen("r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQK2R w KQkq - 0 1").unwrap().board;
+    assert_eq!(eval_king_safety(&board, &p, &mut NoTrace), TaperedScore::default());
+}
+
+}
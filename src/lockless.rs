@@ -0,0 +1,94 @@
+//Hash table shared by several threads without locks, used by the transposition table and the perft cache.
+//Each entry is two words: (key ^ data, data). If another thread overwrites
+//a half of the entry, the key check fails and the entry is just ignored.
+//More: https://www.chessprogramming.org/Shared_Hash_Table#Lockless
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct LocklessTable {
+    entries: Vec<(AtomicU64, AtomicU64)>
+}
+
+impl LocklessTable {
+    pub fn new(size_mb: usize) -> LocklessTable {
+        let entry_size = 2 * ::std::mem::size_of::<u64>();
+        let len = ::std::cmp::max(1, size_mb * 1024 * 1024 / entry_size);
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            entries.push((AtomicU64::new(0), AtomicU64::new(0)));
+        }
+        LocklessTable { entries: entries }
+    }
+
+    pub fn clear(&self) {
+        for (key_word, data_word) in self.entries.iter() {
+            key_word.store(0, Ordering::Relaxed);
+            data_word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    fn entry(&self, key: u64) -> &(AtomicU64, AtomicU64) {
+        &self.entries[(key % self.entries.len() as u64) as usize]
+    }
+
+    //zero data means an empty entry, so users must never store it
+    #[inline]
+    pub fn load(&self, key: u64) -> Option<u64> {
+        let (key_word, data_word) = self.entry(key);
+        let data = data_word.load(Ordering::Relaxed);
+        if data != 0 && key_word.load(Ordering::Relaxed) ^ data == key {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn store(&self, key: u64, data: u64) {
+        let (key_word, data_word) = self.entry(key);
+        key_word.store(key ^ data, Ordering::Relaxed);
+        data_word.store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use lockless::*;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn load_store_test() {
+    let table = LocklessTable::new(1);
+    assert_eq!(table.load(12345), None);
+    table.store(12345, 42);
+    assert_eq!(table.load(12345), Some(42));
+    assert_eq!(table.load(54321), None);
+    table.clear();
+    assert_eq!(table.load(12345), None);
+}
+
+#[test]
+fn concurrent_access_test() {
+    let table = Arc::new(LocklessTable::new(1));
+    let mut workers = Vec::new();
+    for t in 0..4 {
+        let table = table.clone();
+        workers.push(thread::spawn(move || {
+            for i in 0..100000u64 {
+                //entries of all threads compete for the same slots
+                let key = (i % 1000).wrapping_mul(0x9E37_79B9_7F4A_7C15) + t;
+                table.store(key, key % 10000 + 1);
+                if let Some(data) = table.load(key ^ 1) {
+                    //entry is never a mix of two different stores
+                    assert_eq!(data, (key ^ 1) % 10000 + 1);
+                }
+            }
+        }));
+    }
+    for w in workers {
+        w.join().unwrap();
+    }
+}
+
+}
@@ -13,15 +13,109 @@ mod move_gen;
 mod utils;
 mod tables;
 mod eval;
+mod eval_params;
+mod pawns;
+mod mobility;
+mod king_safety;
+mod endgame;
+mod trace;
+mod nnue;
+mod tuner;
+mod gensfen;
 mod search;
+mod see;
+mod tt;
+mod lockless;
+mod move_order;
 mod hash;
+mod perft;
+mod bench;
 pub mod uci;
 #[cfg(test)]
 mod perft_tests;
 #[cfg(test)]
 mod search_test;
+#[cfg(test)]
+mod symmetry_test;
 
 fn main() {
   tables::init_tables();
-  uci::UciEngine::new().std_main_loop();
+  let mut args: Vec<String> = std::env::args().collect();
+  let params = match load_eval_params(&mut args) {
+    Ok(params) => params,
+    Err(e) => {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+  };
+  let network = match load_network(&mut args) {
+    Ok(network) => network,
+    Err(e) => {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+  };
+  if args.len() > 1 && args[1] == "perft" {
+    if let Err(e) = perft::run_cli(&args[2..]) {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+  if args.len() > 1 && args[1] == "bench" {
+    if let Err(e) = bench::run_cli(&args[2..], &params, network.as_ref()) {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+  if args.len() > 1 && args[1] == "tune" {
+    if let Err(e) = tuner::run_cli(&args[2..], &params) {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+  if args.len() > 1 && args[1] == "gensfen" {
+    if let Err(e) = gensfen::run_cli(&args[2..], &params, network.as_ref()) {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+  if args.len() > 1 && args[1] == "eval-params" {
+    print!("{}", params.to_text());
+    return;
+  }
+  let mut engine = uci::UciEngine::with_eval_params(params);
+  if let Some(network) = network {
+    engine.set_network(network);
+  }
+  engine.std_main_loop();
+}
+
+//--eval-file <path> can be given with any command, it is removed from the arguments
+fn load_eval_params(args: &mut Vec<String>) -> Result<eval_params::EvalParams, String> {
+  match args.iter().position(|a| a == "--eval-file") {
+    Some(i) if i + 1 < args.len() => {
+      let path = args.remove(i + 1);
+      args.remove(i);
+      eval_params::EvalParams::load(&path)
+    },
+    Some(_) => Err("usage: --eval-file <path>".to_string()),
+    None => Ok(eval_params::EvalParams::default())
+  }
+}
+
+//--nnue <path> loads a network that replaces the classical evaluation in bench and the UCI mode
+fn load_network(args: &mut Vec<String>) -> Result<Option<nnue::Network>, String> {
+  match args.iter().position(|a| a == "--nnue") {
+    Some(i) if i + 1 < args.len() => {
+      let path = args.remove(i + 1);
+      args.remove(i);
+      nnue::Network::load(&path).map(Some)
+    },
+    Some(_) => Err("usage: --nnue <path>".to_string()),
+    None => Ok(None)
+  }
 }
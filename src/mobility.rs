@@ -0,0 +1,134 @@
+//Piece mobility and activity
+//Mobility is the number of squares a piece attacks, without squares occupied by friendly pieces
+//and squares attacked by enemy pawns, where the piece would be lost for a pawn.
+//More: https://www.chessprogramming.org/Mobility
+//      https://www.chessprogramming.org/Outposts
+use bitset::BitSet;
+use eval::{Score, TaperedScore};
+use eval_params::EvalParams;
+use move_gen::{gen_bishop_moves, gen_knight_moves, gen_queen_moves, gen_rook_moves};
+use pawns::{FILE_A, adjacent_files, forward_ranks, pawn_attacks, relative_rank};
+use trace::{Trace, Term};
+use types::*;
+
+//mobility and activity of knights, bishops, rooks and queens, from the white point of view
+pub fn eval_pieces<T: Trace>(board: &Board, params: &EvalParams, trace: &mut T) -> TaperedScore {
+    let mut score = TaperedScore::default();
+    let occupied = board.occupied();
+    for &color in [White, Black].iter() {
+        let friendly = board.get_color_bitset(color);
+        let own_pawns = (board.pawns & friendly).bits;
+        let enemy_pawns = (board.pawns & board.get_color_bitset(color.inverse())).bits;
+        let enemy_pawn_attacks = BitSet::new(pawn_attacks(enemy_pawns, color.inverse()));
+        let mut side = TaperedScore::default();
+
+        trace.begin(Term::Mobility);
+        for &kind in [Knight, Bishop, Rook, Queen].iter() {
+            for sq in board.get_pieces(kind, color) {
+                let moves = match kind {
+                    Knight => gen_knight_moves(friendly, sq),
+                    Bishop => gen_bishop_moves(occupied, friendly, sq),
+                    Rook => gen_rook_moves(occupied, friendly, sq),
+                    _ => gen_queen_moves(occupied, friendly, sq)
+                };
+                let mobility = (moves & !enemy_pawn_attacks).count() as Score - params.mobility_base[kind as usize];
+                side += params.mobility[kind as usize] * mobility;
+                trace.add(color, mobility, |p| &mut p.mobility[kind as usize]);
+            }
+        }
+
+        trace.begin(Term::PieceActivity);
+        if board.get_pieces(Bishop, color).count() >= 2 {
+            side += params.bishop_pair;
+            trace.add(color, 1, |p| &mut p.bishop_pair);
+        }
+
+        for sq in board.get_pieces(Rook, color) {
+            let file = FILE_A << sq.file();
+            if (own_pawns | enemy_pawns) & file == 0 {
+                side += params.rook_open_file;
+                trace.add(color, 1, |p| &mut p.rook_open_file);
+            } else if own_pawns & file == 0 {
+                side += params.rook_semi_open_file;
+                trace.add(color, 1, |p| &mut p.rook_semi_open_file);
+            }
+            if relative_rank(sq, color) == 6 {
+                let rank = 0xffu64 << (8 * sq.rank());
+                let enemy_king_on_eighth = board.get_pieces(King, color.inverse())
+                    .any(|k| relative_rank(k, color) == 7);
+                if enemy_pawns & rank != 0 || enemy_king_on_eighth {
+                    side += params.rook_on_seventh;
+                    trace.add(color, 1, |p| &mut p.rook_on_seventh);
+                }
+            }
+        }
+
+        for sq in board.get_pieces(Knight, color) {
+            let rank = relative_rank(sq, color);
+            let defended = pawn_attacks(own_pawns, color) & (1u64 << sq.file_and_rank()) != 0;
+            let attackable = enemy_pawns & adjacent_files(sq) & forward_ranks(sq, color) != 0;
+            if (3..=5).contains(&rank) && defended && !attackable {
+                side += params.knight_outpost;
+                trace.add(color, 1, |p| &mut p.knight_outpost);
+            }
+        }
+
+        score += if color == White { side } else { -side };
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+use mobility::*;
+use fen::parse_fen;
+
+fn eval(fen: &str) -> TaperedScore {
+    eval_pieces(&parse_fen(fen).unwrap().board, &EvalParams::default(), &mut ::trace::NoTrace)
+}
+
+#[test]
+fn mobility_test() {
+    ::tables::init_tables();
+    //bishop on d4 attacks 13 squares
+    assert_eq!(eval("4k3/8/8/8/3B4/8/8/4K3 w - - 0 1"), TaperedScore::new(5, 5) * (13 - 6));
+    //bishop on a1 is blocked by its own pawn
+    assert_eq!(eval("4k3/8/8/8/8/8/1P6/B3K3 w - - 0 1"), TaperedScore::new(5, 5) * (0 - 6));
+    //knight on e4 attacks 8 squares, but d6 and f6 are attacked by the pawn on e7
+    assert_eq!(eval("4k3/4p3/8/8/4N3/8/8/4K3 w - - 0 1"), TaperedScore::new(4, 4) * (6 - 4));
+    //mobility of both sides is the same
+    assert_eq!(eval("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"), TaperedScore::default());
+}
+
+#[test]
+fn activity_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    let bishops = eval("4k3/8/8/8/8/8/1P4P1/2B2B1K w - - 0 1");
+    let bishop_and_knight = eval("4k3/8/8/8/8/8/1P4P1/2B2N1K w - - 0 1");
+    //bishop on f1 attacks 5 squares, knight on f1 attacks 4 squares
+    //which is the knight mobility base, so the knight gets no mobility score
+    let knight_mobility = TaperedScore::default();
+    let bishop_mobility = TaperedScore::new(5, 5) * (5 - 6);
+    assert_eq!(bishops - bishop_and_knight, p.bishop_pair + bishop_mobility - knight_mobility);
+
+    //rooks on the open d file and the semi-open e file attack 11 squares, d6 is attacked by the pawn
+    let open = eval("4k3/4p3/8/8/8/8/8/3R2K1 w - - 0 1");
+    let semi_open = eval("4k3/4p3/8/8/8/8/8/4R1K1 w - - 0 1");
+    assert_eq!(open - semi_open, p.rook_open_file - p.rook_semi_open_file);
+
+    //rook on the 7th rank with enemy pawns there, and the same rook without pawns to attack
+    let seventh = eval("7k/R5pp/8/8/8/8/8/6K1 w - - 0 1");
+    let sixth = eval("7k/6pp/R7/8/8/8/8/6K1 w - - 0 1");
+    //on a7 the rook attacks 13 squares, on a6 it attacks 11 squares, because f6, g6 and h6 are attacked by pawns
+    let mobility = TaperedScore::new(2, 4) * (13 - 11);
+    assert_eq!(seventh - sixth, p.rook_on_seventh + mobility);
+
+    //knight on d5 defended by e4, c6 and e6 can't be attacked by black pawns
+    let outpost = eval("4k3/p7/8/3N4/4P3/8/8/4K3 w - - 0 1");
+    let no_outpost = eval("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1");
+    //the knight attacks 7 squares in both positions, b6 is attacked by a pawn and c7 can be captured
+    assert_eq!(outpost - no_outpost, p.knight_outpost);
+}
+
+}
@@ -1,11 +1,28 @@
 use types::*;
 use bitset::BitSet;
+pub use self::GenMode::*;
 
 //Dirty bit tricks are used in move_gen
 //see more here:
 //https://www.chessprogramming.org/Efficient_Generation_of_Sliding_Piece_Attacks
 //https://www.chessprogramming.org/Hyperbola_Quintessence
 
+//Kinds of moves that a moves iterator can be asked for.
+//Quiescence search needs only captures, and main search can try them
+//before the quiet moves without sorting everything.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GenMode {
+    AllMoves,
+    Captures, //captures (including en passant) and all promotions
+    Quiets,   //everything else: non-capturing moves and castlings
+    Evasions  //moves that can get the king out of check, side to move must be in check
+}
+
+//Generates only legal moves.
+//Checkers and pinned pieces are found once for a position, so there is no need
+//to apply every move and look if the king is under attack afterwards.
+//Only en passant captures are still tested this way, because removal of two pawns
+//from a rank can open the king in a way that pins don't describe.
 pub struct LegalMovesIterator {
     moves_iter: MovesIterator
 }
@@ -13,34 +30,47 @@ pub struct LegalMovesIterator {
 impl Iterator for LegalMovesIterator {
     type Item = Move;
 
+    #[inline]
     fn next(&mut self) -> Option<Move> {
-        loop {
-            let next_move = self.moves_iter.next();
-            match next_move {
-                Some(m) => {
-                    if is_legal_move(&self.moves_iter.position, &m) {
-                        return next_move;
-                    }
-                }
-                None => { return None; }
-            };
-        }
+        self.moves_iter.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.moves_iter.size_hint()
     }
 }
 
 impl LegalMovesIterator {
     pub fn new(pos: &Position) -> LegalMovesIterator {
+        LegalMovesIterator::with_mode(pos, AllMoves)
+    }
+
+    pub fn with_mode(pos: &Position, mode: GenMode) -> LegalMovesIterator {
         LegalMovesIterator {
-            moves_iter : MovesIterator::new(pos)
+            moves_iter : MovesIterator::create(pos, mode, true)
         }
     }
 }
 
+//Generates pseudo-legal moves, i.e. moves that might leave the king under attack
 pub struct MovesIterator {
     position: Position,
     moves_cache: Vec<Move>,
     occupied_set: BitSet,
     friendly_set: BitSet,
+    //squares where pieces other than king and pawns can go in the current mode
+    target_set: BitSet,
+    //same for pawns, it also takes care about promotions and en passant
+    pawn_target_set: BitSet,
+    //same for king
+    king_target_set: BitSet,
+    castling_allowed: bool,
+    //if true only legal moves are generated
+    legal: bool,
+    king_sq: Option<Square>,
+    //friendly pieces that can't leave the line between the king and an enemy slider
+    pinned_set: BitSet,
     next_kind: Kind,
     can_gen_more: bool
 }
@@ -65,29 +95,104 @@ impl Iterator for MovesIterator {
 }
 impl MovesIterator {
     pub fn new(pos: &Position) -> MovesIterator {
+        MovesIterator::with_mode(pos, AllMoves)
+    }
+
+    pub fn with_mode(pos: &Position, mode: GenMode) -> MovesIterator {
+        MovesIterator::create(pos, mode, false)
+    }
+
+    fn create(pos: &Position, mode: GenMode, legal: bool) -> MovesIterator {
+        let board = &pos.board;
+        let color = pos.next_to_move;
+        let occupied_set = board.whites | board.blacks;
+        let friendly_set = board.get_color_bitset(color);
+        let enemy_set = board.get_color_bitset(color.inverse());
+        let en_passant_set = match pos.en_passant {
+            Some(s) => BitSet::from_one_square(s),
+            None => BitSet::empty()
+        };
+        let promotion_set = BitSet::new(0xff000000000000ffu64);
+        let everything = !BitSet::empty();
+
+        let (mut target_set, mut pawn_target_set, king_target_set, mut castling_allowed) = match mode {
+            AllMoves => (everything, everything, everything, true),
+            Captures => (enemy_set, enemy_set | en_passant_set | promotion_set, enemy_set, false),
+            Quiets => (!occupied_set, !(occupied_set | en_passant_set | promotion_set), !occupied_set, true),
+            Evasions => (everything, everything, everything, false)
+        };
+
+        let king_sq = (board.kings & friendly_set).iter().next();
+        let checkers = match king_sq {
+            Some(sq) => board.attackers_to(sq, occupied_set) & enemy_set,
+            None => BitSet::empty()
+        };
+        debug_assert!(mode != Evasions || !checkers.is_empty(), "evasions are generated for a position without check");
+
+        if (legal || mode == Evasions) && !checkers.is_empty() {
+            castling_allowed = false;
+            if checkers.count() > 1 {
+                //double check, only king can move
+                target_set = BitSet::empty();
+                pawn_target_set = BitSet::empty();
+            } else {
+                let checker_sq = checkers.iter().next().unwrap();
+                let check_set = checkers | ::tables::get_between_mask(king_sq.unwrap(), checker_sq);
+                target_set = target_set & check_set;
+                //a pawn that just made a double step and gives check can be taken en passant
+                pawn_target_set = if !(checkers & board.pawns).is_empty() {
+                    pawn_target_set & (check_set | en_passant_set)
+                } else {
+                    pawn_target_set & check_set
+                };
+            }
+        }
+
+        let pinned_set = match king_sq {
+            Some(sq) if legal => pinned_pieces(board, sq, color),
+            _ => BitSet::empty()
+        };
+
         MovesIterator {
             position : *pos,
             moves_cache : Vec::with_capacity(32),
             next_kind : Queen,
-            occupied_set : pos.board.whites | pos.board.blacks,
-            friendly_set : pos.board.get_color_bitset(pos.next_to_move),
+            occupied_set : occupied_set,
+            friendly_set : friendly_set,
+            target_set : target_set,
+            pawn_target_set : pawn_target_set,
+            king_target_set : king_target_set,
+            castling_allowed : castling_allowed,
+            legal : legal,
+            king_sq : king_sq,
+            pinned_set : pinned_set,
             can_gen_more : true
         }
     }
 
+    //squares where a piece can go without exposing the king
+    #[inline]
+    fn pin_mask(&self, from_sq: Square) -> BitSet {
+        if self.pinned_set.get(from_sq) {
+            ::tables::get_line_mask(self.king_sq.unwrap(), from_sq)
+        } else {
+            !BitSet::empty()
+        }
+    }
+
     fn gen_more_moves(&mut self) -> bool {
-        let result = &mut self.moves_cache;
         let occupied_set = self.occupied_set;
         let friendly_set = self.friendly_set;
-        let pos = &self.position;
-        let board = &pos.board;
-        let color = pos.next_to_move;
+        let target_set = self.target_set;
+        let color = self.position.next_to_move;
+        let board = self.position.board;
         match self.next_kind {
            Queen => {
                 for from_sq in board.get_pieces(Queen, color) {
-                    let moves_set = gen_queen_moves(occupied_set, friendly_set, from_sq);
+                    let moves_set = gen_queen_moves(occupied_set, friendly_set, from_sq)
+                                    & target_set & self.pin_mask(from_sq);
                     for to_sq in moves_set.iter() {
-                        result.push(squares_to_move(Queen, from_sq, to_sq));
+                        self.moves_cache.push(squares_to_move(Queen, from_sq, to_sq));
                     }
                 }
                 self.next_kind = Rook;
@@ -95,9 +200,10 @@ impl MovesIterator {
            }
            Rook => {
                 for from_sq in board.get_pieces(Rook, color) {
-                    let moves_set = gen_rook_moves(occupied_set, friendly_set, from_sq);
+                    let moves_set = gen_rook_moves(occupied_set, friendly_set, from_sq)
+                                    & target_set & self.pin_mask(from_sq);
                     for to_sq in moves_set.iter() {
-                        result.push(squares_to_move(Rook, from_sq, to_sq));
+                        self.moves_cache.push(squares_to_move(Rook, from_sq, to_sq));
                     }
                 }
                 self.next_kind = Bishop;
@@ -105,9 +211,10 @@ impl MovesIterator {
            }
            Bishop => {
                 for from_sq in board.get_pieces(Bishop, color) {
-                    let moves_set = gen_bishop_moves(occupied_set, friendly_set, from_sq);
+                    let moves_set = gen_bishop_moves(occupied_set, friendly_set, from_sq)
+                                    & target_set & self.pin_mask(from_sq);
                     for to_sq in moves_set.iter() {
-                        result.push(squares_to_move(Bishop, from_sq, to_sq));
+                        self.moves_cache.push(squares_to_move(Bishop, from_sq, to_sq));
                     }
                 }
                 self.next_kind = Knight;
@@ -115,85 +222,143 @@ impl MovesIterator {
            }
            Knight => {
                 for from_sq in board.get_pieces(Knight, color) {
-                    let moves_set = gen_knight_moves(friendly_set, from_sq);
+                    //a pinned knight can never move
+                    let moves_set = gen_knight_moves(friendly_set, from_sq)
+                                    & target_set & self.pin_mask(from_sq);
                     for to_sq in moves_set.iter() {
-                        result.push(squares_to_move(Knight, from_sq, to_sq));
+                        self.moves_cache.push(squares_to_move(Knight, from_sq, to_sq));
                     }
                 }
                 self.next_kind = Pawn;
                 true
            }
            Pawn => {
-                let en_passant_set = match pos.en_passant {
+                let en_passant_set = match self.position.en_passant {
                                         Some(s) => BitSet::from_one_square(s),
                                         None => BitSet::empty()
                                      };
-
-                if color == White {
-                    let pawn_enemy_set = board.get_color_bitset(Black) | en_passant_set;
-                    for from_sq in board.get_pieces(Pawn, color) {
-                        let moves_set = gen_white_pawn_moves(occupied_set, pawn_enemy_set, from_sq);
-                        add_pawn_moves(result, from_sq, moves_set);
-                    }
-                } else {
-                    let pawn_enemy_set = board.get_color_bitset(White) | en_passant_set;
-                    for from_sq in board.get_pieces(Pawn, color) {
-                        let moves_set = gen_black_pawn_moves(occupied_set, pawn_enemy_set, from_sq);
-                        add_pawn_moves(result, from_sq, moves_set);
+                let pawn_enemy_set = board.get_color_bitset(color.inverse()) | en_passant_set;
+                for from_sq in board.get_pieces(Pawn, color) {
+                    let moves_set = if color == White {
+                        gen_white_pawn_moves(occupied_set, pawn_enemy_set, from_sq)
+                    } else {
+                        gen_black_pawn_moves(occupied_set, pawn_enemy_set, from_sq)
+                    } & self.pawn_target_set & self.pin_mask(from_sq);
+
+                    let en_passant_moves = moves_set & en_passant_set;
+                    add_pawn_moves(&mut self.moves_cache, from_sq, moves_set & !en_passant_set);
+                    for to_sq in en_passant_moves.iter() {
+                        let mv = Move::new(Pawn, from_sq, to_sq, None);
+                        if !self.legal || is_legal_move(&self.position, &mv) {
+                            self.moves_cache.push(mv);
+                        }
                     }
                 }
                 self.next_kind = King;
                 true
            },
            King => {
+                //the king itself must not block attacks on squares it goes to
+                let occupied_without_king = match self.king_sq {
+                    Some(sq) => occupied_set & !BitSet::from_one_square(sq),
+                    None => occupied_set
+                };
                 for from_sq in board.get_pieces(King, color) {
-                    let moves_set = gen_king_moves(friendly_set, from_sq);
+                    let moves_set = gen_king_moves(friendly_set, from_sq) & self.king_target_set;
                     for to_sq in moves_set.iter() {
-                        result.push(squares_to_move(King, from_sq, to_sq));
+                        let attackers = board.attackers_to(to_sq, occupied_without_king)
+                                        & board.get_color_bitset(color.inverse());
+                        if !self.legal || attackers.is_empty() {
+                            self.moves_cache.push(squares_to_move(King, from_sq, to_sq));
+                        }
                     }
                 }
 
+                if !self.castling_allowed {
+                    return false;
+                }
+
                 //castling
-                let (castle_rank, queen_castle_allowed, king_castle_allowed) =
-                    match (color, pos.white_castling, pos.black_castling) {
-                    (White, QueenCastling, _) => (0, true,  false),
-                    (White, KingCastling, _)  => (0, false, true),
-                    (White, BothCastling, _)  => (0, true,  true),
-                    (Black, _, QueenCastling) => (7, true,  false),
-                    (Black, _, KingCastling)  => (7, false, true),
-                    (Black, _, BothCastling)  => (7, true,  true),
-                    (White, NoCastling, _)| (Black, _, NoCastling)  => (3, false, false)
+                let (castling, castle_rank) = match color {
+                    White => (self.position.white_castling, 0),
+                    Black => (self.position.black_castling, 7)
                 };
-
-                //here we assume that if castling right is specified then
-                //king and rook are on the castling ready positions
-                if king_castle_allowed {
-                    if occupied_set.get_rank(castle_rank) & 0b01100000u8 == 0 {
-                        result.push(CastleKingSide);
-                    }
+                if castling == NoCastling || !board.get_pieces(King, color).any(|sq| sq.rank() == castle_rank) {
+                    return false;
                 }
-                if queen_castle_allowed {
-                    if occupied_set.get_rank(castle_rank) & 0b00001110u8 == 0 {
-                        result.push(CastleQueenSide);
-                    }
+                if castling == KingCastling || castling == BothCastling {
+                    self.gen_castling(CastleKingSide);
+                }
+                if castling == QueenCastling || castling == BothCastling {
+                    self.gen_castling(CastleQueenSide);
                 }
                 false
            }
         }
     }
+
+    //Castling is possible if all squares between the king and its destination and between the rook and
+    //its destination are empty except for the king and the rook themselves, and the king doesn't pass
+    //attacked squares. The same rule covers standard chess and Chess960.
+    fn gen_castling(&mut self, mv: Move) {
+        use tables::get_between_mask;
+        let board = &self.position.board;
+        let color = self.position.next_to_move;
+        let cs = self.position.castling_squares(&mv);
+        let king_sq = cs.king_from;
+        let rook_set = BitSet::from_one_square(cs.rook_from);
+        if (rook_set & board.rooks & self.friendly_set).is_empty() {
+            return;
+        }
+        let castling_pieces = rook_set | BitSet::from_one_square(king_sq);
+        let king_path = get_between_mask(king_sq, cs.king_to) | BitSet::from_one_square(cs.king_to);
+        let rook_path = get_between_mask(cs.rook_from, cs.rook_to) | BitSet::from_one_square(cs.rook_to);
+        if !((king_path | rook_path) & self.occupied_set & !castling_pieces).is_empty() {
+            return;
+        }
+        if self.legal {
+            //the rook is taken off the board, it may hide an attack on the square where the king goes
+            let occupied = self.occupied_set & !castling_pieces;
+            let enemy_set = board.get_color_bitset(color.inverse());
+            let king_path = king_path | BitSet::from_one_square(king_sq);
+            if king_path.iter().any(|sq| !(board.attackers_to(sq, occupied) & enemy_set).is_empty()) {
+                return;
+            }
+        }
+        self.moves_cache.push(mv);
+    }
+}
+
+//returns friendly pieces that are the only blockers between the king and an enemy slider
+fn pinned_pieces(board: &Board, king_sq: Square, color: Color) -> BitSet {
+    let occupied_set = board.whites | board.blacks;
+    let enemy_set = board.get_color_bitset(color.inverse());
+    let empty = BitSet::empty();
+    //enemy sliders that would attack the king on an empty board
+    let snipers = ((gen_rook_moves(empty, empty, king_sq) & (board.rooks | board.queens))
+                 | (gen_bishop_moves(empty, empty, king_sq) & (board.bishops | board.queens)))
+                 & enemy_set;
+    let mut result = BitSet::empty();
+    for sniper_sq in snipers.iter() {
+        let blockers = ::tables::get_between_mask(king_sq, sniper_sq) & occupied_set;
+        if blockers.count() == 1 {
+            result = result | (blockers & board.get_color_bitset(color));
+        }
+    }
+    result
 }
 
 #[inline]
 fn is_legal_move(pos: &Position, mv: &Move) -> bool {
     let mut new_pos = *pos;
     new_pos.apply_move(mv);
-    let test_area = match (*mv, pos.next_to_move) {
-        (CastleQueenSide, White) => BitSet::new(0b00011100u64),
-        (CastleKingSide,  White) => BitSet::new(0b01110000u64),
-        (CastleQueenSide, Black) => BitSet::new(0b00011100u64) << 7 * 8,
-        (CastleKingSide,  Black) => BitSet::new(0b01110000u64) << 7 * 8,
-        (_, White)               => new_pos.board.kings & new_pos.board.whites,
-        (_, Black)               => new_pos.board.kings & new_pos.board.blacks,
+    let test_area = match *mv {
+        CastleKingSide | CastleQueenSide => {
+            let cs = pos.castling_squares(mv);
+            ::tables::get_between_mask(cs.king_from, cs.king_to)
+                | BitSet::from_one_square(cs.king_from) | BitSet::from_one_square(cs.king_to)
+        }
+        _ => new_pos.board.kings & new_pos.board.get_color_bitset(pos.next_to_move)
     };
     !is_under_attack(&new_pos.board, new_pos.next_to_move, test_area)
 }
@@ -270,7 +435,7 @@ fn squares_to_move(kind:Kind, from:Square, to:Square) -> Move {
     Move::new(kind, from, to, None)
 }
 
-fn gen_white_pawn_moves(occupied_set:BitSet, enemy_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_white_pawn_moves(occupied_set:BitSet, enemy_set:BitSet, sq:Square) -> BitSet {
     use tables::{get_white_pawn_moves_mask, get_white_pawn_attacks_mask};
     let free_set = if sq.rank() == 1 {
         //free_set is a set of squares a pawn can go to.
@@ -299,7 +464,7 @@ pub fn gen_black_pawn_moves(occupied_set:BitSet, enemy_set:BitSet, sq:Square) ->
 
 //generate rook moves on a given board from a given square
 //we don't check that rook is acutally there
-fn gen_rook_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_rook_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
     let rank_move_set = gen_rank_sliding_moves(occupied_set, sq);
     let file_move_set = gen_file_sliding_moves(occupied_set, sq);
 
@@ -308,7 +473,7 @@ fn gen_rook_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet
 
 //generate bishop moves on a given board from a given square
 //we don't check that bishop is acutally there
-fn gen_bishop_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_bishop_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
     let diag_move_set     = gen_diagonal_sliding_moves(occupied_set, sq);
     let antidiag_move_set = gen_antidiagonal_sliding_moves(occupied_set, sq);
 
@@ -317,7 +482,7 @@ fn gen_bishop_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitS
 
 //generate queen moves on a given board from a given square
 //we don't check that queen is acutally there
-fn gen_queen_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_queen_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSet {
     let diag_move_set     = gen_diagonal_sliding_moves(occupied_set, sq);
     let antidiag_move_set = gen_antidiagonal_sliding_moves(occupied_set, sq);
     let rank_move_set     = gen_rank_sliding_moves(occupied_set, sq);
@@ -327,13 +492,13 @@ fn gen_queen_moves(occupied_set:BitSet, friendly_set:BitSet, sq:Square) -> BitSe
         & !friendly_set
 }
 
-fn gen_king_moves(friendly_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_king_moves(friendly_set:BitSet, sq:Square) -> BitSet {
     use tables::get_king_moves_mask;
     let raw_moves = get_king_moves_mask(sq);
     raw_moves & !friendly_set
 }
 
-fn gen_knight_moves(friendly_set:BitSet, sq:Square) -> BitSet {
+pub fn gen_knight_moves(friendly_set:BitSet, sq:Square) -> BitSet {
     use tables::get_knight_moves_mask;
     let raw_moves = get_knight_moves_mask(sq);
     raw_moves & !friendly_set
@@ -389,7 +554,7 @@ fn gen_antidiagonal_sliding_moves(occupied_set: BitSet, sq:Square) -> BitSet {
 mod tests {
 use fen::parse_fen;
 use types::*;
-use move_gen::MovesIterator;
+use move_gen::{MovesIterator, GenMode, Captures, Quiets, Evasions};
 use squares::*;
 
 fn from_square(sq:Square, it:MovesIterator) -> Vec<Move>{
@@ -516,6 +681,28 @@ fn king_moves_test() {
     assert_castles(fen, &[CastleKingSide]);
 }
 
+#[test]
+fn chess960_castling_test() {
+    ::tables::init_tables();
+    let castles = |fen: &str| {
+        let mut moves: Vec<Move> = parse_fen(fen).unwrap().gen_moves()
+            .filter(|m| *m == CastleKingSide || *m == CastleQueenSide)
+            .collect();
+        moves.sort();
+        moves
+    };
+    //the king goes to c1 from the b file
+    assert_eq!(castles("4k3/8/8/8/8/8/8/RK5R w HA - 0 1"), vec![CastleKingSide, CastleQueenSide]);
+    //the king stays on g1, only the rook moves
+    assert_eq!(castles("4k3/8/8/8/8/8/8/6KR w H - 0 1"), vec![CastleKingSide]);
+    //the knight is on the square where the rook goes
+    assert_eq!(castles("4k3/8/8/8/8/8/8/RK1N4 w A - 0 1"), vec![]);
+    //the rook hides the attack of the queen on the king
+    assert_eq!(castles("4k3/8/8/8/8/8/8/qRK5 w B - 0 1"), vec![]);
+    //the attack on f1 doesn't matter when the king goes to c1 through d1
+    assert_eq!(castles("5r2/4k3/8/8/8/8/8/1R2K3 w B - 0 1"), vec![CastleQueenSide]);
+}
+
 #[test]
 fn pawn_moves_test() {
     ::tables::init_tables();
@@ -543,4 +730,68 @@ fn pawn_moves_test() {
     assert_squares(fen, g7, &[f6, h6, g6, g5]);
 }
 
+#[test]
+fn staged_moves_test() {
+    ::tables::init_tables();
+    let mode_moves = |fen:&str, mode:GenMode| {
+        let pos = parse_fen(fen).unwrap();
+        let mut moves:Vec<Move> = pos.gen_moves_with_mode(mode).collect();
+        moves.sort();
+        moves
+    };
+
+    let fen = "4k3/1P6/8/8/8/8/5r2/R3K3 w Q - 0 1";
+    let mut expected = vec![
+        Move::new(King, e1, f2, None),
+        Move::new(Pawn, b7, b8, Some(Queen)),
+        Move::new(Pawn, b7, b8, Some(Rook)),
+        Move::new(Pawn, b7, b8, Some(Bishop)),
+        Move::new(Pawn, b7, b8, Some(Knight))
+    ];
+    expected.sort();
+    assert_eq!(mode_moves(fen, Captures), expected);
+    assert!(mode_moves(fen, Quiets).contains(&CastleQueenSide));
+    assert!(!mode_moves(fen, Quiets).contains(&Move::new(King, e1, f2, None)));
+
+    //black pawn gives check after a double step, it can be taken en passant
+    let fen = "8/8/8/3pP3/4K3/8/8/7k w - d6 0 1";
+    let evasions = mode_moves(fen, Evasions);
+    assert!(evasions.contains(&Move::new(Pawn, e5, d6, None)));
+    assert!(evasions.contains(&Move::new(King, e4, d5, None)));
+    assert!(!evasions.contains(&Move::new(Pawn, e5, e6, None)));
+
+    //double check, only king moves are possible
+    let fen = "4k3/8/8/8/8/5n2/8/R3K1r1 w - - 0 1";
+    let evasions = mode_moves(fen, Evasions);
+    assert!(evasions.iter().all(|m| match *m { OrdinaryMove(mi) => mi.kind == King, _ => false }));
+    assert_eq!(evasions, vec![Move::new(King, e1, e2, None), Move::new(King, e1, f2, None)]);
+}
+
+#[test]
+fn legal_moves_test() {
+    ::tables::init_tables();
+    let legal_moves = |fen:&str| {
+        let pos = parse_fen(fen).unwrap();
+        let moves:Vec<Move> = pos.gen_moves().collect();
+        moves
+    };
+
+    //en passant capture removes both pawns from the rank and exposes the king
+    let moves = legal_moves("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1");
+    assert!(!moves.contains(&Move::new(Pawn, e5, d6, None)));
+    assert!(moves.contains(&Move::new(Pawn, e5, e6, None)));
+
+    //pinned pieces can move only along the pin line
+    let moves = legal_moves("4r2k/8/8/q7/4R3/8/3N4/4K3 w - - 0 1");
+    assert!(moves.contains(&Move::new(Rook, e4, e8, None)));
+    assert!(moves.contains(&Move::new(Rook, e4, e2, None)));
+    assert!(!moves.contains(&Move::new(Rook, e4, a4, None)));
+    assert!(!moves.iter().any(|m| match *m { OrdinaryMove(mi) => mi.from == d2, _ => false }));
+
+    //king can't step back along the line of a checking slider
+    let moves = legal_moves("4r2k/8/8/8/8/8/8/4K3 w - - 0 1");
+    assert!(!moves.contains(&Move::new(King, e1, e2, None)));
+    assert_eq!(moves.len(), 4);
+}
+
 }
@@ -0,0 +1,402 @@
+//Move ordering
+//Alpha-beta prunes more when good moves are searched first, so moves are tried in stages:
+//1. hash move (best move from the transposition table)
+//2. good captures and promotions sorted by MVV-LVA (most valuable victim, least valuable attacker)
+//3. killer moves: quiet moves that caused a beta cutoff at the same ply
+//4. counter-move: quiet move that refuted the previous move last time
+//5. other quiet moves sorted by butterfly history
+//6. captures that lose material according to SEE
+//More: https://www.chessprogramming.org/Move_Ordering
+use eval::Score;
+use move_gen::{GenMode, AllMoves, Captures, Quiets, Evasions};
+use see::{see_ge, see_value};
+use types::*;
+
+pub const MAX_PLY: usize = 128;
+const MAX_HISTORY: i32 = 16384;
+
+//Statistics gathered during search that help to order quiet moves
+pub struct OrderingTables {
+    killers: [[Move; 2]; MAX_PLY],
+    //butterfly history indexed by [color][from][to]
+    history: [[[i32; 64]; 64]; 2],
+    //counter-moves indexed by [piece of the previous move][target square of the previous move]
+    counter_moves: [[Move; 64]; 12]
+}
+
+impl OrderingTables {
+    pub fn new() -> OrderingTables {
+        OrderingTables {
+            killers: [[NullMove; 2]; MAX_PLY],
+            history: [[[0; 64]; 64]; 2],
+            counter_moves: [[NullMove; 64]; 12]
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = OrderingTables::new();
+    }
+
+    #[inline]
+    pub fn killers(&self, ply: usize) -> [Move; 2] {
+        self.killers[ply]
+    }
+
+    pub fn history(&self, color: Color, mv: &Move) -> i32 {
+        match *mv {
+            OrdinaryMove(ref mi) =>
+                self.history[color as usize][mi.from.file_and_rank() as usize][mi.to.file_and_rank() as usize],
+            _ => 0
+        }
+    }
+
+    //prev_move was made by the opponent of color
+    pub fn counter_move(&self, color: Color, prev_move: &Move) -> Move {
+        match *prev_move {
+            OrdinaryMove(ref mi) =>
+                self.counter_moves[counter_index(color.inverse(), mi.kind)][mi.to.file_and_rank() as usize],
+            _ => NullMove
+        }
+    }
+
+    //Called when a quiet move caused a beta cutoff.
+    //quiets_tried are the quiet moves that were searched before it without success.
+    pub fn update_quiet_cutoff(&mut self, color: Color, mv: &Move, prev_move: &Move, quiets_tried: &[Move],
+                               ply: usize, depth: i32) {
+        if self.killers[ply][0] != *mv {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = *mv;
+        }
+
+        if let OrdinaryMove(ref pmi) = *prev_move {
+            self.counter_moves[counter_index(color.inverse(), pmi.kind)][pmi.to.file_and_rank() as usize] = *mv;
+        }
+
+        let bonus = ::std::cmp::min(depth * depth, 400);
+        self.update_history(color, mv, bonus);
+        for quiet in quiets_tried.iter() {
+            self.update_history(color, quiet, -bonus);
+        }
+    }
+
+    //values are kept within +/- MAX_HISTORY, big values move slower
+    fn update_history(&mut self, color: Color, mv: &Move, bonus: i32) {
+        if let OrdinaryMove(ref mi) = *mv {
+            let entry = &mut self.history[color as usize][mi.from.file_and_rank() as usize]
+                                         [mi.to.file_and_rank() as usize];
+            *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+        }
+    }
+}
+
+#[inline]
+fn counter_index(color: Color, kind: Kind) -> usize {
+    (color as usize) * 6 + kind as usize
+}
+
+//Captures are ordered by the value of the victim first and by the value of the attacker second
+pub fn mvv_lva(pos: &Position, mv: &Move) -> Score {
+    match *mv {
+        OrdinaryMove(ref mi) => {
+            let victim = match pos.board.get_piece(mi.to) {
+                Some(p) => see_value(p.kind()),
+                None if pos.is_capture(mv) => see_value(Pawn),
+                None => 0
+            };
+            let promotion = mi.promotion.map(see_value).unwrap_or(0);
+            (victim + promotion) * 16 - (mi.kind as Score)
+        }
+        _ => 0
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Stage {
+    HashMove,
+    GenCaptures,
+    GoodCaptures,
+    GenQuiets,
+    Killers,
+    CounterMove,
+    Quiets,
+    BadCaptures,
+    GenEvasions,
+    Evasions,
+    GenUnordered,
+    Unordered, //all moves in the move generator order
+    Done
+}
+
+//Returns moves of a position one by one in the order described above.
+//Moves are generated lazily: if the hash move causes a cutoff, nothing else is generated.
+pub struct MovePicker {
+    position: Position,
+    stage: Stage,
+    captures_only: bool,
+    hash_move: Move,
+    killers: [Move; 2],
+    counter_move: Move,
+    killer_index: usize,
+    //moves of the current stage with their scores, the best one is at the end
+    moves: Vec<(Move, i32)>,
+    quiets: Vec<Move>,
+    bad_captures: Vec<Move>
+}
+
+impl MovePicker {
+    //Picker for the main search. Killers and counter-move may be illegal in this position,
+    //they are returned only if the move generator produces them too.
+    pub fn new(pos: &Position, hash_move: Move, killers: [Move; 2], counter_move: Move) -> MovePicker {
+        let stage = if pos.is_check() {
+            Stage::GenEvasions
+        } else if hash_move != NullMove {
+            Stage::HashMove
+        } else {
+            Stage::GenCaptures
+        };
+        MovePicker {
+            position: *pos,
+            stage: stage,
+            captures_only: false,
+            hash_move: hash_move,
+            killers: killers,
+            counter_move: counter_move,
+            killer_index: 0,
+            moves: Vec::new(),
+            quiets: Vec::new(),
+            bad_captures: Vec::new()
+        }
+    }
+
+    //Picker for quiescence search: all captures and promotions, or all evasions when in check
+    pub fn captures(pos: &Position) -> MovePicker {
+        let mut picker = MovePicker::new(pos, NullMove, [NullMove; 2], NullMove);
+        picker.captures_only = true;
+        picker
+    }
+
+    //Picker that returns moves in the move generator order, used to measure the benefit of ordering
+    pub fn unordered(pos: &Position) -> MovePicker {
+        let mut picker = MovePicker::new(pos, NullMove, [NullMove; 2], NullMove);
+        picker.stage = Stage::GenUnordered;
+        picker
+    }
+
+    fn gen_moves(&self, mode: GenMode) -> Vec<Move> {
+        self.position.gen_moves_with_mode(mode).collect()
+    }
+
+    //removes the best scored move of the current stage
+    #[inline]
+    fn pop_best(&mut self) -> Option<Move> {
+        self.moves.pop().map(|(m, _)| m)
+    }
+
+    fn sort_moves(&mut self) {
+        self.moves.sort_by_key(|&(_, score)| score);
+    }
+
+    fn take_quiet(&mut self, mv: Move) -> bool {
+        if mv == NullMove || mv == self.hash_move {
+            return false;
+        }
+        match self.quiets.iter().position(|&m| m == mv) {
+            Some(i) => {
+                self.quiets.swap_remove(i);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn next(&mut self, tables: &OrderingTables) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::GenCaptures;
+                    let hash_move = self.hash_move;
+                    let mode = if self.position.is_quiet(&hash_move) { Quiets } else { Captures };
+                    //hash move can come from a different position with the same hash
+                    if self.position.gen_moves_with_mode(mode).any(|m| m == hash_move) {
+                        return Some(hash_move);
+                    }
+                    self.hash_move = NullMove;
+                }
+                Stage::GenCaptures => {
+                    let pos = self.position;
+                    let hash_move = self.hash_move;
+                    self.moves = self.gen_moves(Captures).into_iter()
+                        .filter(|m| *m != hash_move)
+                        .map(|m| (m, mvv_lva(&pos, &m)))
+                        .collect();
+                    self.sort_moves();
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    match self.pop_best() {
+                        Some(mv) => {
+                            if self.captures_only || see_ge(&self.position, &mv, 0) {
+                                return Some(mv);
+                            }
+                            self.bad_captures.push(mv);
+                        }
+                        None => {
+                            self.stage = if self.captures_only { Stage::Done } else { Stage::GenQuiets };
+                        }
+                    }
+                }
+                Stage::GenQuiets => {
+                    self.quiets = self.gen_moves(Quiets);
+                    self.stage = Stage::Killers;
+                }
+                Stage::Killers => {
+                    if self.killer_index >= 2 {
+                        self.stage = Stage::CounterMove;
+                        continue;
+                    }
+                    let killer = self.killers[self.killer_index];
+                    self.killer_index += 1;
+                    if self.take_quiet(killer) {
+                        return Some(killer);
+                    }
+                }
+                Stage::CounterMove => {
+                    self.stage = Stage::Quiets;
+                    let color = self.position.next_to_move;
+                    let counter_move = self.counter_move;
+                    let quiets = ::std::mem::take(&mut self.quiets);
+                    self.moves = quiets.into_iter()
+                        .filter(|m| *m != self.hash_move)
+                        .map(|m| (m, tables.history(color, &m)))
+                        .collect();
+                    self.sort_moves();
+                    if counter_move != NullMove && counter_move != self.hash_move
+                            && !self.killers.contains(&counter_move) {
+                        if let Some(i) = self.moves.iter().position(|&(m, _)| m == counter_move) {
+                            self.moves.remove(i);
+                            return Some(counter_move);
+                        }
+                    }
+                }
+                Stage::Quiets => {
+                    match self.pop_best() {
+                        Some(mv) => return Some(mv),
+                        None => {
+                            self.bad_captures.reverse();
+                            self.stage = Stage::BadCaptures;
+                        }
+                    }
+                }
+                Stage::BadCaptures => {
+                    match self.bad_captures.pop() {
+                        Some(mv) => return Some(mv),
+                        None => self.stage = Stage::Done
+                    }
+                }
+                Stage::GenEvasions => {
+                    let pos = self.position;
+                    let hash_move = self.hash_move;
+                    let color = pos.next_to_move;
+                    //captures of the checking piece go first, then other moves by history
+                    self.moves = self.gen_moves(Evasions).into_iter()
+                        .map(|m| {
+                            let score = if m == hash_move {
+                                i32::MAX
+                            } else if pos.is_quiet(&m) {
+                                tables.history(color, &m) - 2 * MAX_HISTORY
+                            } else {
+                                mvv_lva(&pos, &m)
+                            };
+                            (m, score)
+                        })
+                        .collect();
+                    self.sort_moves();
+                    self.stage = Stage::Evasions;
+                }
+                Stage::Evasions => {
+                    match self.pop_best() {
+                        Some(mv) => return Some(mv),
+                        None => self.stage = Stage::Done
+                    }
+                }
+                Stage::GenUnordered => {
+                    //moves are taken from the end
+                    self.moves = self.gen_moves(AllMoves).into_iter().rev().map(|m| (m, 0)).collect();
+                    self.stage = Stage::Unordered;
+                }
+                Stage::Unordered => {
+                    match self.moves.pop() {
+                        Some((mv, _)) => return Some(mv),
+                        None => self.stage = Stage::Done
+                    }
+                }
+                Stage::Done => return None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use fen::parse_fen;
+use move_order::*;
+use squares::*;
+
+fn picked_moves(picker: &mut MovePicker, tables: &OrderingTables) -> Vec<Move> {
+    let mut result = Vec::new();
+    while let Some(mv) = picker.next(tables) {
+        result.push(mv);
+    }
+    result
+}
+
+#[test]
+fn move_picker_test() {
+    ::tables::init_tables();
+    let tables = OrderingTables::new();
+    let fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "4k3/8/8/8/8/5n2/8/R3K1r1 w - - 0 1"
+    ];
+    for &fen in fens.iter() {
+        let pos = parse_fen(fen).unwrap();
+        let mut expected: Vec<Move> = pos.gen_moves().collect();
+        expected.sort();
+        //some hash and killer moves are illegal and must be ignored
+        for &hash_move in [NullMove, expected[0], Move::new(Queen, a1, h8, None)].iter() {
+            let killers = [expected[expected.len() - 1], Move::new(Knight, b1, b2, None)];
+            let mut picker = MovePicker::new(&pos, hash_move, killers, expected[1]);
+            let mut moves = picked_moves(&mut picker, &tables);
+            if hash_move != NullMove && expected.contains(&hash_move) {
+                assert_eq!(moves[0], hash_move);
+            }
+            moves.sort();
+            assert_eq!(moves, expected, "fen: {}", fen);
+        }
+        let mut picker = MovePicker::unordered(&pos);
+        assert_eq!(picked_moves(&mut picker, &tables), pos.gen_moves().collect::<Vec<Move>>(), "fen: {}", fen);
+    }
+}
+
+#[test]
+fn captures_order_test() {
+    ::tables::init_tables();
+    let tables = OrderingTables::new();
+    //queen can be taken by a pawn, rook by the rook or the queen, rook takes a defended pawn
+    let pos = parse_fen("4k3/8/2p5/1p1q4/2P5/1R1r4/8/3QK3 w - - 0 1").unwrap();
+    let mut picker = MovePicker::new(&pos, NullMove, [NullMove; 2], NullMove);
+    let moves = picked_moves(&mut picker, &tables);
+    assert_eq!(moves[0], Move::new(Pawn, c4, d5, None));
+    assert_eq!(moves[1], Move::new(Rook, b3, d3, None));
+    assert_eq!(moves[2], Move::new(Queen, d1, d3, None));
+    assert_eq!(moves[moves.len() - 1], Move::new(Rook, b3, b5, None));
+
+    let mut picker = MovePicker::captures(&pos);
+    let moves = picked_moves(&mut picker, &tables);
+    assert_eq!(moves.len(), 5);
+    assert_eq!(moves[0], Move::new(Pawn, c4, d5, None));
+}
+
+}
@@ -0,0 +1,441 @@
+//NNUE: efficiently updatable neural network evaluation
+//The first layer has an input for every piece on every square, seen from the side of both players.
+//A move changes only a few inputs, so the output of the first layer (the accumulator) is updated
+//by the search on every move instead of being computed again in every node.
+//More: https://www.chessprogramming.org/NNUE
+//
+//Network: 768 inputs per side -> N hidden neurons per side -> 1 output
+//Input of a piece seen from a side: color * 384 + kind * 64 + square
+//  color is 0 for the pieces of the side and 1 for the pieces of its opponent
+//  kind is in the order: pawn, bishop, knight, rook, queen, king
+//  square is a1 = 0, b1 = 1, .. h8 = 63, mirrored vertically for black, so black's a8 is a1
+//Both sides share the weights of the first layer. Hidden values are clipped to 0..1 (ClippedReLU),
+//the hidden layer of the side to move and then the one of its opponent are connected to the output.
+//Hidden values are summed in 32 bits, so the bias and 32 weights can't overflow whatever the file contains.
+//
+//File format, numbers are little endian:
+//  magic             4 bytes "RCNN"
+//  version           u32, always 1
+//  hidden size       u32, N from 1 to 2048
+//  scale             u32, centipawns per 1.0 of the output
+//  feature weights   768 * N i16, N weights of input 0 first, 1.0 = 127
+//  feature biases    N i16, 1.0 = 127
+//  output weights    2 * N i8, the side to move first, 1.0 = 64
+//  output bias       i32, 1.0 = 127 * 64
+//Evaluation = (output bias + sum of clipped hidden values * output weights) * scale / (127 * 64)
+//from the point of view of the side to move.
+use std::cmp::{max, min};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use types::*;
+use eval::{Evaluator, GameStage, Score, game_stage};
+
+pub const INPUTS: usize = 768;
+pub const MAX_HIDDEN_SIZE: usize = 2048;
+//quantization of hidden values and of output weights
+pub const QA: i32 = 127;
+pub const QB: i32 = 64;
+//evaluation is kept far from mate scores whatever the weights are
+static MAX_SCORE: Score = 50000;
+
+static MAGIC: &[u8; 4] = b"RCNN";
+static VERSION: u32 = 1;
+static HEADER_SIZE: usize = 16;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Network {
+    pub hidden_size: usize,
+    pub scale: i32,
+    //indexed by input * hidden_size + neuron
+    pub feature_weights: Vec<i16>,
+    pub feature_biases: Vec<i16>,
+    pub output_weights: Vec<i8>,
+    pub output_bias: i32
+}
+
+//hidden values before the activation for both sides and the board they were computed for
+#[derive(Clone)]
+struct Accumulator {
+    board: Board,
+    //indexed by the color of the side
+    values: [Vec<i32>; 2]
+}
+
+impl Accumulator {
+    fn new(hidden_size: usize) -> Accumulator {
+        Accumulator {
+            board: Board::empty(),
+            values: [vec![0; hidden_size], vec![0; hidden_size]]
+        }
+    }
+}
+
+impl Network {
+    //all weights are zero, every position is a draw
+    pub fn new(hidden_size: usize, scale: i32) -> Network {
+        Network {
+            hidden_size: hidden_size,
+            scale: scale,
+            feature_weights: vec![0; INPUTS * hidden_size],
+            feature_biases: vec![0; hidden_size],
+            output_weights: vec![0; 2 * hidden_size],
+            output_bias: 0
+        }
+    }
+
+    pub fn read(input: &mut dyn Read) -> Result<Network, String> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+            return Err("Not a network file".to_string());
+        }
+        let version = read_u32(&bytes[4..8]);
+        if version != VERSION {
+            return Err(format!("Unsupported network version {}", version));
+        }
+        let hidden_size = read_u32(&bytes[8..12]) as usize;
+        if hidden_size == 0 || hidden_size > MAX_HIDDEN_SIZE {
+            return Err(format!("Hidden size must be from 1 to {}, found {}", MAX_HIDDEN_SIZE, hidden_size));
+        }
+        let scale = read_u32(&bytes[12..16]) as i32;
+        let expected_size = HEADER_SIZE + 2 * INPUTS * hidden_size + 2 * hidden_size + 2 * hidden_size + 4;
+        if bytes.len() != expected_size {
+            return Err(format!("Network file must have {} bytes, found {}", expected_size, bytes.len()));
+        }
+
+        let mut network = Network::new(hidden_size, scale);
+        let (weights, rest) = bytes[HEADER_SIZE..].split_at(2 * INPUTS * hidden_size);
+        let (biases, rest) = rest.split_at(2 * hidden_size);
+        let (output_weights, output_bias) = rest.split_at(2 * hidden_size);
+        for (w, b) in network.feature_weights.iter_mut().zip(weights.chunks(2)) {
+            *w = i16::from_le_bytes([b[0], b[1]]);
+        }
+        for (w, b) in network.feature_biases.iter_mut().zip(biases.chunks(2)) {
+            *w = i16::from_le_bytes([b[0], b[1]]);
+        }
+        for (w, &b) in network.output_weights.iter_mut().zip(output_weights) {
+            *w = b as i8;
+        }
+        network.output_bias = read_u32(output_bias) as i32;
+        Ok(network)
+    }
+
+    pub fn write(&self, output: &mut dyn Write) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.scale as u32).to_le_bytes());
+        for w in self.feature_weights.iter().chain(self.feature_biases.iter()) {
+            bytes.extend_from_slice(&w.to_le_bytes());
+        }
+        bytes.extend(self.output_weights.iter().map(|&w| w as u8));
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        output.write_all(&bytes)
+    }
+
+    pub fn load(path: &str) -> Result<Network, String> {
+        File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| Network::read(&mut f))
+            .map_err(|e| format!("Can't read {}: {}", path, e))
+    }
+
+    fn feature_index(side: Color, Piece(kind, color): Piece, sq: Square) -> usize {
+        let relative_color = if color == side { 0 } else { 1 };
+        let relative_square = if side == White { sq.0 } else { sq.0 ^ 56 };
+        relative_color * 384 + kind as usize * 64 + relative_square as usize
+    }
+
+    fn weights(&self, index: usize) -> &[i16] {
+        &self.feature_weights[index * self.hidden_size..(index + 1) * self.hidden_size]
+    }
+
+    //computes the accumulator from scratch
+    fn refresh(&self, board: &Board, acc: &mut Accumulator) {
+        acc.board = *board;
+        for &side in [White, Black].iter() {
+            let values = &mut acc.values[side as usize];
+            for (v, &b) in values.iter_mut().zip(self.feature_biases.iter()) {
+                *v = b as i32;
+            }
+            for &color in [White, Black].iter() {
+                for &kind in [Pawn, Bishop, Knight, Rook, Queen, King].iter() {
+                    for sq in board.get_pieces(kind, color) {
+                        let weights = self.weights(Network::feature_index(side, Piece(kind, color), sq));
+                        for (v, &w) in values.iter_mut().zip(weights) {
+                            *v += w as i32;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //computes the accumulator of a board from the accumulator of the board before a move,
+    //only pieces that appeared or disappeared on a square are added or removed
+    fn update(&self, prev: &Accumulator, board: &Board, acc: &mut Accumulator) {
+        acc.board = *board;
+        for &side in [White, Black].iter() {
+            acc.values[side as usize].copy_from_slice(&prev.values[side as usize]);
+        }
+        for &color in [White, Black].iter() {
+            for &kind in [Pawn, Bishop, Knight, Rook, Queen, King].iter() {
+                let before = prev.board.get_piece_bitset(kind) & prev.board.get_color_bitset(color);
+                let after = board.get_piece_bitset(kind) & board.get_color_bitset(color);
+                for sq in (before & !after).iter() {
+                    for &side in [White, Black].iter() {
+                        let weights = self.weights(Network::feature_index(side, Piece(kind, color), sq));
+                        for (v, &w) in acc.values[side as usize].iter_mut().zip(weights) {
+                            *v -= w as i32;
+                        }
+                    }
+                }
+                for sq in (after & !before).iter() {
+                    for &side in [White, Black].iter() {
+                        let weights = self.weights(Network::feature_index(side, Piece(kind, color), sq));
+                        for (v, &w) in acc.values[side as usize].iter_mut().zip(weights) {
+                            *v += w as i32;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //evaluation from the point of view of the side to move
+    fn output(&self, acc: &Accumulator, side_to_move: Color) -> Score {
+        let mut sum: i64 = self.output_bias as i64;
+        let sides = [side_to_move, side_to_move.inverse()];
+        for (&side, weights) in sides.iter().zip(self.output_weights.chunks(self.hidden_size)) {
+            let side_sum: i32 = acc.values[side as usize].iter().zip(weights)
+                .map(|(&v, &w)| v.clamp(0, QA) * w as i32)
+                .sum();
+            sum += side_sum as i64;
+        }
+        let score = sum * self.scale as i64 / (QA * QB) as i64;
+        max(-MAX_SCORE as i64, min(MAX_SCORE as i64, score)) as Score
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+//Every search thread has its own evaluator, the network is shared.
+pub struct NnueEvaluator {
+    network: Arc<Network>,
+    //accumulators of the positions from the search root to the current position
+    stack: Vec<Accumulator>,
+    //number of accumulators in use, the last one belongs to the current position
+    depth: usize
+}
+
+impl NnueEvaluator {
+    pub fn new(network: Arc<Network>) -> NnueEvaluator {
+        NnueEvaluator {
+            network: network,
+            stack: Vec::new(),
+            depth: 0
+        }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    fn push_accumulator(&mut self) {
+        if self.depth == self.stack.len() {
+            self.stack.push(Accumulator::new(self.network.hidden_size));
+        }
+        self.depth += 1;
+    }
+}
+
+impl Evaluator for NnueEvaluator {
+    //the current accumulator is used if it belongs to the position, otherwise it is computed from scratch
+    fn eval(&self, position: &Position) -> Score {
+        let score = match self.depth {
+            0 => None,
+            d => Some(&self.stack[d - 1])
+        }.filter(|acc| acc.board == position.board)
+            .map(|acc| self.network.output(acc, position.next_to_move))
+            .unwrap_or_else(|| {
+                let mut acc = Accumulator::new(self.network.hidden_size);
+                self.network.refresh(&position.board, &mut acc);
+                self.network.output(&acc, position.next_to_move)
+            });
+        if position.next_to_move == White { score } else { -score }
+    }
+
+    fn classify(&self, position: &Position) -> GameStage {
+        game_stage(position)
+    }
+
+    fn set_root(&mut self, position: &Position) {
+        self.depth = 0;
+        self.push_accumulator();
+        self.network.refresh(&position.board, &mut self.stack[0]);
+    }
+
+    fn make_move(&mut self, before: &Position, after: &Position) {
+        self.push_accumulator();
+        let (done, rest) = self.stack.split_at_mut(self.depth - 1);
+        match done.last() {
+            Some(prev) if prev.board == before.board => self.network.update(prev, &after.board, &mut rest[0]),
+            _ => self.network.refresh(&after.board, &mut rest[0])
+        }
+    }
+
+    fn unmake_move(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use nnue::*;
+use fen::parse_fen;
+use search::SearchEngine;
+use rand::isaac::Isaac64Rng;
+use rand::{RngCore, SeedableRng};
+
+//number from low to high inclusive
+fn random(rng: &mut Isaac64Rng, low: i64, high: i64) -> i64 {
+    low + (rng.next_u64() % (high - low + 1) as u64) as i64
+}
+
+fn random_network(hidden_size: usize) -> Network {
+    let mut rng: Isaac64Rng = SeedableRng::from_seed([7; 32]);
+    let mut network = Network::new(hidden_size, 400);
+    for w in network.feature_weights.iter_mut() {
+        *w = random(&mut rng, -20, 20) as i16;
+    }
+    for w in network.feature_biases.iter_mut() {
+        *w = random(&mut rng, -50, 50) as i16;
+    }
+    for w in network.output_weights.iter_mut() {
+        *w = random(&mut rng, -30, 30) as i8;
+    }
+    network.output_bias = random(&mut rng, -1000, 1000) as i32;
+    network
+}
+
+#[test]
+fn file_format_test() {
+    let network = random_network(8);
+    let mut bytes = Vec::new();
+    network.write(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 16 + 768 * 8 * 2 + 8 * 2 + 2 * 8 + 4);
+    assert_eq!(&bytes[0..8], &[b'R', b'C', b'N', b'N', 1, 0, 0, 0]);
+    assert_eq!(Network::read(&mut &bytes[..]), Ok(network));
+
+    assert!(Network::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    assert!(Network::read(&mut &b"RCNX"[..]).is_err());
+    bytes[8] = 0;
+    assert!(Network::read(&mut &bytes[..]).is_err());
+}
+
+#[test]
+fn eval_test() {
+    ::tables::init_tables();
+    //one neuron counts the pawns of a side, the output is the difference of the counts in tenths of pawns
+    let mut network = Network::new(1, QA);
+    for sq in 0..64 {
+        network.feature_weights[Pawn as usize * 64 + sq] = 10;
+    }
+    network.output_weights = vec![QB as i8, -QB as i8];
+    let evaluator = NnueEvaluator::new(Arc::new(network));
+
+    let position = parse_fen("4k3/ppp1pppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1").unwrap();
+    assert_eq!(evaluator.eval(&position), 10);
+    let position = parse_fen("4k3/ppp1pppp/8/8/8/8/PPPPPPPP/4K3 b - - 0 1").unwrap();
+    assert_eq!(evaluator.eval(&position), 10);
+    let position = parse_fen("4k3/pppppppp/8/8/8/8/PPP5/4K3 b - - 0 1").unwrap();
+    assert_eq!(evaluator.eval(&position), -50);
+}
+
+#[test]
+fn incremental_update_test() {
+    ::tables::init_tables();
+    let network = Arc::new(random_network(16));
+    let mut evaluator = NnueEvaluator::new(network.clone());
+    let fresh = NnueEvaluator::new(network);
+    let mut rng: Isaac64Rng = SeedableRng::from_seed([3; 32]);
+    //castling, en passant and promotions
+    let fens = ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1"];
+    for fen in fens.iter() {
+        let root = parse_fen(fen).unwrap();
+        evaluator.set_root(&root);
+        let mut positions = vec![root];
+        for _ in 0..40 {
+            let pos = *positions.last().unwrap();
+            let moves: Vec<Move> = pos.gen_moves().collect();
+            if moves.is_empty() {
+                break;
+            }
+            let mut new_pos = pos;
+            new_pos.apply_move(&moves[random(&mut rng, 0, moves.len() as i64 - 1) as usize]);
+            evaluator.make_move(&pos, &new_pos);
+            let mut acc = Accumulator::new(16);
+            evaluator.network().refresh(&new_pos.board, &mut acc);
+            assert_eq!(evaluator.stack[evaluator.depth - 1].values, acc.values, "{}", fen);
+            assert_eq!(evaluator.eval(&new_pos), fresh.eval(&new_pos), "{}", fen);
+            positions.push(new_pos);
+        }
+        while positions.len() > 1 {
+            positions.pop();
+            evaluator.unmake_move();
+            let pos = positions.last().unwrap();
+            assert_eq!(evaluator.eval(pos), fresh.eval(pos), "{}", fen);
+        }
+    }
+}
+
+#[test]
+fn large_weights_test() {
+    ::tables::init_tables();
+    //biases and weights at the limits of i16 add up far beyond it with 32 pieces on the board
+    let mut network = Network::new(2, QA);
+    for (i, w) in network.feature_weights.iter_mut().enumerate() {
+        *w = if i % 2 == 0 { i16::MAX } else { i16::MIN };
+    }
+    network.feature_biases = vec![i16::MAX, i16::MIN];
+    network.output_weights = vec![QB as i8, QB as i8, -QB as i8, -QB as i8];
+    let mut bytes = Vec::new();
+    network.write(&mut bytes).unwrap();
+    let network = Arc::new(Network::read(&mut &bytes[..]).unwrap());
+
+    let mut evaluator = NnueEvaluator::new(network.clone());
+    let root = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    evaluator.set_root(&root);
+    assert_eq!(evaluator.stack[0].values[White as usize], vec![33 * i16::MAX as i32, 33 * i16::MIN as i32]);
+    assert_eq!(evaluator.eval(&root), 0);
+    for mv in root.gen_moves() {
+        let mut pos = root;
+        pos.apply_move(&mv);
+        evaluator.make_move(&root, &pos);
+        assert_eq!(evaluator.eval(&pos), NnueEvaluator::new(network.clone()).eval(&pos));
+        evaluator.unmake_move();
+    }
+
+    let mut engine = SearchEngine::new();
+    engine.set_network(Some(network));
+    assert!(engine.search(&root, 3).best_move.is_some());
+}
+
+#[test]
+fn search_test() {
+    ::tables::init_tables();
+    let mut engine = SearchEngine::new();
+    engine.set_network(Some(Arc::new(random_network(16))));
+    let pos = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    let result = engine.search(&pos, 4);
+    assert!(result.best_move.is_some());
+    assert_eq!(result.depth, 4);
+}
+
+}
@@ -0,0 +1,267 @@
+//Pawn structure evaluation
+//Terms that depend only on pawns are cached in a pawn hash table keyed by the pawn hash,
+//because the pawn structure changes rarely during the search and most probes are hits.
+//Passed pawns are also stored in the table, their bonuses that depend on other pieces
+//(free path and king proximity) are computed on every evaluation.
+//More: https://www.chessprogramming.org/Pawn_Structure
+//      https://www.chessprogramming.org/Pawn_Hash_Table
+use std::cell::Cell;
+use bitset::BitSet;
+use eval::{Score, TaperedScore};
+use eval_params::EvalParams;
+use hash::calc_pawn_hash;
+use trace::{Trace, NoTrace};
+use types::*;
+
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+const FILE_H: u64 = FILE_A << 7;
+
+//number of entries in the pawn hash table, every evaluator has its own table
+const PAWN_HASH_SIZE: usize = 16384;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct PawnEntry {
+    pub key: u64,
+    pub score: TaperedScore, //from the white point of view
+    pub passed: [BitSet; 2]  //passed pawns indexed by color
+}
+
+//Table is not shared between threads, so a Cell is enough.
+//Entries are valid only for the parameters they were computed with, the evaluator never changes them.
+//Empty entries have key 0, which is also the key of a position without pawns,
+//and the empty entry is the correct result for such position.
+#[derive(Clone)]
+pub struct PawnHashTable {
+    entries: Vec<Cell<PawnEntry>>
+}
+
+impl PawnHashTable {
+    pub fn new() -> PawnHashTable {
+        let empty = PawnEntry { key: 0, score: TaperedScore::default(), passed: [BitSet::empty(); 2] };
+        PawnHashTable { entries: vec![Cell::new(empty); PAWN_HASH_SIZE] }
+    }
+
+    pub fn probe(&self, position: &Position, params: &EvalParams) -> PawnEntry {
+        let key = calc_pawn_hash(position);
+        let cell = &self.entries[(key % PAWN_HASH_SIZE as u64) as usize];
+        let entry = cell.get();
+        if entry.key == key {
+            return entry;
+        }
+        let entry = eval_pawn_structure(&position.board, key, params, &mut NoTrace);
+        cell.set(entry);
+        entry
+    }
+}
+
+//rank counted from the pawn's side, 1 for pawns on their initial squares
+#[inline]
+pub fn relative_rank(sq: Square, color: Color) -> usize {
+    match color {
+        White => sq.rank() as usize,
+        Black => 7 - sq.rank() as usize
+    }
+}
+
+//all squares on the ranks in front of the square, from the point of view of color
+#[inline]
+pub fn forward_ranks(sq: Square, color: Color) -> u64 {
+    let rank = sq.rank() as u64;
+    match color {
+        White => if rank == 7 { 0 } else { !0u64 << (8 * (rank + 1)) },
+        Black => (1u64 << (8 * rank)) - 1
+    }
+}
+
+#[inline]
+pub fn adjacent_files(sq: Square) -> u64 {
+    let file = FILE_A << sq.file();
+    ((file << 1) & !FILE_A) | ((file >> 1) & !FILE_H)
+}
+
+//squares attacked by pawns of the given color
+#[inline]
+pub fn pawn_attacks(pawns: u64, color: Color) -> u64 {
+    match color {
+        White => ((pawns << 7) & !FILE_H) | ((pawns << 9) & !FILE_A),
+        Black => ((pawns >> 9) & !FILE_H) | ((pawns >> 7) & !FILE_A)
+    }
+}
+
+#[inline]
+pub fn distance(a: Square, b: Square) -> Score {
+    let files = (a.file() as Score - b.file() as Score).abs();
+    let ranks = (a.rank() as Score - b.rank() as Score).abs();
+    ::std::cmp::max(files, ranks)
+}
+
+pub fn eval_pawn_structure<T: Trace>(board: &Board, key: u64, params: &EvalParams, trace: &mut T) -> PawnEntry {
+    let mut score = TaperedScore::default();
+    let mut passed = [BitSet::empty(); 2];
+    for &color in [White, Black].iter() {
+        let own = (board.pawns & board.get_color_bitset(color)).bits;
+        let enemy = (board.pawns & board.get_color_bitset(color.inverse())).bits;
+        let own_attacks = pawn_attacks(own, color);
+        let enemy_attacks = pawn_attacks(enemy, color.inverse());
+        let mut side = TaperedScore::default();
+        for sq in BitSet::new(own).iter() {
+            let bit = 1u64 << sq.file_and_rank();
+            let file = FILE_A << sq.file();
+            let adjacent = adjacent_files(sq);
+            let forward = forward_ranks(sq, color);
+
+            //only the rear pawn of doubled pawns is penalized
+            let doubled = own & file & forward != 0;
+            if doubled {
+                side += params.doubled_pawn;
+                trace.add(color, 1, |p| &mut p.doubled_pawn);
+            }
+            if own & adjacent == 0 {
+                side += params.isolated_pawn;
+                trace.add(color, 1, |p| &mut p.isolated_pawn);
+            } else if own & adjacent & !forward == 0 {
+                //no friendly pawn can support it and it can't advance safely
+                let stop = match color {
+                    White => bit << 8,
+                    Black => bit >> 8
+                };
+                if stop & enemy_attacks != 0 {
+                    side += params.backward_pawn;
+                    trace.add(color, 1, |p| &mut p.backward_pawn);
+                }
+            }
+            let rank = sq.rank() as u64;
+            if own & adjacent & (0xffu64 << (8 * rank)) != 0 {
+                side += params.connected_pawn;
+                trace.add(color, 1, |p| &mut p.connected_pawn);
+            }
+            if own_attacks & bit != 0 {
+                side += params.chained_pawn;
+                trace.add(color, 1, |p| &mut p.chained_pawn);
+            }
+            if !doubled && enemy & (file | adjacent) & forward == 0 {
+                passed[color as usize].set(sq, true);
+                let rank = relative_rank(sq, color);
+                side += params.passed_pawn[rank];
+                trace.add(color, 1, |p| &mut p.passed_pawn[rank]);
+            }
+        }
+        score += if color == White { side } else { -side };
+    }
+    PawnEntry { key: key, score: score, passed: passed }
+}
+
+//terms of passed pawns that depend on other pieces, from the white point of view
+pub fn eval_passed_pawns<T: Trace>(board: &Board, passed: &[BitSet; 2], params: &EvalParams, trace: &mut T) -> TaperedScore {
+    let mut score = TaperedScore::default();
+    let occupied = board.occupied().bits;
+    for &color in [White, Black].iter() {
+        let own_king = board.get_pieces(King, color).next();
+        let enemy_king = board.get_pieces(King, color.inverse()).next();
+        let mut side = TaperedScore::default();
+        for sq in passed[color as usize].iter() {
+            let factor = params.passed_pawn_rank_factor[relative_rank(sq, color)];
+            if factor == 0 {
+                continue;
+            }
+            let path = (FILE_A << sq.file()) & forward_ranks(sq, color);
+            if path & occupied == 0 {
+                side += params.free_passed_pawn * factor;
+                trace.add(color, factor, |p| &mut p.free_passed_pawn);
+            }
+            let stop = match color {
+                White => Square(sq.file_and_rank() + 8),
+                Black => Square(sq.file_and_rank() - 8)
+            };
+            if let (Some(own_king), Some(enemy_king)) = (own_king, enemy_king) {
+                let enemy_distance = distance(enemy_king, stop) * factor;
+                let own_distance = distance(own_king, stop) * factor;
+                side += TaperedScore::new(0, enemy_distance * params.passed_pawn_enemy_king_distance -
+                                             own_distance * params.passed_pawn_own_king_distance);
+                trace.add_eg(color, enemy_distance, |p| &mut p.passed_pawn_enemy_king_distance);
+                trace.add_eg(color, -own_distance, |p| &mut p.passed_pawn_own_king_distance);
+            }
+        }
+        score += if color == White { side } else { -side };
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+use pawns::*;
+use fen::parse_fen;
+
+fn structure(fen: &str) -> PawnEntry {
+    let position = parse_fen(fen).unwrap();
+    eval_pawn_structure(&position.board, calc_pawn_hash(&position), &EvalParams::default(), &mut NoTrace)
+}
+
+#[test]
+fn pawn_structure_terms_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    //pawns blocked by each other, so none of them is passed
+    let blocked = "4k3/8/4p3/4P3/8/8/8/4K3 w - - 0 1";
+    //an isolated pawn for each side
+    assert_eq!(structure(blocked).score, TaperedScore::default());
+    assert_eq!(structure(blocked).passed, [BitSet::empty(); 2]);
+
+    //doubled and isolated white pawns against one isolated pawn
+    let doubled = structure("4k3/8/4p3/4P3/4P3/8/8/4K3 w - - 0 1");
+    assert_eq!(doubled.score, p.doubled_pawn + p.isolated_pawn);
+
+    //d4 and e4 are connected, f3 defends e4, a7 and b7 are connected, all pawns are passed
+    let connected = structure("4k3/pp6/8/8/3PP3/5P2/8/4K3 w - - 0 1");
+    assert_eq!(connected.passed[White as usize].count(), 3);
+    assert_eq!(connected.passed[Black as usize].count(), 2);
+    let white = p.connected_pawn * 2 + p.chained_pawn + p.passed_pawn[3] * 2 + p.passed_pawn[2];
+    let black = p.connected_pawn * 2 + p.passed_pawn[1] * 2;
+    assert_eq!(connected.score, white - black);
+
+    //d2 can't be supported by c3 or e3 and its stop square d3 is attacked by the pawn on e4
+    let backward = structure("4k3/8/8/8/2P1p3/8/3P4/4K3 w - - 0 1");
+    //c4 is passed, d2 and e4 are not passed because they can capture each other, e4 is isolated
+    let white = p.backward_pawn + p.passed_pawn[3];
+    let black = p.isolated_pawn;
+    assert_eq!(backward.score, white - black);
+}
+
+#[test]
+fn passed_pawns_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    let position = parse_fen("8/3k4/8/1P6/8/8/8/6K1 w - - 0 1").unwrap();
+    let entry = eval_pawn_structure(&position.board, calc_pawn_hash(&position), &p, &mut NoTrace);
+    assert_eq!(entry.passed[White as usize].iter().collect::<Vec<_>>(), vec![Square::new(1, 4)]);
+    //free path, black king is 2 squares from b6 and white king is 5 squares from it
+    let factor = p.passed_pawn_rank_factor[4];
+    let proximity = 2 * p.passed_pawn_enemy_king_distance - 5 * p.passed_pawn_own_king_distance;
+    let expected = p.free_passed_pawn * factor + TaperedScore::new(0, proximity * factor);
+    assert_eq!(eval_passed_pawns(&position.board, &entry.passed, &p, &mut NoTrace), expected);
+
+    //blocked passed pawn with the enemy king in front of it is worth less
+    let blocked = parse_fen("8/8/1k6/1P6/8/8/8/6K1 w - - 0 1").unwrap();
+    assert!(eval_passed_pawns(&blocked.board, &entry.passed, &p, &mut NoTrace).eg < expected.eg);
+}
+
+#[test]
+fn pawn_hash_table_test() {
+    ::tables::init_tables();
+    let p = EvalParams::default();
+    let table = PawnHashTable::new();
+    let position = parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let entry = table.probe(&position, &p);
+    assert_eq!(entry, eval_pawn_structure(&position.board, calc_pawn_hash(&position), &p, &mut NoTrace));
+    assert_eq!(table.probe(&position, &p), entry);
+
+    //other pieces don't change the cached entry
+    let same_pawns = parse_fen("4k3/p1ppqp2/4p1p1/3P4/1p2P3/7p/PPP2PPP/4K3 b - - 0 1").unwrap();
+    assert_eq!(table.probe(&same_pawns, &p), entry);
+
+    //position without pawns
+    let no_pawns = parse_fen("4k3/8/8/8/8/8/8/4K2R w - - 0 1").unwrap();
+    assert_eq!(table.probe(&no_pawns, &p).score, TaperedScore::default());
+}
+
+}
@@ -0,0 +1,229 @@
+//Perft (performance test) walks the tree of legal moves and counts leaf nodes.
+//Its results can be compared with other engines to find bugs in the move generator.
+//More: https://www.chessprogramming.org/Perft
+use fen::parse_fen;
+use hash::calc_position_hash;
+use lockless::LocklessTable;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use types::*;
+use uci::{move_to_uci, UciMove};
+
+#[derive(Clone, Copy, Debug)]
+pub struct PerftOptions {
+    pub threads: usize,
+    pub hash_mb: usize, //0 means no hash table
+    pub bulk: bool      //count moves at depth 1 instead of making them
+}
+
+impl PerftOptions {
+    pub fn new() -> PerftOptions {
+        PerftOptions {
+            threads: 1,
+            hash_mb: 0,
+            bulk: true
+        }
+    }
+}
+
+//Lock-free hash table shared by all perft threads.
+pub struct PerftCache {
+    table: LocklessTable
+}
+
+impl PerftCache {
+    pub fn new(size_mb: usize) -> PerftCache {
+        PerftCache { table: LocklessTable::new(size_mb) }
+    }
+
+    //data word: node count in the upper 56 bits and depth in the lower 8 bits
+    pub fn get(&self, hash: u64, depth: usize) -> Option<u64> {
+        match self.table.load(hash) {
+            Some(data) if (data & 0xff) as usize == depth => Some(data >> 8),
+            _ => None
+        }
+    }
+
+    pub fn put(&self, hash: u64, depth: usize, count: u64) {
+        self.table.store(hash, (count << 8) | (depth as u64 & 0xff));
+    }
+}
+
+pub fn perft(p: &Position, depth: usize, bulk: bool, cache: Option<&PerftCache>) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = p.gen_moves();
+    if depth == 1 && bulk {
+        return moves.count() as u64;
+    }
+
+    let hash = match cache {
+        Some(c) if depth > 1 => {
+            let hash = calc_position_hash(p);
+            if let Some(count) = c.get(hash, depth) {
+                return count;
+            }
+            Some(hash)
+        }
+        _ => None
+    };
+
+    let mut result = 0;
+    for mv in moves {
+        let mut p1 = *p;
+        p1.apply_move(&mv);
+        result += perft(&p1, depth - 1, bulk, cache);
+    }
+
+    if let (Some(c), Some(h)) = (cache, hash) {
+        c.put(h, depth, result);
+    }
+    result
+}
+
+//Counts leaf nodes separately for each root move.
+//Root moves are distributed between threads, and the order of the result follows move generator order.
+pub fn divide(p: &Position, depth: usize, options: &PerftOptions) -> Vec<(Move, u64)> {
+    assert!(depth > 0);
+    let root_moves: Arc<Vec<Move>> = Arc::new(p.gen_moves().collect());
+    let counts: Arc<Vec<AtomicU64>> = Arc::new(root_moves.iter().map(|_| AtomicU64::new(0)).collect());
+    let next_move = Arc::new(AtomicUsize::new(0));
+    let cache = if options.hash_mb > 0 {
+        Some(Arc::new(PerftCache::new(options.hash_mb)))
+    } else {
+        None
+    };
+
+    let mut workers = Vec::with_capacity(options.threads);
+    for _ in 0..::std::cmp::max(1, options.threads) {
+        let root_moves = root_moves.clone();
+        let counts = counts.clone();
+        let next_move = next_move.clone();
+        let cache = cache.clone();
+        let position = *p;
+        let bulk = options.bulk;
+        workers.push(thread::spawn(move || loop {
+            let i = next_move.fetch_add(1, Ordering::Relaxed);
+            if i >= root_moves.len() {
+                break;
+            }
+            let mut p1 = position;
+            p1.apply_move(&root_moves[i]);
+            let count = perft(&p1, depth - 1, bulk, cache.as_deref());
+            counts[i].store(count, Ordering::Relaxed);
+        }));
+    }
+    for w in workers {
+        w.join().expect("perft thread panicked");
+    }
+
+    root_moves.iter().zip(counts.iter()).map(|(mv, c)| (*mv, c.load(Ordering::Relaxed))).collect()
+}
+
+//Entry point for: rchess perft <fen|startpos> <depth> [--threads N] [--hash MB] [--no-bulk] [--chess960]
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    let usage = "usage: rchess perft <fen|startpos> <depth> [--threads N] [--hash MB] [--no-bulk] [--chess960]";
+    if args.len() < 2 {
+        return Err(usage.to_string());
+    }
+    let position = if args[0] == "startpos" {
+        parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")?
+    } else {
+        parse_fen(&args[0])?
+    };
+    let depth: usize = match args[1].parse() {
+        Ok(d) if d > 0 => d,
+        _ => return Err(format!("Invalid perft depth: {}", args[1]))
+    };
+
+    let mut options = PerftOptions::new();
+    //castling moves are printed as king takes rook
+    let mut chess960 = false;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--threads" | "--hash" => {
+                let value: usize = match args.get(i + 1).map(|v| v.parse()) {
+                    Some(Ok(v)) => v,
+                    _ => return Err(format!("{} expects a number", args[i]))
+                };
+                if args[i] == "--threads" {
+                    options.threads = value;
+                } else {
+                    options.hash_mb = value;
+                }
+                i += 2;
+            }
+            "--no-bulk" => {
+                options.bulk = false;
+                i += 1;
+            }
+            "--chess960" => {
+                chess960 = true;
+                i += 1;
+            }
+            other => return Err(format!("Unexpected perft argument {}\n{}", other, usage))
+        }
+    }
+
+    let start = Instant::now();
+    let results = divide(&position, depth, &options);
+    let elapsed = start.elapsed();
+
+    let mut lines: Vec<(UciMove, u64)> = results.iter()
+        .map(|&(mv, count)| (move_to_uci(&mv, &position, chess960), count))
+        .collect();
+    lines.sort_by_key(|(mv, _)| mv.to_string());
+    let mut total = 0;
+    for &(mv, count) in lines.iter() {
+        println!("{}: {}", mv, count);
+        total += count;
+    }
+    let msc = elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64;
+    println!();
+    println!("Moves: {}", lines.len());
+    println!("Nodes: {}", total);
+    println!("Time: {} ms ({} knps)", msc, total / ::std::cmp::max(1, msc));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+use fen::parse_fen;
+use perft::*;
+
+#[test]
+fn divide_test() {
+    ::tables::init_tables();
+    let pos = parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+
+    let mut options = PerftOptions::new();
+    let results = divide(&pos, 3, &options);
+    assert_eq!(results.len(), 48);
+    assert_eq!(results.iter().map(|&(_, c)| c).sum::<u64>(), 97862);
+
+    options.threads = 4;
+    options.hash_mb = 1;
+    let hashed_results = divide(&pos, 3, &options);
+    assert_eq!(results, hashed_results);
+
+    options.bulk = false;
+    let no_bulk_results = divide(&pos, 3, &options);
+    assert_eq!(results, no_bulk_results);
+}
+
+#[test]
+fn hashed_perft_test() {
+    ::tables::init_tables();
+    let pos = parse_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+    let cache = PerftCache::new(1);
+    assert_eq!(perft(&pos, 5, true, Some(&cache)), 674624);
+    //second run is answered mostly from the cache
+    assert_eq!(perft(&pos, 5, true, Some(&cache)), 674624);
+    assert_eq!(perft(&pos, 4, false, None), 43238);
+}
+
+}
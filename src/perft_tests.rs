@@ -203,4 +203,78 @@ fn rocechess_suite() {
     assert_perft("n1n5/1Pk5/8/8/8/8/5Kp1/5N1N b - - 0 1", &[24, 421, 7421, 124608, 2193768, 37665329], depth);
     assert_perft("8/PPPk4/8/8/8/8/4Kppp/8 b - - 0 1", &[18, 270, 4699, 79355, 1533145, 28859283], depth);
     assert_perft("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", &[24, 496, 9483, 182838, 3605103, 71179139], depth);
-}
\ No newline at end of file
+}
+#[test]
+fn chess960_suite() {
+    //source: https://www.chessprogramming.org/Chess960_Perft_Results
+    ::tables::init_tables();
+    let depth = 5;
+
+    assert_perft("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189, 326672, 8146062], depth);
+    assert_perft("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002, 667366, 16253601], depth);
+    assert_perft("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", &[20, 479, 10471, 273318, 6417013], depth);
+    assert_perft("qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9", &[22, 593, 13440, 382958, 9183776], depth);
+    assert_perft("1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9", &[28, 1120, 31058, 1171749, 34030312], depth);
+    assert_perft("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9", &[29, 899, 26578, 824055, 24851983], depth);
+    assert_perft("q1bnrkr1/ppppp2p/2n2p2/4b1p1/2NP4/8/PPP1PPPP/QNB1RRKB w ge - 1 9", &[30, 860, 24566, 732757, 21093346], depth);
+    assert_perft("qbn1brkr/ppp1p1p1/2n4p/3p1p2/P7/6PP/QPPPPP2/1BNNBRKR w HFhf - 0 9", &[25, 635, 17054, 465806, 13203304], depth);
+    assert_perft("qn1rbbkr/ppp2p1p/1n1pp1p1/8/3P4/P6P/1PP1PPPK/QNNRBB1R w hd - 2 9", &[28, 811, 23175, 679699, 19836606], depth);
+    assert_perft("qnr1bkrb/pppp2pp/3np3/5p2/8/P2P2P1/NPP1PP1P/QN1RBKRB w GDg - 3 9", &[33, 823, 26895, 713420, 23114629], depth);
+    assert_perft("qb1nrkbr/1pppp1p1/1n3p2/p1B4p/8/3P1P1P/PPP1P1P1/QBNNRK1R w HEhe - 0 9", &[31, 855, 25620, 735703, 21796206], depth);
+    assert_perft("qnnbrk1r/1p1ppbpp/2p5/p4p2/2NP3P/8/PPP1PPP1/Q1NBRKBR w HEhe - 0 9", &[26, 790, 21238, 642367, 17819770], depth);
+    assert_perft("1qnrkbbr/1pppppp1/p1n4p/8/P7/1P1N1P2/2PPP1PP/QN1RKBBR w HDhd - 0 9", &[37, 883, 32187, 815535, 29370838], depth);
+    assert_perft("qn1rkrbb/pp1p1ppp/2p1p3/3n4/4P2P/2NP4/PPP2PP1/Q1NRKRBB w FDfd - 1 9", &[24, 585, 14769, 356950, 9482310], depth);
+    assert_perft("bb1qnrkr/pp1p1pp1/1np1p3/4N2p/8/1P4P1/P1PPPP1P/BBNQ1RKR w HFhf - 0 9", &[29, 864, 25747, 799727, 24219627], depth);
+    assert_perft("bnqbnr1r/p1p1ppkp/3p4/1p4p1/P7/3NP2P/1PPP1PP1/BNQB1RKR w HF - 0 9", &[26, 889, 24353, 832956, 23701014], depth);
+    assert_perft("b1qnrrkb/ppp1pp1p/n2p1Pp1/8/8/P7/1PPPP1PP/BNQNRKRB w GE - 0 9", &[20, 484, 10532, 281606, 6718715], depth);
+    assert_perft("n1bqnrkr/pp1ppp1p/2p5/6p1/2P2b2/PN6/1PNPPPPP/1BBQ1RKR w HFhf - 2 9", &[23, 732, 17746, 558191, 14481581], depth);
+}
+
+//walks the move tree and checks that staged generation modes produce exactly the legal moves
+fn assert_staged_moves(p: &Position, depth:usize) {
+    use move_gen::{Captures, Quiets, Evasions};
+    let sorted = |it: ::move_gen::LegalMovesIterator| {
+        let mut moves: Vec<Move> = it.collect();
+        moves.sort();
+        moves
+    };
+    let all_moves = sorted(p.gen_moves());
+    let captures = sorted(p.gen_moves_with_mode(Captures));
+    let quiets = sorted(p.gen_moves_with_mode(Quiets));
+
+    let mut union: Vec<Move> = captures.iter().chain(quiets.iter()).cloned().collect();
+    union.sort();
+    assert_eq!(all_moves.len(), captures.len() + quiets.len(), "captures and quiets overlap");
+    assert_eq!(all_moves, union, "captures and quiets don't match all moves");
+    if p.is_check() {
+        assert_eq!(all_moves, sorted(p.gen_moves_with_mode(Evasions)), "evasions don't match all moves");
+    }
+
+    if depth > 0 {
+        for mv in all_moves.iter() {
+            let mut p1 = *p;
+            p1.apply_move(mv);
+            assert_staged_moves(&p1, depth - 1);
+        }
+    }
+}
+
+#[test]
+fn staged_generation_suite() {
+    ::tables::init_tables();
+    let fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbqkb1r/pp1p1ppp/2p5/4P3/2B5/8/PPP1NnPP/RNBQK2R w KQkq - 0 6",
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        "3k4/3pp3/8/8/8/8/3PP3/3K4 w - - 0 1",
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9"
+    ];
+    for &fen in fens.iter() {
+        println!("testing: {}", fen);
+        assert_staged_moves(&parse_fen(fen).unwrap(), 3);
+    }
+}
@@ -1,159 +1,825 @@
+use std::cmp::{max, min};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use types::*;
 use eval::{SimpleEvaluator, Evaluator, INFINITY, Score};
-use std::cmp::{max,min};
-use std::collections::HashMap;
+use eval_params::EvalParams;
+use nnue::{Network, NnueEvaluator};
+use hash::calc_position_hash;
+use move_order::{MovePicker, OrderingTables, MAX_PLY};
+use see::see_ge;
+use tt::{TranspositionTable, TTEntry, Bound};
 
-type SearchCache = HashMap<u64, PositionInfo>;
+//Scores are from the point of view of the side to move (negamax).
+//Mate in N plies is MATE_SCORE - N, being mated in N plies is -MATE_SCORE + N.
+pub static MATE_SCORE: Score = 100000;
+pub static MATE_BOUND: Score = 100000 - MAX_PLY as Score;
 
-struct SearchEngine {
-    evaluator: Box<dyn Evaluator + 'static>,
-    search_cache: SearchCache
+static DEFAULT_HASH_MB: usize = 16;
+
+//Aspiration windows: iterations from this depth start with a window of this size around the previous score.
+//It is widened by half of its size after each failure, and dropped when it gets too wide.
+//More: https://www.chessprogramming.org/Aspiration_Windows
+static ASPIRATION_MIN_DEPTH: usize = 4;
+static ASPIRATION_WINDOW: Score = 25;
+static ASPIRATION_MAX_WINDOW: Score = 1000;
+
+//time is checked once per this number of nodes, must be a power of 2
+static TIME_CHECK_NODES: u64 = 1024;
+
+//time limits are converted to node limits with this speed in deterministic mode
+pub static DETERMINISTIC_NODES_PER_MS: u64 = 1000;
+
+//null move pruning parameters
+static NULL_MOVE_MIN_DEPTH: i32 = 2;
+//from this depth on a null move cutoff is confirmed by a reduced search without null moves
+static NULL_MOVE_VERIFICATION_DEPTH: i32 = 8;
+
+//late move reductions are applied from this depth and after this many moves
+static LMR_MIN_DEPTH: i32 = 3;
+static LMR_MIN_MOVES: usize = 3;
+
+//Singular extension is tried from this depth, for hash moves from entries at most 3 plies shallower.
+//A move is singular if all other moves fail low against the hash score minus margin * depth.
+static SINGULAR_MIN_DEPTH: i32 = 6;
+static SINGULAR_MARGIN: Score = 2;
+
+//pruning margins in centipawns
+static REVERSE_FUTILITY_MAX_DEPTH: i32 = 6;
+static REVERSE_FUTILITY_MARGIN: Score = 90;
+static FUTILITY_MAX_DEPTH: i32 = 2;
+static FUTILITY_MARGINS: [Score; 3] = [0, 200, 450];
+static RAZORING_MAX_DEPTH: i32 = 2;
+static RAZORING_MARGINS: [Score; 3] = [0, 300, 550];
+
+//Switches for search features, mostly useful for testing and benchmarking
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    pub move_ordering: bool,
+    pub null_move: bool,
+    //null move cutoffs at high depth are confirmed by a search without null moves
+    pub null_move_verification: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    pub razoring: bool,
+    pub check_extension: bool,
+    pub singular_extension: bool,
+    //quiescence search stands pat on an estimate when it is far outside the window
+    pub lazy_eval: bool,
+    pub threads: usize,
+    //Single thread, no time limits and hash cleared before every search,
+    //so the same search always gives the same result. Time is reported as 0.
+    pub deterministic: bool
 }
 
-struct Search {
-    position: Position,
-    top_line: Line
+impl SearchOptions {
+    pub fn new() -> SearchOptions {
+        SearchOptions {
+            move_ordering: true,
+            null_move: true,
+            null_move_verification: true,
+            late_move_reductions: true,
+            reverse_futility: true,
+            futility: true,
+            razoring: true,
+            check_extension: true,
+            singular_extension: true,
+            lazy_eval: true,
+            threads: 1,
+            deterministic: false
+        }
+    }
+}
+
+//Lazy SMP: all threads search the same position and share only the transposition table.
+//Helper threads start at different depths, so they fill the table with results
+//the main thread needs later. Threads stop when the main thread finishes.
+//More: https://www.chessprogramming.org/Lazy_SMP
+pub struct SearchEngine {
+    evaluator: Box<dyn Evaluator + Send>,
+    //helper threads get their own evaluators with the same parameters and network
+    eval_params: Arc<EvalParams>,
+    //the neural network evaluator is used instead of the classical one when set
+    network: Option<Arc<Network>>,
+    tt: Arc<TranspositionTable>,
+    ordering: Box<OrderingTables>,
+    pub options: SearchOptions,
+    nodes: u64,
+    root_best_move: Option<Move>,
+    //null moves are not tried before this ply, used by verification search
+    null_move_min_ply: usize,
+    //move excluded from the search at a ply, used to test if the hash move is singular
+    excluded_moves: [Move; MAX_PLY],
+    //side to move was in check at a ply, razoring is skipped after check evasions
+    checks: [bool; MAX_PLY],
+    root_depth: i32,
+    max_nodes: Option<u64>,
+    start_time: Instant,
+    hard_time: Option<Duration>,
+    //set when the hard time limit is reached or the search is stopped, all scores are invalid after that
+    stopped: bool,
+    //shared by all threads of one search
+    stop: Arc<AtomicBool>,
+    total_nodes: Arc<AtomicU64>
 }
 
-#[derive(Clone, Copy)]
-struct Window {
-    alpha: Score, //min score that whites can already count on
-    beta: Score   //max score that blacks can already count on
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: Score,
+    pub depth: usize,
+    pub nodes: u64,
+    pub time_ms: u64
 }
 
-struct Line {
-    mv: Move, //move, unfortunately 'move' is a keyword in rust
-    score: Score,
-    children: Vec<Line>
+//Limits of one search. A new iteration is not started after the soft time limit,
+//the search is aborted at the hard time limit or when the main thread has searched the given number of nodes.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub depth: usize,
+    pub nodes: Option<u64>,
+    pub soft_time: Option<Duration>,
+    pub hard_time: Option<Duration>
+}
+
+impl SearchLimits {
+    pub fn depth(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth: depth,
+            nodes: None,
+            soft_time: None,
+            hard_time: None
+        }
+    }
+
+    pub fn nodes(nodes: u64) -> SearchLimits {
+        SearchLimits {
+            depth: MAX_PLY - 1,
+            nodes: Some(nodes),
+            soft_time: None,
+            hard_time: None
+        }
+    }
+
+    pub fn movetime(time_ms: u64) -> SearchLimits {
+        SearchLimits {
+            depth: MAX_PLY - 1,
+            nodes: None,
+            soft_time: Some(Duration::from_millis(time_ms)),
+            hard_time: Some(Duration::from_millis(time_ms))
+        }
+    }
+
+    //Splits the remaining time evenly between the moves left to the next time control,
+    //30 moves are assumed when it is not known. Hard limit allows to spend extra time
+    //when the score drops, but never more than a third of the remaining time.
+    pub fn clock(time_left_ms: u64, increment_ms: u64, moves_to_go: Option<u64>) -> SearchLimits {
+        let moves_to_go = max(1, moves_to_go.unwrap_or(30));
+        let hard = time_left_ms / 3;
+        let soft = min(hard, time_left_ms / moves_to_go + increment_ms * 3 / 4);
+        SearchLimits {
+            depth: MAX_PLY - 1,
+            nodes: None,
+            soft_time: Some(Duration::from_millis(soft)),
+            hard_time: Some(Duration::from_millis(hard))
+        }
+    }
+
+    //same limits with the soft time limit converted to nodes, used in deterministic mode
+    pub fn without_time(&self) -> SearchLimits {
+        let nodes = match (self.nodes, self.soft_time) {
+            (Some(n), _) => Some(n),
+            (None, Some(t)) => Some(max(1, duration_ms(t) * DETERMINISTIC_NODES_PER_MS)),
+            (None, None) => None
+        };
+        SearchLimits {
+            depth: self.depth,
+            nodes: nodes,
+            soft_time: None,
+            hard_time: None
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
-struct PositionInfo {
-    depth: usize,
-    score: Score,
-    win: Window
+//Late moves are searched with reduced depth, because with good move ordering they rarely turn out best.
+//More: https://www.chessprogramming.org/Late_Move_Reductions
+#[inline]
+fn late_move_reduction(depth: i32, move_number: usize) -> i32 {
+    let r = 0.75 + (depth as f64).ln() * (move_number as f64).ln() / 2.25;
+    ::std::cmp::min(r as i32, depth - 2)
+}
+
+#[inline]
+fn score_to_tt(score: Score, ply: usize) -> Score {
+    //mate scores are stored relative to the position, not to the root
+    if score > MATE_BOUND {
+        score + ply as Score
+    } else if score < -MATE_BOUND {
+        score - ply as Score
+    } else {
+        score
+    }
+}
+
+#[inline]
+fn score_from_tt(score: Score, ply: usize) -> Score {
+    if score > MATE_BOUND {
+        score - ply as Score
+    } else if score < -MATE_BOUND {
+        score + ply as Score
+    } else {
+        score
+    }
 }
 
 impl SearchEngine {
     pub fn new() -> SearchEngine {
+        SearchEngine::with_shared_state(Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+                                        Arc::new(AtomicBool::new(false)), Arc::new(AtomicU64::new(0)))
+    }
+
+    fn with_shared_state(tt: Arc<TranspositionTable>, stop: Arc<AtomicBool>, total_nodes: Arc<AtomicU64>) -> SearchEngine {
         SearchEngine {
             evaluator: Box::new(SimpleEvaluator::new()),
-            search_cache: HashMap::new()
+            eval_params: Arc::new(EvalParams::default()),
+            network: None,
+            tt: tt,
+            ordering: Box::new(OrderingTables::new()),
+            options: SearchOptions::new(),
+            nodes: 0,
+            root_best_move: None,
+            null_move_min_ply: 0,
+            excluded_moves: [NullMove; MAX_PLY],
+            checks: [false; MAX_PLY],
+            root_depth: 0,
+            max_nodes: None,
+            start_time: Instant::now(),
+            hard_time: None,
+            stopped: false,
+            stop: stop,
+            total_nodes: total_nodes
         }
     }
-}
 
-impl Search {
-    pub fn new(pos: &Position) -> Search {
-        Search {
-            position: *pos,
-            top_line: Line {
-                mv:NullMove,
-                score: 0,
-                children: Vec::with_capacity(0),
-            },
+    //helper thread engine sharing the transposition table and stop flag with this one
+    fn helper(&self) -> SearchEngine {
+        let mut helper = SearchEngine::with_shared_state(self.tt.clone(), self.stop.clone(), self.total_nodes.clone());
+        helper.options = SearchOptions { threads: 1, ..self.options };
+        helper.eval_params = self.eval_params.clone();
+        helper.set_network(self.network.clone());
+        helper
+    }
+
+    pub fn set_eval_params(&mut self, params: Arc<EvalParams>) {
+        self.eval_params = params;
+        self.update_evaluator();
+    }
+
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+        self.update_evaluator();
+    }
+
+    fn update_evaluator(&mut self) {
+        self.evaluator = match self.network {
+            Some(ref network) => Box::new(NnueEvaluator::new(network.clone())),
+            None => Box::new(SimpleEvaluator::with_params((*self.eval_params).clone()))
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.ordering.clear();
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    pub fn search(&mut self, pos: &Position, depth: usize) -> SearchResult {
+        self.search_with_limits(pos, &SearchLimits::depth(depth), &mut |_| {})
+    }
+
+    //Info is called by the main thread after each completed iteration,
+    //node counts in it are summed over all threads.
+    //Result is taken from the thread with the deepest completed iteration.
+    pub fn search_with_limits(&mut self, pos: &Position, limits: &SearchLimits,
+                              info: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+        self.stop.store(false, Ordering::Relaxed);
+        self.total_nodes.store(0, Ordering::Relaxed);
+        //deterministic search uses only the main thread, options.threads is left for later searches
+        if self.options.deterministic {
+            self.clear();
+            return self.iterative_deepening(pos, &limits.without_time(), 0, info);
+        }
+        let helper_limits = SearchLimits { soft_time: None, ..*limits };
+        let mut helpers = Vec::new();
+        for id in 1..max(1, self.options.threads) {
+            let mut helper = self.helper();
+            let pos = *pos;
+            helpers.push(thread::spawn(move || {
+                //every other helper is one iteration ahead, so threads diverge
+                helper.iterative_deepening(&pos, &helper_limits, id % 2, &mut |_| {})
+            }));
         }
+
+        let mut result = self.iterative_deepening(pos, limits, 0, info);
+        self.stop.store(true, Ordering::Relaxed);
+        for h in helpers {
+            let helper_result = h.join().expect("search thread panicked");
+            if helper_result.depth > result.depth && helper_result.best_move.is_some() {
+                result = SearchResult { nodes: result.nodes, time_ms: result.time_ms, ..helper_result };
+            }
+        }
+        result.nodes = self.total_nodes.load(Ordering::Relaxed);
+        result.time_ms = self.elapsed_ms();
+        result
     }
 
+    //Iterative deepening: each iteration fills the transposition table and move ordering tables
+    //that make the next iteration faster.
+    fn iterative_deepening(&mut self, pos: &Position, limits: &SearchLimits, depth_offset: usize,
+                           info: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+        self.nodes = 0;
+        self.null_move_min_ply = 0;
+        self.start_time = Instant::now();
+        self.max_nodes = limits.nodes;
+        self.hard_time = limits.hard_time;
+        self.stopped = false;
+        self.root_best_move = None;
+        self.evaluator.set_root(pos);
+        let mut soft_time = limits.soft_time;
+        let mut result = SearchResult { best_move: None, score: 0, depth: 0, nodes: 0, time_ms: 0 };
+        for d in (1 + depth_offset)..limits.depth + 1 {
+            self.root_depth = d as i32;
+            let mut delta = ASPIRATION_WINDOW;
+            let (mut alpha, mut beta) = if d >= ASPIRATION_MIN_DEPTH && result.score.abs() < MATE_BOUND {
+                (result.score - delta, result.score + delta)
+            } else {
+                (-INFINITY, INFINITY)
+            };
+            let score = loop {
+                let score = self.alphabeta(pos, alpha, beta, d as i32, 0, NullMove);
+                if self.stopped {
+                    break score;
+                }
+                if score <= alpha {
+                    //fail low: the score is dropping, so more time is needed to find a better move
+                    beta = (alpha + beta) / 2;
+                    alpha = max(score - delta, -INFINITY);
+                    soft_time = soft_time.map(|t| extend_time(t, limits.hard_time));
+                } else if score >= beta {
+                    beta = min(score + delta, INFINITY);
+                } else {
+                    break score;
+                }
+                delta += delta / 2;
+                if delta > ASPIRATION_MAX_WINDOW {
+                    alpha = -INFINITY;
+                    beta = INFINITY;
+                }
+            };
+            if self.stopped {
+                //best move of an unfinished iteration can only be used if there wasn't one before
+                if result.best_move.is_none() {
+                    result.best_move = self.root_best_move;
+                }
+                break;
+            }
 
-    pub fn top_moves(&self, n:usize) -> Vec<Move> {
-        let mut result:Vec<Move> = Vec::with_capacity(n);
-        for i in 0..min(n, self.top_line.children.len()) {
-            result.push(self.top_line.children[i].mv);
+            result = SearchResult {
+                best_move: self.root_best_move,
+                score: score,
+                depth: d,
+                nodes: self.searched_nodes(),
+                time_ms: self.elapsed_ms()
+            };
+            info(&result);
+            if score.abs() > MATE_BOUND && d as Score >= MATE_SCORE - score.abs() {
+                //mate is found and proven by a search at least that deep
+                break;
+            }
+            if let Some(t) = soft_time {
+                if self.start_time.elapsed() >= t {
+                    break;
+                }
+            }
         }
+        //nodes not yet added to the total
+        self.total_nodes.fetch_add(self.nodes & (TIME_CHECK_NODES - 1), Ordering::Relaxed);
+        result.nodes = self.nodes;
+        result.time_ms = self.elapsed_ms();
         result
     }
 
-    pub fn calculate_lines(&mut self, search_engine: &mut SearchEngine, depth:usize) {
-        let window = Window { alpha: -INFINITY, beta:INFINITY };
-        self.top_line.score = alphabeta(search_engine, &self.position, &mut self.top_line, window, depth);
+    fn elapsed_ms(&self) -> u64 {
+        if self.options.deterministic { 0 } else { duration_ms(self.start_time.elapsed()) }
     }
 
-}
+    //nodes searched by all threads so far
+    fn searched_nodes(&self) -> u64 {
+        self.total_nodes.load(Ordering::Relaxed) + (self.nodes & (TIME_CHECK_NODES - 1))
+    }
 
-fn alphabeta(search_engine: &mut SearchEngine, pos: &Position, line: &mut Line, win: Window, depth: usize ) -> Score {
-    let very_bad_score = if pos.next_to_move == White { -INFINITY } else { INFINITY };
-    if line.children.is_empty() {
-        let moves = pos.gen_moves();
-        let (size, _) = moves.size_hint();
-        line.children.reserve(size);
-        for mv in moves {
-            line.children.push(Line {
-                mv: mv,
-                score: very_bad_score,
-                children: Vec::with_capacity(0)
-            });
+    //Called for every node, checks the node limit. The rest is done only once per TIME_CHECK_NODES nodes:
+    //they are added to the total and the time and the stop flag are checked.
+    #[inline]
+    fn check_time(&mut self) {
+        if let Some(n) = self.max_nodes {
+            if self.nodes >= n {
+                self.stopped = true;
+            }
         }
-    } else {
-        //Sort child moves in order of their decreasing benefit for the moving side.
-        //This sort is based on the score that was obtained on previous iterations.
-        //It should help us to do more pruning, since we look through better moves first.
+        if self.nodes & (TIME_CHECK_NODES - 1) != 0 {
+            return;
+        }
+        self.total_nodes.fetch_add(TIME_CHECK_NODES, Ordering::Relaxed);
+        if self.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        }
+        if let Some(t) = self.hard_time {
+            if self.start_time.elapsed() >= t {
+                self.stopped = true;
+            }
+        }
+    }
+
+    //static evaluation from the point of view of the side to move
+    #[inline]
+    fn evaluate(&self, pos: &Position) -> Score {
+        let score = self.evaluator.eval(pos);
+        if pos.next_to_move == White { score } else { -score }
+    }
+
+    //static evaluation that may be only an estimate when it is far outside the window
+    #[inline]
+    fn evaluate_lazy(&self, pos: &Position, alpha: Score, beta: Score) -> Score {
         if pos.next_to_move == White {
-            line.children.sort_by(|a, b| b.score.cmp(&a.score));
+            self.evaluator.eval_lazy(pos, alpha, beta)
         } else {
-            line.children.sort_by(|a, b| a.score.cmp(&b.score));
+            -self.evaluator.eval_lazy(pos, -beta, -alpha)
         }
     }
 
-    let mut window = Window { alpha: win.alpha, beta:win.beta };
-    for child in line.children.iter_mut() {
-        let mut new_pos = *pos;
-        new_pos.apply_move(&child.mv);
-        let hash = ::hash::calc_position_hash(&new_pos);
+    fn picker(&self, pos: &Position, hash_move: Move, ply: usize, prev_move: &Move) -> MovePicker {
+        if self.options.move_ordering {
+            let counter_move = self.ordering.counter_move(pos.next_to_move, prev_move);
+            MovePicker::new(pos, hash_move, self.ordering.killers(ply), counter_move)
+        } else {
+            MovePicker::unordered(pos)
+        }
+    }
 
-        //let pi = search_engine.search_cache.find(&hash);
-        //TODO: use pi value
+    fn alphabeta(&mut self, pos: &Position, mut alpha: Score, beta: Score, depth: i32, ply: usize,
+                 prev_move: Move) -> Score {
+        if depth <= 0 {
+            return self.quiescence(pos, alpha, beta, ply);
+        }
+        if self.stopped {
+            return 0;
+        }
+        self.nodes += 1;
+        self.check_time();
+        if self.stopped {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(pos);
+        }
 
-        let score = if depth == 0 {
-            search_engine.evaluator.eval(&new_pos)
-        } else {
-            alphabeta(search_engine, &new_pos, child, window, depth - 1)
+        let hash = calc_position_hash(pos);
+        let excluded_move = self.excluded_moves[ply];
+        let singular_search = excluded_move != NullMove;
+        let tt_entry = self.tt.probe(hash, pos);
+        let mut hash_move = NullMove;
+        if let Some(entry) = tt_entry {
+            hash_move = entry.mv;
+            if ply > 0 && !singular_search && entry.depth >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        let in_check = pos.is_check();
+        self.checks[ply] = in_check;
+        //the previous move escaped a check, quiet moves that continue the attack are likely to matter
+        let after_evasion = ply > 0 && self.checks[ply - 1];
+        let is_pv = beta > alpha + 1;
+        //static evaluation is meaningless in check, all evasions are searched
+        let static_eval = if in_check { -INFINITY } else { self.evaluate(pos) };
+        let no_mate_scores = alpha.abs() < MATE_BOUND && beta.abs() < MATE_BOUND;
+
+        if !is_pv && !in_check && ply > 0 && no_mate_scores && !singular_search {
+            //Reverse futility pruning: static evaluation is so far above beta
+            //that a quiet move is unlikely to bring it below.
+            //More: https://www.chessprogramming.org/Reverse_Futility_Pruning
+            if self.options.reverse_futility && depth <= REVERSE_FUTILITY_MAX_DEPTH
+                && static_eval - REVERSE_FUTILITY_MARGIN * depth >= beta {
+                return static_eval;
+            }
+
+            //Razoring: static evaluation is far below alpha, so only captures can save the position.
+            //More: https://www.chessprogramming.org/Razoring
+            if self.options.razoring && !after_evasion && depth <= RAZORING_MAX_DEPTH
+                && static_eval + RAZORING_MARGINS[depth as usize] < alpha {
+                let score = self.quiescence(pos, alpha, alpha + 1, ply);
+                if score <= alpha {
+                    return score;
+                }
+            }
+        }
+
+        if !in_check && !singular_search && self.null_move_allowed(pos, static_eval, beta, depth, ply, &prev_move) {
+            if let Some(score) = self.null_move_search(pos, beta, depth, ply) {
+                return score;
+            }
+        }
+
+        //Futility pruning: at frontier nodes quiet moves can't raise the score above alpha
+        //More: https://www.chessprogramming.org/Futility_Pruning
+        let futile = self.options.futility && !is_pv && !in_check && no_mate_scores
+            && depth <= FUTILITY_MAX_DEPTH && static_eval + FUTILITY_MARGINS[depth as usize] <= alpha;
+
+        let singular_move = match tt_entry {
+            Some(entry) if !singular_search && ply > 0 => self.singular_move(pos, &entry, depth, ply, prev_move),
+            _ => NullMove
         };
 
-        search_engine.search_cache.insert(hash, PositionInfo { score:score, depth: depth, win: window });
+        let original_alpha = alpha;
+        let color = pos.next_to_move;
+        let killers = self.ordering.killers(ply);
+        let mut picker = self.picker(pos, hash_move, ply, &prev_move);
+        let mut best_score = -INFINITY;
+        let mut best_move = NullMove;
+        let mut moves_searched = 0;
+        let mut quiets_tried: Vec<Move> = Vec::new();
+        while let Some(mv) = picker.next(&self.ordering) {
+            if mv == excluded_move {
+                continue;
+            }
+            let is_quiet = pos.is_quiet(&mv);
+            let mut new_pos = *pos;
+            new_pos.apply_move(&mv);
+            let gives_check = new_pos.is_check();
 
-        child.score = score;
-        if pos.next_to_move == White {
-            window.alpha = max(window.alpha, score);
+            //forcing moves are searched one ply deeper
+            let extension = if !self.extension_allowed(ply) {
+                0
+            } else if mv == singular_move || (self.options.check_extension && gives_check) {
+                1
+            } else {
+                0
+            };
+            let new_depth = depth - 1 + extension;
+
+            if futile && is_quiet && !gives_check && moves_searched > 0 {
+                if best_score < static_eval {
+                    best_score = static_eval;
+                }
+                continue;
+            }
+
+            //Principal variation search: the first move is searched with the full window,
+            //the rest with a null window to prove they are worse, re-searched if they are not.
+            self.evaluator.make_move(pos, &new_pos);
+            let score = if moves_searched == 0 {
+                -self.alphabeta(&new_pos, -beta, -alpha, new_depth, ply + 1, mv)
+            } else {
+                let reduction = if self.options.late_move_reductions && depth >= LMR_MIN_DEPTH
+                    && moves_searched >= LMR_MIN_MOVES && is_quiet && !in_check && !gives_check
+                    && mv != killers[0] && mv != killers[1] {
+                    late_move_reduction(depth, moves_searched)
+                } else {
+                    0
+                };
+                let mut score = -self.alphabeta(&new_pos, -alpha - 1, -alpha, new_depth - reduction, ply + 1, mv);
+                if score > alpha && reduction > 0 {
+                    score = -self.alphabeta(&new_pos, -alpha - 1, -alpha, new_depth, ply + 1, mv);
+                }
+                if score > alpha && score < beta {
+                    score = -self.alphabeta(&new_pos, -beta, -alpha, new_depth, ply + 1, mv);
+                }
+                score
+            };
+            self.evaluator.unmake_move();
+            moves_searched += 1;
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = mv;
+                if score > alpha {
+                    if ply == 0 {
+                        //moves that fail low at the root are not better than the previous best one
+                        self.root_best_move = Some(mv);
+                    }
+                    alpha = score;
+                    if alpha >= beta {
+                        if is_quiet {
+                            self.ordering.update_quiet_cutoff(color, &mv, &prev_move, &quiets_tried, ply, depth);
+                        }
+                        break;
+                    }
+                }
+            }
+            if is_quiet {
+                quiets_tried.push(mv);
+            }
+        }
+
+        if moves_searched == 0 {
+            return if singular_search {
+                //excluded move is the only legal move
+                alpha
+            } else if in_check {
+                //checkmate
+                -MATE_SCORE + ply as Score
+            } else {
+                //stalemate
+                0
+            };
+        }
+
+        if singular_search {
+            //result without the excluded move must not be mixed with the real one
+            return best_score;
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
         } else {
-            window.beta = min(window.beta, score);
+            Bound::Upper
+        };
+        self.tt.store(TTEntry {
+            key: hash,
+            mv: best_move,
+            score: score_to_tt(best_score, ply),
+            depth: depth,
+            bound: bound
+        });
+        best_score
+    }
+
+    //Extensions are limited to twice the root depth, so they can't make the search explode
+    #[inline]
+    fn extension_allowed(&self, ply: usize) -> bool {
+        (ply as i32) < 2 * self.root_depth
+    }
+
+    //Singular extension: the hash move is extended if it is much better than all other moves.
+    //This is verified by a reduced search of the node without the hash move.
+    //Returns the hash move if it is singular, NullMove otherwise.
+    //More: https://www.chessprogramming.org/Singular_Extensions
+    fn singular_move(&mut self, pos: &Position, entry: &TTEntry, depth: i32, ply: usize, prev_move: Move) -> Move {
+        if !self.options.singular_extension || depth < SINGULAR_MIN_DEPTH || entry.mv == NullMove
+            || entry.bound == Bound::Upper || entry.depth < depth - 3
+            || entry.score.abs() >= MATE_BOUND || !self.extension_allowed(ply) {
+            return NullMove;
+        }
+        let singular_beta = score_from_tt(entry.score, ply) - SINGULAR_MARGIN * depth;
+        self.excluded_moves[ply] = entry.mv;
+        let score = self.alphabeta(pos, singular_beta - 1, singular_beta, (depth - 1) / 2, ply, prev_move);
+        self.excluded_moves[ply] = NullMove;
+        if score < singular_beta { entry.mv } else { NullMove }
+    }
+
+    //Null move is not tried right after another null move, and when the side to move has only pawns left,
+    //because zugzwang is common there. It is also not tried in check, where it would be illegal.
+    fn null_move_allowed(&self, pos: &Position, static_eval: Score, beta: Score, depth: i32, ply: usize,
+                         prev_move: &Move) -> bool {
+        self.options.null_move
+            && ply > 0
+            && ply >= self.null_move_min_ply
+            && depth >= NULL_MOVE_MIN_DEPTH
+            && *prev_move != NullMove
+            && beta.abs() < MATE_BOUND
+            && has_non_pawn_material(pos, pos.next_to_move)
+            && static_eval >= beta
+    }
+
+    //Null move pruning: if the side to move gives the opponent a free move
+    //and a reduced search still fails high, the real search would most likely fail high too.
+    //Returns the score to cut off with, or None if the node has to be searched.
+    //More: https://www.chessprogramming.org/Null_Move_Pruning
+    fn null_move_search(&mut self, pos: &Position, beta: Score, depth: i32, ply: usize) -> Option<Score> {
+        //adaptive reduction, deeper searches are reduced more
+        let reduction = 2 + depth / 4;
+        let mut new_pos = *pos;
+        new_pos.apply_move(&NullMove);
+        self.evaluator.make_move(pos, &new_pos);
+        let mut score = -self.alphabeta(&new_pos, -beta, -beta + 1, depth - 1 - reduction, ply + 1, NullMove);
+        self.evaluator.unmake_move();
+        if score < beta {
+            return None;
+        }
+        //mate found after a null move is not proven
+        if score >= MATE_BOUND {
+            score = beta;
         }
-        if window.beta <= window.alpha {
-            break;
+        if depth < NULL_MOVE_VERIFICATION_DEPTH || !self.options.null_move_verification {
+            return Some(score);
         }
+
+        //verification search of the same node without null moves in the upper part of the tree,
+        //so zugzwang positions are not pruned
+        let previous_min_ply = self.null_move_min_ply;
+        self.null_move_min_ply = ply + (3 * (depth - reduction) / 4) as usize;
+        let verified = self.alphabeta(pos, beta - 1, beta, depth - reduction, ply, NullMove);
+        self.null_move_min_ply = previous_min_ply;
+        if verified >= beta { Some(score) } else { None }
     }
 
-    let result = if line.children.len() > 0 {
-        if pos.next_to_move == White {
-            window.alpha
-        } else {
-            window.beta
+    //Searches only captures and promotions to get a quiet position for the static evaluation.
+    //All evasions are searched when in check.
+    //More: https://www.chessprogramming.org/Quiescence_Search
+    fn quiescence(&mut self, pos: &Position, mut alpha: Score, beta: Score, ply: usize) -> Score {
+        if self.stopped {
+            return 0;
         }
-    } else {
-        if pos.is_check() {
-            //no moves available and check - checkmate
-            very_bad_score
-        } else {
-            //no moves, but no check - stalemate
-            //draw!
-            0
+        self.nodes += 1;
+        self.check_time();
+        if self.stopped {
+            return 0;
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(pos);
+        }
+
+        let in_check = pos.is_check();
+        let mut best_score = -INFINITY;
+        if !in_check {
+            //side to move can usually do better than nothing, so static evaluation is a lower bound
+            best_score = if self.options.lazy_eval {
+                self.evaluate_lazy(pos, alpha, beta)
+            } else {
+                self.evaluate(pos)
+            };
+            if best_score >= beta {
+                return best_score;
+            }
+            if best_score > alpha {
+                alpha = best_score;
+            }
         }
-    };
-    result
-}
 
+        let mut picker = MovePicker::captures(pos);
+        let mut moves_searched = 0;
+        while let Some(mv) = picker.next(&self.ordering) {
+            moves_searched += 1;
+            //captures that lose material are not worth searching
+            if !in_check && !see_ge(pos, &mv, 0) {
+                continue;
+            }
+            let mut new_pos = *pos;
+            new_pos.apply_move(&mv);
+            self.evaluator.make_move(pos, &new_pos);
+            let score = -self.quiescence(&new_pos, -beta, -alpha, ply + 1);
+            self.evaluator.unmake_move();
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
 
-pub fn search(pos: &Position, depth: usize) -> Option<Move> {
-    let mut search = Search::new(pos);
-    let mut search_engine = SearchEngine::new();
-    for i in 0..depth + 1 {
-        search.calculate_lines(&mut search_engine, i);
+        if in_check && moves_searched == 0 {
+            return -MATE_SCORE + ply as Score;
+        }
+        best_score
     }
-    let moves = search.top_moves(3);
-    if moves.len() > 0 {
-        Some(moves[0])
-    } else {
-        None
+}
+
+fn duration_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+//soft time limit is increased by half, but not above the hard limit
+fn extend_time(soft_time: Duration, hard_time: Option<Duration>) -> Duration {
+    let extended = soft_time + soft_time / 2;
+    match hard_time {
+        Some(hard) => min(extended, hard),
+        None => extended
     }
+}
 
+fn has_non_pawn_material(pos: &Position, color: Color) -> bool {
+    let board = &pos.board;
+    let pawns_and_kings = board.get_piece_bitset(Pawn) | board.get_piece_bitset(King);
+    !(board.get_color_bitset(color) & !pawns_and_kings).is_empty()
 }
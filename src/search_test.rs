@@ -43,11 +43,261 @@ fn assert_bestmove(fen:&str, best_move_hint:&str, depth:i32) {
     assert!(result.contains(&best_mv));
 }
 
+#[test]
+fn quiescence_test() {
+    ::tables::init_tables();
+    //at depth 1 the pawn on d5 looks free, quiescence search sees that it is defended
+    let free = parse_fen("4k3/8/8/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+    let defended = parse_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+    let capture = Move::new(Queen, ::squares::d1, ::squares::d5, None);
+    assert_eq!(::search::SearchEngine::new().search(&free, 1).best_move, Some(capture));
+    let result = ::search::SearchEngine::new().search(&defended, 1);
+    assert!(result.best_move != Some(capture));
+    assert!(result.score > 0);
+}
+
+#[test]
+fn transposition_table_test() {
+    ::tables::init_tables();
+    //the second search of the same position starts with the table filled by the first one
+    let pos = parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let mut engine = ::search::SearchEngine::new();
+    let first = engine.search(&pos, 4);
+    let second = engine.search(&pos, 4);
+    assert!(second.nodes < first.nodes);
+    assert_eq!(second.best_move, first.best_move);
+
+    engine.clear();
+    assert_eq!(engine.search(&pos, 4).nodes, first.nodes);
+}
+
 #[test]
 fn search_test() {
     ::tables::init_tables();
     let depth = 6;
 
     assert_bestmove("1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1", "Qd1", depth);
+    //reductions and pruning must not hide the mate without the help of extensions
+    let mut no_extensions = ::search::SearchOptions::new();
+    no_extensions.check_extension = false;
+    no_extensions.singular_extension = false;
+    let result = search_with("1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1", depth as usize, no_extensions);
+    assert_eq!(result.best_move.unwrap().to_string(), "q d6-d1");
     // assert_bestmove("r1b1r1k1/p1p3pp/2p2n2/2bp4/5P2/3BBQPq/PPPK3P/R4N1R b q - 0 1", "Bg4", depth);
 }
+
+fn searched_nodes(fen: &str, depth: usize, move_ordering: bool) -> u64 {
+    let pos = parse_fen(fen).unwrap();
+    let mut engine = ::search::SearchEngine::new();
+    engine.options.move_ordering = move_ordering;
+    engine.search(&pos, depth).nodes
+}
+
+#[test]
+fn move_ordering_nodes_test() {
+    ::tables::init_tables();
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10"
+    ];
+    for &fen in fens.iter() {
+        let ordered = searched_nodes(fen, 4, true);
+        let unordered = searched_nodes(fen, 4, false);
+        println!("{}: ordered {} nodes, unordered {} nodes", fen, ordered, unordered);
+        assert!(ordered < unordered);
+    }
+}
+
+fn search_with(fen: &str, depth: usize, options: ::search::SearchOptions) -> ::search::SearchResult {
+    let pos = parse_fen(fen).unwrap();
+    let mut engine = ::search::SearchEngine::new();
+    engine.options = options;
+    engine.search(&pos, depth)
+}
+
+#[test]
+fn null_move_pruning_test() {
+    ::tables::init_tables();
+    let mut no_null_move = ::search::SearchOptions::new();
+    no_null_move.null_move = false;
+
+    //null move pruning saves nodes in middlegame positions
+    let fen = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+    let with_null = search_with(fen, 5, ::search::SearchOptions::new());
+    let without_null = search_with(fen, 5, no_null_move);
+    println!("{}: null move {} nodes, no null move {} nodes", fen, with_null.nodes, without_null.nodes);
+    assert!(with_null.nodes < without_null.nodes);
+
+    //A zugzwang test position with pieces on the board, the best move is Nxd5. Unverified null moves let
+    //the side in zugzwang pass and prune the refutation, the verification search that starts at
+    //NULL_MOVE_VERIFICATION_DEPTH finds it like the search without null moves does.
+    let fen = "8/8/1p1r1k2/p1pPN1p1/P3KnP1/1P6/8/3R4 b - - 0 1";
+    let best_move = Move::new(Knight, ::squares::f4, ::squares::d5, None);
+    let mut no_verification = ::search::SearchOptions::new();
+    no_verification.null_move_verification = false;
+    assert_eq!(search_with(fen, 12, ::search::SearchOptions::new()).best_move, Some(best_move));
+    assert_eq!(search_with(fen, 12, no_null_move).best_move, Some(best_move));
+    assert!(search_with(fen, 12, no_verification).best_move != Some(best_move));
+}
+
+#[test]
+fn pruning_options_test() {
+    ::tables::init_tables();
+    let fen = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+    let mut plain = ::search::SearchOptions::new();
+    plain.late_move_reductions = false;
+    plain.reverse_futility = false;
+    plain.futility = false;
+    plain.razoring = false;
+    let plain_nodes = search_with(fen, 5, plain).nodes;
+
+    //each technique alone searches fewer nodes than the plain search
+    let mut options = plain;
+    options.late_move_reductions = true;
+    let lmr_nodes = search_with(fen, 5, options).nodes;
+    let mut options = plain;
+    options.reverse_futility = true;
+    let reverse_futility_nodes = search_with(fen, 5, options).nodes;
+    let mut options = plain;
+    options.futility = true;
+    let futility_nodes = search_with(fen, 5, options).nodes;
+    let mut options = plain;
+    options.razoring = true;
+    let razoring_nodes = search_with(fen, 5, options).nodes;
+    let all_nodes = search_with(fen, 5, ::search::SearchOptions::new()).nodes;
+    println!("plain {}, lmr {}, reverse futility {}, futility {}, razoring {}, all {}",
+             plain_nodes, lmr_nodes, reverse_futility_nodes, futility_nodes, razoring_nodes, all_nodes);
+    assert!(lmr_nodes < plain_nodes);
+    assert!(reverse_futility_nodes < plain_nodes);
+    assert!(futility_nodes < plain_nodes);
+    assert!(razoring_nodes <= plain_nodes);
+    assert!(all_nodes < plain_nodes);
+}
+
+
+
+#[test]
+fn extensions_test() {
+    ::tables::init_tables();
+    //mate in 3 with a queen sacrifice and a double check takes 5 plies, within 4 plies it is found only thanks to check extension
+    let fen = "1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1";
+    let mut no_check_extension = ::search::SearchOptions::new();
+    no_check_extension.check_extension = false;
+    let extended = search_with(fen, 4, ::search::SearchOptions::new());
+    let not_extended = search_with(fen, 4, no_check_extension);
+    assert_eq!(extended.best_move.unwrap().to_string(), "q d6-d1");
+    assert!(extended.score > ::search::MATE_BOUND);
+    assert!(not_extended.score < ::search::MATE_BOUND);
+
+    //at depth 8 only the search with singular extension finds the winning pawn push c6 (WAC.019)
+    let fen = "r1b2rk1/ppbn1ppp/4p3/1QP4q/3P4/N4N2/5PPP/R1B2RK1 w - - 0 1";
+    let best_move = Move::new(Pawn, ::squares::c5, ::squares::c6, None);
+    let mut no_singular_extension = ::search::SearchOptions::new();
+    no_singular_extension.singular_extension = false;
+    assert_eq!(search_with(fen, 8, ::search::SearchOptions::new()).best_move, Some(best_move));
+    assert!(search_with(fen, 8, no_singular_extension).best_move != Some(best_move));
+
+    //singular extension and lazy evaluation keep the winning moves
+    let mut no_lazy_eval = ::search::SearchOptions::new();
+    no_lazy_eval.lazy_eval = false;
+    for &(fen, best_move) in [("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4", "q h5-f7"),
+                              ("r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1", "q h6-h7"),
+                              ("r1b1r1k1/p1p3pp/2p2n2/2bp4/5P2/3BBQPq/PPPK3P/R4N1R b - - 0 1", "b c8-g4")].iter() {
+        for &options in [::search::SearchOptions::new(), no_singular_extension, no_lazy_eval].iter() {
+            let result = search_with(fen, 7, options);
+            assert_eq!(result.best_move.unwrap().to_string(), best_move, "{}", fen);
+        }
+    }
+}
+
+#[test]
+fn time_limits_test() {
+    use std::time::Duration;
+    use search::SearchLimits;
+    let limits = SearchLimits::clock(60000, 1000, None);
+    assert_eq!(limits.soft_time, Some(Duration::from_millis(2750)));
+    assert_eq!(limits.hard_time, Some(Duration::from_millis(20000)));
+    //the last move before the time control can use the hard limit only
+    let limits = SearchLimits::clock(3000, 0, Some(1));
+    assert_eq!(limits.soft_time, Some(Duration::from_millis(1000)));
+    assert_eq!(limits.hard_time, Some(Duration::from_millis(1000)));
+}
+
+#[test]
+fn iterative_deepening_test() {
+    ::tables::init_tables();
+    let pos = parse_fen("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10").unwrap();
+    let mut engine = ::search::SearchEngine::new();
+
+    //info is reported after every iteration, scores inside aspiration windows are exact
+    let mut iterations = Vec::new();
+    let result = engine.search_with_limits(&pos, &::search::SearchLimits::depth(7),
+                                           &mut |r: &::search::SearchResult| iterations.push(*r));
+    assert_eq!(iterations.iter().map(|r| r.depth).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(result.score, iterations[6].score);
+    assert_eq!(result.best_move, iterations[6].best_move);
+
+    //search is stopped when the time is over, but a move is always found
+    let result = engine.search_with_limits(&pos, &::search::SearchLimits::movetime(50), &mut |_| {});
+    assert!(result.best_move.is_some());
+    assert!(result.time_ms < 500);
+}
+
+#[test]
+fn lazy_smp_test() {
+    ::tables::init_tables();
+    let mut options = ::search::SearchOptions::new();
+    options.threads = 4;
+
+    //all threads agree on a forced mate
+    let result = search_with("1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1", 6, options);
+    assert_eq!(result.best_move.unwrap().to_string(), "q d6-d1");
+    assert!(result.score > ::search::MATE_BOUND);
+
+    //nodes of all threads are reported after every iteration
+    let pos = parse_fen("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10").unwrap();
+    let mut engine = ::search::SearchEngine::new();
+    engine.options = options;
+    let mut iterations = Vec::new();
+    let result = engine.search_with_limits(&pos, &::search::SearchLimits::depth(7),
+                                           &mut |r: &::search::SearchResult| iterations.push(*r));
+    assert!(result.best_move.is_some());
+    assert!(result.depth >= 7);
+    assert!(iterations.windows(2).all(|w| w[0].nodes <= w[1].nodes));
+    assert!(result.nodes >= iterations.last().unwrap().nodes);
+}
+
+fn uci_output(commands: &str) -> String {
+    use std::io::{Read, Cursor};
+    let mut input = Cursor::new(commands.as_bytes().to_vec());
+    let mut output = Cursor::new(Vec::new());
+    ::uci::UciEngine::new().main_loop(&mut input, &mut output);
+    let mut result = String::new();
+    output.set_position(0);
+    output.read_to_string(&mut result).unwrap();
+    result
+}
+
+#[test]
+fn deterministic_mode_test() {
+    ::tables::init_tables();
+    //node and time limits give the same output every time, even with more threads requested
+    let commands = "setoption name Deterministic value true\nsetoption name Threads value 4\n\
+                    position fen r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10\n\
+                    go nodes 20000\ngo movetime 20\ngo depth 6\n";
+    let output = uci_output(commands);
+    assert_eq!(output, uci_output(commands));
+    assert!(!output.contains(" time "));
+    assert_eq!(output.matches("bestmove").count(), 3);
+
+    //search stops at the node limit
+    let pos = parse_fen("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10").unwrap();
+    let mut engine = ::search::SearchEngine::new();
+    engine.options.deterministic = true;
+    engine.options.threads = 4;
+    let result = engine.search_with_limits(&pos, &::search::SearchLimits::nodes(5000), &mut |_| {});
+    assert_eq!(result.nodes, 5000);
+    assert!(result.best_move.is_some());
+    //the search doesn't change the options
+    assert_eq!(engine.options.threads, 4);
+}
@@ -0,0 +1,219 @@
+//Static exchange evaluation (SEE)
+//Plays out all captures on one square, each time capturing with the least valuable piece.
+//Both sides may stop capturing when it is not profitable for them.
+//Pins and checks are not taken into account.
+//More: https://www.chessprogramming.org/Static_Exchange_Evaluation
+use bitset::BitSet;
+use eval::Score;
+use types::*;
+
+//piece values in centipawns indexed by Kind
+pub static SEE_VALUES: [Score; 6] = [100, 330, 320, 500, 900, 20000];
+
+#[inline]
+pub fn see_value(kind: Kind) -> Score {
+    SEE_VALUES[kind as usize]
+}
+
+//kinds in the order they are used for recaptures
+static ATTACKERS_ORDER: [Kind; 6] = [Pawn, Knight, Bishop, Rook, Queen, King];
+
+//returns the least valuable piece of a given color from the attackers set
+#[inline]
+fn least_valuable_attacker(board: &Board, attackers: BitSet, color: Color) -> Option<(Square, Kind)> {
+    let color_attackers = attackers & board.get_color_bitset(color);
+    if color_attackers.is_empty() {
+        return None;
+    }
+    for &kind in ATTACKERS_ORDER.iter() {
+        if let Some(sq) = (color_attackers & board.get_piece_bitset(kind)).iter().next() {
+            return Some((sq, kind));
+        }
+    }
+    None
+}
+
+//material that is captured by the move and the piece standing on the target square after it
+fn move_outcome(pos: &Position, mi: &OrdinaryMoveInfo) -> (Score, Kind) {
+    let mut captured = match pos.board.get_piece(mi.to) {
+        Some(p) => see_value(p.kind()),
+        None if mi.kind == Pawn && Some(mi.to) == pos.en_passant => see_value(Pawn),
+        None => 0
+    };
+    let piece_on_square = match mi.promotion {
+        Some(promo) => {
+            captured += see_value(promo) - see_value(Pawn);
+            promo
+        }
+        None => mi.kind
+    };
+    (captured, piece_on_square)
+}
+
+//squares that are empty after the move has been made
+fn initial_occupancy(pos: &Position, mi: &OrdinaryMoveInfo) -> BitSet {
+    let mut occupied = pos.board.occupied() & !BitSet::from_one_square(mi.from);
+    if mi.kind == Pawn && Some(mi.to) == pos.en_passant {
+        let captured_sq = Square::new(mi.to.file(), mi.from.rank());
+        occupied = occupied & !BitSet::from_one_square(captured_sq);
+    }
+    occupied | BitSet::from_one_square(mi.to)
+}
+
+//Returns the material balance of the exchange started by the move,
+//from the point of view of the side that makes the move.
+pub fn see(pos: &Position, mv: &Move) -> Score {
+    let mi = match *mv {
+        OrdinaryMove(ref mi) => mi,
+        _ => return 0
+    };
+    let board = &pos.board;
+    let (captured, mut piece_on_square) = move_outcome(pos, mi);
+    let mut occupied = initial_occupancy(pos, mi);
+    let mut color = pos.next_to_move;
+
+    //gains[i] is the balance after i-th capture, if the side to move stops there
+    let mut gains: [Score; 32] = [0; 32];
+    gains[0] = captured;
+    let mut depth = 0;
+    loop {
+        color = color.inverse();
+        let attackers = board.attackers_to(mi.to, occupied) & occupied;
+        let (sq, kind) = match least_valuable_attacker(board, attackers, color) {
+            Some(a) => a,
+            None => break
+        };
+        depth += 1;
+        gains[depth] = see_value(piece_on_square) - gains[depth - 1];
+        if gains[depth] <= -gains[depth - 1] {
+            //even if the opponent doesn't recapture, this capture is not better than stopping
+            break;
+        }
+        piece_on_square = kind;
+        //removing the attacker may open an x-ray attack of a slider behind it
+        occupied = occupied & !BitSet::from_one_square(sq);
+    }
+
+    while depth > 0 {
+        gains[depth - 1] = -::std::cmp::max(-gains[depth - 1], gains[depth]);
+        depth -= 1;
+    }
+    gains[0]
+}
+
+//Returns true if see(pos, mv) >= threshold.
+//It is faster than see because it stops as soon as the answer is known.
+pub fn see_ge(pos: &Position, mv: &Move, threshold: Score) -> bool {
+    let mi = match *mv {
+        OrdinaryMove(ref mi) => mi,
+        _ => return 0 >= threshold
+    };
+    let board = &pos.board;
+    let (captured, piece_on_square) = move_outcome(pos, mi);
+
+    //balance if the opponent doesn't recapture
+    let mut swap = captured - threshold;
+    if swap < 0 {
+        return false;
+    }
+    //balance if the opponent recaptures and we stop
+    swap = see_value(piece_on_square) - swap;
+    if swap <= 0 {
+        return true;
+    }
+
+    let mut occupied = initial_occupancy(pos, mi);
+    let mut color = pos.next_to_move;
+    //result is true if the side that made the move wins the exchange
+    let mut result = true;
+    loop {
+        color = color.inverse();
+        let attackers = board.attackers_to(mi.to, occupied) & occupied;
+        let (sq, kind) = match least_valuable_attacker(board, attackers, color) {
+            Some(a) => a,
+            None => break
+        };
+
+        if kind == King {
+            //king can only recapture if the other side has no attackers left
+            let opponent_attackers = attackers & board.get_color_bitset(color.inverse());
+            return if opponent_attackers.is_empty() { !result } else { result };
+        }
+
+        result = !result;
+        swap = see_value(kind) - swap;
+        //the side that just recaptured can't lose the exchange anymore
+        if swap < result as Score {
+            break;
+        }
+        occupied = occupied & !BitSet::from_one_square(sq);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+use fen::parse_fen;
+use see::*;
+use squares::*;
+
+fn capture_see(fen: &str, mv: Move) -> Score {
+    let pos = parse_fen(fen).unwrap();
+    assert!(pos.gen_moves().any(|m| m == mv), "move {} is not legal in {}", mv, fen);
+    see(&pos, &mv)
+}
+
+#[test]
+fn see_test() {
+    ::tables::init_tables();
+    //undefended pawn
+    assert_eq!(capture_see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", Move::new(Rook, e1, e5, None)), 100);
+    //queen takes a pawn defended by a pawn
+    assert_eq!(capture_see("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1", Move::new(Queen, e1, e5, None)), -800);
+    //pawn takes a defended knight
+    assert_eq!(capture_see("4k3/2p5/3n4/4P3/8/8/8/4K3 w - - 0 1", Move::new(Pawn, e5, d6, None)), 220);
+    //second rook attacks through the first one
+    assert_eq!(capture_see("4r2k/8/8/4p3/8/8/4R3/4R2K w - - 0 1", Move::new(Rook, e2, e5, None)), 100);
+    assert_eq!(capture_see("4r2k/8/8/4p3/8/8/8/4R2K w - - 0 1", Move::new(Rook, e1, e5, None)), -400);
+    //knight takes a pawn, but black recaptures with a knight and has more attackers behind
+    assert_eq!(capture_see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                           Move::new(Knight, d3, e5, None)), -220);
+    //en passant
+    assert_eq!(capture_see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", Move::new(Pawn, e5, d6, None)), 100);
+    //promotion with capture, king recaptures the new queen
+    assert_eq!(capture_see("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1", Move::new(Pawn, e7, d8, Some(Queen))), 400);
+    assert_eq!(capture_see("3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1", Move::new(Pawn, e7, d8, Some(Queen))), 1300);
+    //rook is recaptured by the rook, not by the king
+    assert_eq!(capture_see("4k3/4r3/8/8/8/8/4Q3/4RK2 b - - 0 1", Move::new(Rook, e7, e2, None)), 400);
+    //king can't recapture a defended piece
+    assert_eq!(capture_see("4r1k1/4r3/8/8/8/8/4Q3/5K2 b - - 0 1", Move::new(Rook, e7, e2, None)), 900);
+    //quiet moves exchange nothing unless the piece can be taken
+    assert_eq!(capture_see("4k3/8/8/8/8/8/3p4/R6K w - - 0 1", Move::new(Rook, a1, c1, None)), -500);
+    assert_eq!(capture_see("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", Move::new(Rook, a1, a7, None)), 0);
+}
+
+#[test]
+fn see_ge_test() {
+    ::tables::init_tables();
+    let fens = [
+        "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "4r2k/8/8/4p3/8/8/4R3/4R2K w - - 0 1",
+        "4k3/4r3/8/8/8/8/4Q3/4RK2 b - - 0 1",
+        "4r1k1/4r3/8/8/8/8/4Q3/5K2 b - - 0 1",
+        "3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1"
+    ];
+    for &fen in fens.iter() {
+        let pos = parse_fen(fen).unwrap();
+        for mv in pos.gen_moves() {
+            let value = see(&pos, &mv);
+            for &threshold in [-1000, -500, -320, -100, -1, 0, 1, 100, 220, 320, 500, 1000].iter() {
+                assert_eq!(see_ge(&pos, &mv, threshold), value >= threshold,
+                    "fen: {} move: {} see: {} threshold: {}", fen, mv, value, threshold);
+            }
+        }
+    }
+}
+
+}
@@ -0,0 +1,102 @@
+use fen::{parse_fen, render_fen};
+use types::*;
+use eval::{Evaluator, SimpleEvaluator};
+use hash::calc_position_hash;
+use rand::isaac::Isaac64Rng;
+use rand::{RngCore, SeedableRng};
+
+static SEED_POSITIONS: [&str; 15] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbqkb1r/pp1p1ppp/2p5/4P3/2B5/8/PPP1NnPP/RNBQK2R w KQkq - 0 6",
+    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+    "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+    //Chess960 castling with rooks on other files, in Shredder-FEN and X-FEN
+    "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1",
+    "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w KQkq - 0 1",
+    "rr2k2r/ppp2ppp/8/3pp3/3PP3/8/PPP2PPP/RR2K2R b KBkb - 0 1",
+    //endings known to the endgame registry
+    "8/8/8/4k3/8/8/4P3/4K3 w - - 0 1",
+    "8/8/3k4/8/8/2KBN3/8/8 b - - 0 1",
+    "8/3k4/8/3b4/8/8/3RK3/8 w - - 0 1",
+    "8/8/8/4k3/1pp5/8/4KPP1/8 w - - 0 1"
+];
+
+//positions reachable in two plies from the seed positions and positions of random games
+fn test_positions() -> Vec<Position> {
+    let mut positions = Vec::new();
+    for fen in SEED_POSITIONS.iter() {
+        let pos = parse_fen(fen).unwrap();
+        positions.push(pos);
+        for mv in pos.gen_moves() {
+            let mut p1 = pos;
+            p1.apply_move(&mv);
+            positions.push(p1);
+            for mv in p1.gen_moves() {
+                let mut p2 = p1;
+                p2.apply_move(&mv);
+                positions.push(p2);
+            }
+        }
+    }
+
+    let mut rng: Isaac64Rng = SeedableRng::from_seed([11; 32]);
+    for fen in SEED_POSITIONS.iter() {
+        for _ in 0..10 {
+            let mut pos = parse_fen(fen).unwrap();
+            for _ in 0..80 {
+                let moves: Vec<Move> = pos.gen_moves().collect();
+                if moves.is_empty() {
+                    break;
+                }
+                pos.apply_move(&moves[(rng.next_u64() % moves.len() as u64) as usize]);
+                positions.push(pos);
+            }
+        }
+    }
+    positions
+}
+
+#[test]
+fn flip_colors_test() {
+    ::tables::init_tables();
+    let pos = parse_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K1R1 b Qk e3 4 20").unwrap();
+    let flipped = pos.flip_colors();
+    assert_eq!(render_fen(&flipped), "r3k1r1/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R w Kq e6 4 20");
+    assert_eq!(flipped.flip_colors(), pos);
+
+    //castling rook files are swapped with the rights
+    let pos = parse_fen("rr2k2r/8/8/8/8/8/8/1R2K1R1 w KQkb - 0 1").unwrap();
+    let flipped = pos.flip_colors();
+    assert_eq!(render_fen(&flipped), "1r2k1r1/8/8/8/8/8/8/RR2K2R b KBkq - 0 1");
+    assert_eq!(flipped.white_castling_files, pos.black_castling_files);
+    assert_eq!(flipped.black_castling_files, pos.white_castling_files);
+}
+
+//A position and the same position with the colors flipped must be equally good for the side to move,
+//have the same number of legal moves and different hashes.
+#[test]
+fn symmetry_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let positions = test_positions();
+    assert!(positions.len() > 10000);
+    //Chess960 seeds reach positions where rooks on other files can castle
+    assert!(positions.iter().any(|p| p.castling_files(p.next_to_move) != STANDARD_CASTLING_FILES
+        && p.gen_moves().any(|mv| mv == CastleKingSide || mv == CastleQueenSide)));
+    for pos in positions.iter() {
+        let flipped = pos.flip_colors();
+        let fen = render_fen(pos);
+        assert_eq!(flipped.flip_colors(), *pos, "{}", fen);
+        assert_eq!(flipped.white_castling_files, pos.black_castling_files, "{}", fen);
+        assert_eq!(flipped.black_castling_files, pos.white_castling_files, "{}", fen);
+        assert_eq!(evaluator.eval(&flipped), -evaluator.eval(pos), "{}", fen);
+        assert_eq!(evaluator.classify(&flipped), evaluator.classify(pos), "{}", fen);
+        assert!(calc_position_hash(&flipped) != calc_position_hash(pos), "{}", fen);
+        assert_eq!(flipped.is_check(), pos.is_check(), "{}", fen);
+        assert_eq!(flipped.gen_moves().count(), pos.gen_moves().count(), "{}", fen);
+    }
+}
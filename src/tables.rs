@@ -67,7 +67,14 @@ static BYTE_REVERSE:[u8; 256] =
  0x7, 0x87, 0x47, 0xC7, 0x27, 0xA7, 0x67, 0xE7, 0x17, 0x97, 0x57, 0xD7, 0x37, 0xB7, 0x77, 0xF7,
  0xF, 0x8F, 0x4F, 0xCF, 0x2F, 0xAF, 0x6F, 0xEF, 0x1F, 0x9F, 0x5F, 0xDF, 0x3F, 0xBF, 0x7F, 0xFF];
 
- static mut RANDOM_NUMBERS:[u64; 850] = [0; 850];
+ static mut RANDOM_NUMBERS:[u64; 880] = [0; 880];
+
+//For each pair of squares on the same rank, file or diagonal:
+//BETWEEN_MASKS contains squares strictly between them,
+//LINE_MASKS contains the whole line going through both of them (including the squares themselves).
+//For squares that are not aligned both masks are empty.
+static mut BETWEEN_MASKS:[[BitSet; 64]; 64] = [[BitSet { bits:0 }; 64]; 64];
+static mut LINE_MASKS:[[BitSet; 64]; 64] = [[BitSet { bits:0 }; 64]; 64];
 
 //this function reverses bits in a given byte
 #[inline]
@@ -145,6 +152,20 @@ pub fn get_black_pawn_attacks_mask(sq:Square) -> BitSet {
     }
 }
 
+#[inline]
+pub fn get_between_mask(a:Square, b:Square) -> BitSet {
+    unsafe {
+        BETWEEN_MASKS[a.file_and_rank() as usize][b.file_and_rank() as usize]
+    }
+}
+
+#[inline]
+pub fn get_line_mask(a:Square, b:Square) -> BitSet {
+    unsafe {
+        LINE_MASKS[a.file_and_rank() as usize][b.file_and_rank() as usize]
+    }
+}
+
 #[inline]
 pub fn get_random_number(n:usize) -> u64 {
     unsafe {
@@ -155,7 +176,10 @@ pub fn get_random_number(n:usize) -> u64 {
 
 pub fn init_tables() {
     init_move_data();
+    init_line_data();
     init_random_numbers();
+    ::endgame::init_kpk_bitbase();
+    ::eval::init_psq_table();
 }
 
 fn init_random_numbers() {
@@ -218,6 +242,40 @@ fn init_move_data() {
     }
 }
 
+fn init_line_data() {
+    let directions:[(i8, i8); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, -1), (1, -1), (-1, 1)];
+    let on_board = |file:i8, rank:i8| (0..8).contains(&file) && (0..8).contains(&rank);
+    for from in 0..64u8 {
+        let from_sq = Square(from);
+        for &(df, dr) in directions.iter() {
+            //whole line in both directions
+            let mut line = BitSet::from_one_square(from_sq);
+            for &sign in [1i8, -1i8].iter() {
+                let (mut file, mut rank) = (from_sq.file() as i8 + df * sign, from_sq.rank() as i8 + dr * sign);
+                while on_board(file, rank) {
+                    line.set(Square::new(file as u8, rank as u8), true);
+                    file += df * sign;
+                    rank += dr * sign;
+                }
+            }
+
+            //walk in one direction remembering squares we've passed
+            let mut between = BitSet::empty();
+            let (mut file, mut rank) = (from_sq.file() as i8 + df, from_sq.rank() as i8 + dr);
+            while on_board(file, rank) {
+                let to_sq = Square::new(file as u8, rank as u8);
+                unsafe {
+                    BETWEEN_MASKS[from as usize][to_sq.file_and_rank() as usize] = between;
+                    LINE_MASKS[from as usize][to_sq.file_and_rank() as usize] = line;
+                }
+                between.set(to_sq, true);
+                file += df;
+                rank += dr;
+            }
+        }
+    }
+}
+
 fn gen_pawn_moves(sq:Square, color:Color) -> BitSet {
     let sq_set = BitSet::from_one_square(sq);
     if color == White {
@@ -295,4 +353,35 @@ fn shift(x:u8, base:usize, n:isize) -> u64 {
         x >> ((-n) as usize)
     };
     (byte as u64) << base * 8
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+use tables::*;
+use squares::*;
+use bitset::BitSet;
+
+fn to_bitset(squares:&[Square]) -> BitSet {
+    let mut result = BitSet::empty();
+    for &sq in squares.iter() {
+        result.set(sq, true);
+    }
+    result
+}
+
+#[test]
+fn line_masks_test() {
+    init_tables();
+    assert_eq!(get_between_mask(a1, h8), to_bitset(&[b2, c3, d4, e5, f6, g7]));
+    assert_eq!(get_between_mask(h8, a1), to_bitset(&[b2, c3, d4, e5, f6, g7]));
+    assert_eq!(get_between_mask(e1, e4), to_bitset(&[e2, e3]));
+    assert_eq!(get_between_mask(b7, c7), BitSet::empty());
+    assert_eq!(get_between_mask(b1, c3), BitSet::empty());
+
+    assert_eq!(get_line_mask(c3, e5), to_bitset(&[a1, b2, c3, d4, e5, f6, g7, h8]));
+    assert_eq!(get_line_mask(d1, a4), to_bitset(&[a4, b3, c2, d1]));
+    assert_eq!(get_line_mask(a2, g2), get_line_mask(h2, b2));
+    assert_eq!(get_line_mask(b1, c3), BitSet::empty());
+}
+
+}
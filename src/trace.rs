@@ -0,0 +1,350 @@
+//Evaluation trace
+//Most terms of the evaluation are a parameter multiplied by a count, like the number of doubled pawns
+//or the number of squares a bishop attacks. Evaluation functions report every such term to a Trace.
+//The search uses NoTrace, which does nothing and is optimized away.
+//
+//LinearTrace records the counts (white minus black) as coefficients of the parameters, so the evaluation
+//of a position can be recomputed for other parameters as a weighted sum, which is what the tuner needs.
+//Terms that are not linear in the parameters (king safety, endgame scale factors) are recorded as constants.
+//
+//EvalBreakdown sums the terms by the part of the evaluation they belong to, for each side and game phase.
+use std::fmt;
+use eval::{Score, TaperedScore, MAX_PHASE};
+use eval_params::EvalParams;
+use endgame::SCALE_NORMAL;
+use types::*;
+
+//parts of the evaluation, the evaluation functions tell the trace which part they are computing
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Term {
+    Material,
+    PieceSquare(Kind),
+    PawnStructure,
+    Mobility,
+    PieceActivity,
+    KingSafety
+}
+
+pub static ALL_TERMS: [Term; 11] = [
+    Term::Material,
+    Term::PieceSquare(Pawn), Term::PieceSquare(Knight), Term::PieceSquare(Bishop),
+    Term::PieceSquare(Rook), Term::PieceSquare(Queen), Term::PieceSquare(King),
+    Term::PawnStructure, Term::Mobility, Term::PieceActivity, Term::KingSafety
+];
+
+pub trait Trace {
+    //false for NoTrace, so cached results without a trace can be used
+    const ACTIVE: bool;
+
+    //terms reported after this call belong to the given part of the evaluation
+    fn begin(&mut self, term: Term);
+
+    //tapered parameter added count times to the score of color
+    fn add<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut TaperedScore;
+
+    //parameter added count times to the endgame score of color
+    fn add_eg<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut Score;
+
+    //term that is not linear in the parameters, from the point of view of color
+    fn add_nonlinear(&mut self, color: Color, score: TaperedScore);
+}
+
+pub struct NoTrace;
+
+impl Trace for NoTrace {
+    const ACTIVE: bool = false;
+
+    #[inline(always)]
+    fn begin(&mut self, _term: Term) {}
+
+    #[inline(always)]
+    fn add<F>(&mut self, _color: Color, _count: Score, _param: F)
+        where F: Fn(&mut EvalParams) -> &mut TaperedScore {}
+
+    #[inline(always)]
+    fn add_eg<F>(&mut self, _color: Color, _count: Score, _param: F)
+        where F: Fn(&mut EvalParams) -> &mut Score {}
+
+    #[inline(always)]
+    fn add_nonlinear(&mut self, _color: Color, _score: TaperedScore) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct LinearTrace {
+    //coefficients of the middlegame and endgame scores, from the white point of view
+    pub mg: EvalParams,
+    pub eg: EvalParams,
+    pub nonlinear: TaperedScore,
+    pub phase: i32,
+    pub scale: Score,
+    //score of a specialized endgame evaluation, it replaces all other terms
+    pub endgame: Option<Score>
+}
+
+impl LinearTrace {
+    pub fn new() -> LinearTrace {
+        LinearTrace {
+            mg: EvalParams::zero(),
+            eg: EvalParams::zero(),
+            nonlinear: TaperedScore::default(),
+            phase: MAX_PHASE,
+            scale: SCALE_NORMAL,
+            endgame: None
+        }
+    }
+
+    //Coefficients of the final score for every value of EvalParams::values(),
+    //the score is their weighted sum plus the constant. Only values with non-zero coefficients are returned.
+    pub fn coefficients(&self) -> (Vec<(usize, f64)>, f64) {
+        if let Some(score) = self.endgame {
+            return (Vec::new(), score as f64);
+        }
+        let phase = self.phase as f64 / MAX_PHASE as f64;
+        let eg_factor = (1.0 - phase) * self.scale as f64 / SCALE_NORMAL as f64;
+        let coefficients = self.mg.values().iter().zip(self.eg.values())
+            .map(|(&mg, eg)| mg as f64 * phase + eg as f64 * eg_factor)
+            .enumerate()
+            .filter(|&(_, c)| c != 0.0)
+            .collect();
+        (coefficients, self.nonlinear.mg as f64 * phase + self.nonlinear.eg as f64 * eg_factor)
+    }
+
+    //score for the given parameters, the same as the evaluation up to rounding
+    pub fn eval(&self, params: &EvalParams) -> f64 {
+        let values = params.values();
+        let (coefficients, constant) = self.coefficients();
+        constant + coefficients.iter().map(|&(i, c)| c * values[i] as f64).sum::<f64>()
+    }
+}
+
+impl Trace for LinearTrace {
+    const ACTIVE: bool = true;
+
+    fn begin(&mut self, _term: Term) {}
+
+    fn add<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut TaperedScore {
+        let count = if color == White { count } else { -count };
+        param(&mut self.mg).mg += count;
+        param(&mut self.eg).eg += count;
+    }
+
+    fn add_eg<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut Score {
+        *param(&mut self.eg) += if color == White { count } else { -count };
+    }
+
+    fn add_nonlinear(&mut self, color: Color, score: TaperedScore) {
+        self.nonlinear += if color == White { score } else { -score };
+    }
+}
+
+//Evaluation split into terms, every term has a score of each side from its own point of view.
+#[derive(Clone, Debug)]
+pub struct EvalBreakdown {
+    params: EvalParams,
+    current: Term,
+    //scores of white and black for every term of ALL_TERMS
+    scores: Vec<[TaperedScore; 2]>,
+    pub phase: i32,
+    pub scale: Score,
+    //score of a specialized endgame evaluation, it replaces all other terms
+    pub endgame: Option<Score>,
+    //final score from the white point of view, the same as the evaluation
+    pub score: Score
+}
+
+impl EvalBreakdown {
+    pub fn new(params: &EvalParams) -> EvalBreakdown {
+        EvalBreakdown {
+            params: params.clone(),
+            current: Term::Material,
+            scores: vec![[TaperedScore::default(); 2]; ALL_TERMS.len()],
+            phase: MAX_PHASE,
+            scale: SCALE_NORMAL,
+            endgame: None,
+            score: 0
+        }
+    }
+
+    pub fn get(&self, term: Term, color: Color) -> TaperedScore {
+        self.scores[term_index(term)][color as usize]
+    }
+
+    //white score minus black score
+    pub fn net(&self, term: Term) -> TaperedScore {
+        self.get(term, White) - self.get(term, Black)
+    }
+
+    //sum of all terms from the white point of view, before the endgame scaling
+    pub fn total(&self) -> TaperedScore {
+        ALL_TERMS.iter().fold(TaperedScore::default(), |sum, &term| sum + self.net(term))
+    }
+
+    fn add_score(&mut self, color: Color, score: TaperedScore) {
+        self.scores[term_index(self.current)][color as usize] += score;
+    }
+}
+
+fn term_index(term: Term) -> usize {
+    ALL_TERMS.iter().position(|&t| t == term).unwrap()
+}
+
+impl Trace for EvalBreakdown {
+    const ACTIVE: bool = true;
+
+    fn begin(&mut self, term: Term) {
+        self.current = term;
+    }
+
+    fn add<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut TaperedScore {
+        let score = *param(&mut self.params) * count;
+        self.add_score(color, score);
+    }
+
+    fn add_eg<F>(&mut self, color: Color, count: Score, param: F)
+        where F: Fn(&mut EvalParams) -> &mut Score {
+        let score = TaperedScore::new(0, *param(&mut self.params) * count);
+        self.add_score(color, score);
+    }
+
+    fn add_nonlinear(&mut self, color: Color, score: TaperedScore) {
+        self.add_score(color, score);
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Material => write!(f, "Material"),
+            Term::PieceSquare(kind) => write!(f, "{:?} squares", kind),
+            Term::PawnStructure => write!(f, "Pawn structure"),
+            Term::Mobility => write!(f, "Mobility"),
+            Term::PieceActivity => write!(f, "Piece activity"),
+            Term::KingSafety => write!(f, "King safety")
+        }
+    }
+}
+
+//Table of all terms in centipawns, one row per term with middlegame and endgame scores of each side
+impl fmt::Display for EvalBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = "----------------+-------------+-------------+-------------";
+        writeln!(f, "      Term      |    White    |    Black    |    Total")?;
+        writeln!(f, "                |   MG    EG  |   MG    EG  |   MG    EG")?;
+        writeln!(f, "{}", line)?;
+        let row = |f: &mut fmt::Formatter, name: &str, white: TaperedScore, black: TaperedScore, total: TaperedScore| {
+            writeln!(f, "{:<16}| {:5} {:5} | {:5} {:5} | {:5} {:5}",
+                     name, white.mg, white.eg, black.mg, black.eg, total.mg, total.eg)
+        };
+        for &term in ALL_TERMS.iter() {
+            row(f, &term.to_string(), self.get(term, White), self.get(term, Black), self.net(term))?;
+        }
+        writeln!(f, "{}", line)?;
+        let total = self.total();
+        writeln!(f, "{:<16}|             |             | {:5} {:5}", "Total", total.mg, total.eg)?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{}, endgame scale: {}/{}", self.phase, MAX_PHASE, self.scale, SCALE_NORMAL)?;
+        if let Some(score) = self.endgame {
+            writeln!(f, "Known endgame: {}", score)?;
+        }
+        write!(f, "Final evaluation: {} (white side)", self.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use trace::*;
+use eval::{Evaluator, SimpleEvaluator};
+use fen::parse_fen;
+
+static FENS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 0 1",
+    "6k1/8/8/3P4/8/8/1K6/8 w - - 0 1"
+];
+
+#[test]
+fn trace_matches_eval_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    for fen in FENS.iter() {
+        let position = parse_fen(fen).unwrap();
+        let score = evaluator.trace(&position).eval(evaluator.params());
+        //integer divisions in the evaluation round the score
+        assert!((score - evaluator.eval(&position) as f64).abs() < 2.0, "{}: {}", fen, score);
+    }
+
+    //rook against bishop is scaled down, king and pawn against king is a known endgame
+    let position = parse_fen("k7/8/8/3K4/8/8/8/2b4R w - - 0 40").unwrap();
+    assert_eq!(evaluator.trace(&position).scale, 16);
+    let position = parse_fen("6k1/8/8/3P4/8/8/1K6/8 w - - 0 1").unwrap();
+    let trace = evaluator.trace(&position);
+    assert_eq!(trace.endgame, Some(evaluator.eval(&position)));
+    assert_eq!(trace.coefficients().0, vec![]);
+}
+
+#[test]
+fn breakdown_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let startpos = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    let breakdown = evaluator.breakdown(&startpos);
+    for &term in ALL_TERMS.iter() {
+        assert_eq!(breakdown.get(term, White), breakdown.get(term, Black), "{}", term);
+    }
+    assert_eq!(breakdown.get(Term::Material, White), TaperedScore::new(8 * 100 + 2 * 320 + 2 * 330 + 2 * 500 + 900 + 20000,
+                                                                       8 * 120 + 2 * 300 + 2 * 320 + 2 * 540 + 950 + 20000));
+    assert_eq!(breakdown.score, 0);
+
+    let position = parse_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+    let breakdown = evaluator.breakdown(&position);
+    //white rook on a1 is on a corner square of its table and on an open file
+    assert_eq!(breakdown.get(Term::PieceSquare(Rook), White), TaperedScore::new(0, 0));
+    assert_eq!(breakdown.get(Term::PieceActivity, White), evaluator.params().rook_open_file);
+    assert_eq!(breakdown.net(Term::Material), evaluator.params().piece_values[Rook as usize]);
+    assert_eq!(breakdown.total().taper(breakdown.phase), evaluator.eval(&position));
+    assert_eq!(breakdown.score, evaluator.eval(&position));
+
+    let table = breakdown.to_string();
+    assert!(table.contains("Rook squares    |     0     0 |     0     0 |     0     0"));
+    assert!(table.ends_with(&format!("Final evaluation: {} (white side)", breakdown.score)));
+}
+
+#[test]
+fn trace_coefficients_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let trace = evaluator.trace(&parse_fen("4k3/8/4p3/4P3/4P3/8/8/2B1KB2 w - - 0 1").unwrap());
+    assert_eq!(trace.mg.piece_values[Pawn as usize], TaperedScore::new(1, 0));
+    assert_eq!(trace.eg.piece_values[Pawn as usize], TaperedScore::new(0, 1));
+    assert_eq!(trace.mg.bishop_pair.mg, 1);
+    //white pawns are doubled and isolated, the black pawn is isolated
+    assert_eq!(trace.mg.doubled_pawn.mg, 1);
+    assert_eq!(trace.mg.isolated_pawn.mg, 2 - 1);
+    //black pawn on e6 uses the square e3 of the table
+    assert_eq!(trace.mg.pst[Pawn as usize][44].mg, -1);
+    assert_eq!(trace.phase, 2);
+
+    //other parameters give the same score as an evaluator with them
+    let mut params = evaluator.params().clone();
+    params.bishop_pair = TaperedScore::new(45, 80);
+    params.doubled_pawn = TaperedScore::new(-30, -40);
+    params.pst[Pawn as usize][44] = TaperedScore::new(17, -9);
+    params.mobility[Bishop as usize] = TaperedScore::new(9, 3);
+    params.passed_pawn_enemy_king_distance = 11;
+    let tuned = SimpleEvaluator::with_params(params.clone());
+    for fen in FENS.iter() {
+        let position = parse_fen(fen).unwrap();
+        let score = evaluator.trace(&position).eval(&params);
+        assert!((score - tuned.eval(&position) as f64).abs() < 2.0, "{}: {}", fen, score);
+    }
+}
+
+}
@@ -0,0 +1,204 @@
+//Transposition table
+//Remembers results of searches in positions that have already been visited,
+//so they can be reused when the same position is reached by a different move order.
+//More: https://www.chessprogramming.org/Transposition_Table
+//
+//The table is shared by all search threads without locks.
+use eval::Score;
+use lockless::LocklessTable;
+use types::*;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Bound {
+    Exact, //score is exact
+    Lower, //real score is >= score (search failed high)
+    Upper  //real score is <= score (search failed low)
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct TTEntry {
+    pub key: u64,
+    pub mv: Move, //best move found, NullMove if there was no such move
+    pub score: Score,
+    pub depth: i32,
+    pub bound: Bound
+}
+
+pub struct TranspositionTable {
+    table: LocklessTable
+}
+
+//Move packed into 16 bits: from square in bits 0-5, to square in bits 6-11, flags in bits 12-15.
+//Flags are 0 for ordinary moves, 1-4 for promotions to knight, bishop, rook and queen, 5-6 for castling.
+//Zero is the null move, because a move from a1 to a1 is impossible.
+pub fn pack_move(mv: &Move) -> u16 {
+    match *mv {
+        OrdinaryMove(ref mi) => {
+            let flags = match mi.promotion {
+                None => 0,
+                Some(Knight) => 1,
+                Some(Bishop) => 2,
+                Some(Rook) => 3,
+                Some(_) => 4
+            };
+            mi.from.file_and_rank() as u16 | (mi.to.file_and_rank() as u16) << 6 | flags << 12
+        }
+        CastleKingSide => 5 << 12,
+        CastleQueenSide => 6 << 12,
+        NullMove => 0
+    }
+}
+
+//Kind of the moving piece is taken from the position, NullMove is returned if the from square is empty.
+//The move may still be illegal in the position in case of a hash collision.
+pub fn unpack_move(packed: u16, pos: &Position) -> Move {
+    let square = |index: u16| Square::new((index & 7) as u8, (index >> 3 & 7) as u8);
+    let promotion = match packed >> 12 {
+        0 => None,
+        1 => Some(Knight),
+        2 => Some(Bishop),
+        3 => Some(Rook),
+        4 => Some(Queen),
+        5 => return CastleKingSide,
+        6 => return CastleQueenSide,
+        _ => return NullMove
+    };
+    if packed == 0 {
+        return NullMove;
+    }
+    let from = square(packed);
+    match pos.board.get_piece(from) {
+        Some(piece) => Move::new(piece.kind(), from, square(packed >> 6), promotion),
+        None => NullMove
+    }
+}
+
+//data word: move in bits 0-15, score in bits 16-47, depth in bits 48-55, bound in bits 56-57
+//bound is never zero, so zero data means an empty entry
+fn pack_data(entry: &TTEntry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3
+    };
+    let depth = entry.depth.clamp(0, 255) as u64;
+    pack_move(&entry.mv) as u64 | (entry.score as u32 as u64) << 16 | depth << 48 | bound << 56
+}
+
+fn data_depth(data: u64) -> i32 {
+    (data >> 48 & 0xff) as i32
+}
+
+fn data_bound(data: u64) -> Bound {
+    match data >> 56 & 3 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        _ => Bound::Upper
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        TranspositionTable { table: LocklessTable::new(size_mb) }
+    }
+
+    pub fn clear(&self) {
+        self.table.clear();
+    }
+
+    pub fn probe(&self, key: u64, pos: &Position) -> Option<TTEntry> {
+        self.table.load(key).map(|data| TTEntry {
+            key: key,
+            mv: unpack_move(data as u16, pos),
+            score: (data >> 16) as u32 as Score,
+            depth: data_depth(data),
+            bound: data_bound(data)
+        })
+    }
+
+    //Entries of other positions are always replaced.
+    //For the same position a shallower result doesn't replace a deeper one,
+    //unless it is exact.
+    pub fn store(&self, entry: TTEntry) {
+        let replace = match self.table.load(entry.key) {
+            Some(old) => entry.depth >= data_depth(old) || entry.bound == Bound::Exact,
+            None => true
+        };
+        if replace {
+            self.table.store(entry.key, pack_data(&entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+use tt::*;
+use fen::parse_fen;
+use squares::*;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn store_probe_test() {
+    let pos = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    let tt = TranspositionTable::new(1);
+    let mv = Move::new(Pawn, e2, e4, None);
+    let entry = TTEntry { key: 12345, mv: mv, score: 10, depth: 5, bound: Bound::Exact };
+    tt.store(entry);
+    assert_eq!(tt.probe(12345, &pos), Some(entry));
+    assert_eq!(tt.probe(54321, &pos), None);
+
+    //shallower result doesn't replace a deeper one
+    tt.store(TTEntry { depth: 2, bound: Bound::Lower, ..entry });
+    assert_eq!(tt.probe(12345, &pos), Some(entry));
+    tt.store(TTEntry { depth: 6, bound: Bound::Upper, score: -99990, ..entry });
+    assert_eq!(tt.probe(12345, &pos), Some(TTEntry { depth: 6, bound: Bound::Upper, score: -99990, ..entry }));
+
+    tt.clear();
+    assert_eq!(tt.probe(12345, &pos), None);
+}
+
+#[test]
+fn pack_move_test() {
+    ::tables::init_tables();
+    for fen in ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"].iter() {
+        let pos = parse_fen(fen).unwrap();
+        for mv in pos.gen_moves() {
+            assert_eq!(unpack_move(pack_move(&mv), &pos), mv);
+        }
+    }
+    let pos = parse_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    assert_eq!(pack_move(&NullMove), 0);
+    assert_eq!(unpack_move(0, &pos), NullMove);
+    //from square is empty
+    assert_eq!(unpack_move(pack_move(&Move::new(Pawn, e2, e4, None)), &pos), NullMove);
+}
+
+#[test]
+fn concurrent_access_test() {
+    let pos = parse_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let tt = Arc::new(TranspositionTable::new(1));
+    let mut workers = Vec::new();
+    for t in 0..4 {
+        let tt = tt.clone();
+        workers.push(thread::spawn(move || {
+            for i in 0..100000u64 {
+                //entries of all threads compete for the same slots
+                let key = (i % 1000).wrapping_mul(0x9E37_79B9_7F4A_7C15) + t;
+                let score = (key % 10000) as Score;
+                tt.store(TTEntry { key: key, mv: NullMove, score: score, depth: (i % 20) as i32, bound: Bound::Lower });
+                if let Some(e) = tt.probe(key ^ 1, &pos) {
+                    //entry is never a mix of two different stores
+                    assert_eq!(e.score, ((key ^ 1) % 10000) as Score);
+                }
+            }
+        }));
+    }
+    for w in workers {
+        w.join().unwrap();
+    }
+}
+
+}
@@ -0,0 +1,381 @@
+//Texel tuning of the evaluation parameters
+//Positions from games are labelled with the game result (1 white wins, 0.5 draw, 0 black wins).
+//The error of a parameter set is the mean squared difference between the results and the winning chances
+//predicted by the evaluation, the tuner changes parameters to minimize it.
+//Positions are resolved by a quiescence search first and the quiet position at its end is evaluated.
+//
+//Parameters with coefficients in the evaluation trace are tuned by gradient descent (Adam),
+//the error of every position is computed from its trace, without evaluating it again.
+//Parameters listed in NONLINEAR_PARAMS are tuned by local search, which tries small steps
+//in both directions and keeps the ones that decrease the error.
+//More: https://www.chessprogramming.org/Texel%27s_Tuning_Method
+use std::cmp::max;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use eval::{Evaluator, SimpleEvaluator, Score, INFINITY};
+use eval_params::{EvalParams, NONLINEAR_PARAMS};
+use fen::parse_fen;
+use move_order::{MovePicker, OrderingTables};
+use search::MATE_SCORE;
+use see::see_ge;
+use types::*;
+
+pub static DEFAULT_EPOCHS: usize = 3;
+pub static DEFAULT_ITERATIONS: usize = 500;
+pub static DEFAULT_LEARNING_RATE: f64 = 1.0;
+
+//quiescence search used to resolve positions stops at this depth
+static MAX_RESOLVE_PLY: usize = 16;
+
+//Adam parameters
+static BETA1: f64 = 0.9;
+static BETA2: f64 = 0.999;
+static EPSILON: f64 = 1e-8;
+
+//sparse trace of a position: coefficients of the parameter values, the constant part and the game result
+struct TracedPosition {
+    coefficients: Vec<(usize, f64)>,
+    constant: f64,
+    result: f64
+}
+
+pub struct Tuner {
+    //quiet positions with results
+    positions: Vec<(Position, f64)>,
+    params: EvalParams,
+    //scale of the sigmoid, it is fitted to the initial parameters and then fixed
+    k: f64
+}
+
+//winning chances of white for a score in centipawns
+#[inline]
+fn sigmoid(k: f64, score: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+impl Tuner {
+    pub fn new(positions: &[(Position, f64)], params: EvalParams) -> Tuner {
+        let evaluator = SimpleEvaluator::with_params(params.clone());
+        let tables = OrderingTables::new();
+        let quiet = positions.iter()
+            .map(|&(ref pos, result)| (quiet_position(&evaluator, &tables, pos), result))
+            .collect();
+        let mut tuner = Tuner { positions: quiet, params: params, k: 1.0 };
+        tuner.k = tuner.fit_k();
+        tuner
+    }
+
+    pub fn params(&self) -> &EvalParams {
+        &self.params
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    //error of the current parameters, computed by the evaluator
+    pub fn error(&self) -> f64 {
+        self.error_with(&self.params, self.k)
+    }
+
+    fn error_with(&self, params: &EvalParams, k: f64) -> f64 {
+        let evaluator = SimpleEvaluator::with_params(params.clone());
+        let sum: f64 = self.positions.iter()
+            .map(|&(ref pos, result)| (result - sigmoid(k, evaluator.eval(pos) as f64)).powi(2))
+            .sum();
+        sum / max(1, self.positions.len()) as f64
+    }
+
+    //K with the lowest error, found by scanning with smaller steps around the best value
+    fn fit_k(&self) -> f64 {
+        let mut best = (1.0, self.error_with(&self.params, 1.0));
+        let mut step = 0.5;
+        for _ in 0..4 {
+            let center = best.0;
+            for i in -5..=5 {
+                let k = center + step * i as f64;
+                if k <= 0.0 {
+                    continue;
+                }
+                let error = self.error_with(&self.params, k);
+                if error < best.1 {
+                    best = (k, error);
+                }
+            }
+            step /= 5.0;
+        }
+        best.0
+    }
+
+    fn traces(&self) -> Vec<TracedPosition> {
+        let evaluator = SimpleEvaluator::with_params(self.params.clone());
+        self.positions.iter().map(|&(ref pos, result)| {
+            let (coefficients, constant) = evaluator.trace(pos).coefficients();
+            TracedPosition { coefficients: coefficients, constant: constant, result: result }
+        }).collect()
+    }
+
+    //Tunes the linear parameters, report is called with the iteration number and the error every 100 iterations.
+    //Returns the error of the rounded parameters.
+    pub fn gradient_descent(&mut self, iterations: usize, learning_rate: f64,
+                            report: &mut dyn FnMut(usize, f64)) -> f64 {
+        let traces = self.traces();
+        let mut values: Vec<f64> = self.params.values().iter().map(|&v| v as f64).collect();
+        let mut m = vec![0.0; values.len()];
+        let mut v = vec![0.0; values.len()];
+        for t in 1..=iterations {
+            let (gradient, error) = self.gradient(&traces, &values);
+            for i in 0..values.len() {
+                m[i] = BETA1 * m[i] + (1.0 - BETA1) * gradient[i];
+                v[i] = BETA2 * v[i] + (1.0 - BETA2) * gradient[i] * gradient[i];
+                let m_hat = m[i] / (1.0 - BETA1.powi(t as i32));
+                let v_hat = v[i] / (1.0 - BETA2.powi(t as i32));
+                values[i] -= learning_rate * m_hat / (v_hat.sqrt() + EPSILON);
+            }
+            if t % 100 == 0 {
+                report(t, error);
+            }
+        }
+        let rounded: Vec<Score> = values.iter().map(|v| v.round() as Score).collect();
+        self.params.set_values(&rounded);
+        self.error()
+    }
+
+    //gradient of the error and the error before the step
+    fn gradient(&self, traces: &[TracedPosition], values: &[f64]) -> (Vec<f64>, f64) {
+        let mut gradient = vec![0.0; values.len()];
+        let mut error = 0.0;
+        //derivative of the sigmoid is sigmoid * (1 - sigmoid) * K * ln(10) / 400
+        let scale = self.k * 10f64.ln() / 400.0;
+        for trace in traces.iter() {
+            let score = trace.constant + trace.coefficients.iter().map(|&(i, c)| c * values[i]).sum::<f64>();
+            let s = sigmoid(self.k, score);
+            error += (trace.result - s).powi(2);
+            let d = -2.0 * (trace.result - s) * s * (1.0 - s) * scale;
+            for &(i, c) in trace.coefficients.iter() {
+                gradient[i] += d * c;
+            }
+        }
+        let n = max(1, traces.len()) as f64;
+        (gradient.iter().map(|g| g / n).collect(), error / n)
+    }
+
+    //One pass over the non-linear parameters, steps are about 5% of the value and at least 1.
+    //Report is called with the parameter label and its new value after every improvement.
+    pub fn local_search(&mut self, report: &mut dyn FnMut(&str, Score)) -> f64 {
+        let names = self.params.value_names();
+        let mut values = self.params.values();
+        let mut best_error = self.error();
+        for i in 0..values.len() {
+            if !NONLINEAR_PARAMS.contains(&names[i].as_str()) {
+                continue;
+            }
+            let step = max(1, values[i].abs() / 20);
+            for &delta in [step, -step].iter() {
+                let mut candidate = values.clone();
+                candidate[i] += delta;
+                let mut params = self.params.clone();
+                params.set_values(&candidate);
+                let error = self.error_with(&params, self.k);
+                if error < best_error {
+                    best_error = error;
+                    values = candidate;
+                    self.params = params;
+                    report(&value_label(&names, i), values[i]);
+                    break;
+                }
+            }
+        }
+        best_error
+    }
+}
+
+//parameter name with the index of the value for arrays, like mobility_base[2]
+fn value_label(names: &[String], i: usize) -> String {
+    let first = names.iter().position(|n| *n == names[i]).unwrap();
+    let count = names.iter().filter(|&n| *n == names[i]).count();
+    if count == 1 { names[i].clone() } else { format!("{}[{}]", names[i], i - first) }
+}
+
+//Position at the end of the principal variation of a quiescence search, all captures in it are resolved.
+pub fn quiet_position(evaluator: &SimpleEvaluator, tables: &OrderingTables, pos: &Position) -> Position {
+    quiescence(evaluator, tables, pos, -INFINITY, INFINITY, 0).1
+}
+
+//same as the quiescence search of the engine, but it returns the position the score comes from
+fn quiescence(evaluator: &SimpleEvaluator, tables: &OrderingTables, pos: &Position,
+              mut alpha: Score, beta: Score, ply: usize) -> (Score, Position) {
+    let in_check = pos.is_check();
+    let mut best = (-INFINITY, *pos);
+    if !in_check || ply >= MAX_RESOLVE_PLY {
+        let score = evaluator.eval(pos);
+        let stand_pat = if pos.next_to_move == White { score } else { -score };
+        if stand_pat >= beta || ply >= MAX_RESOLVE_PLY {
+            return (stand_pat, *pos);
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+        best = (stand_pat, *pos);
+    }
+
+    let mut picker = MovePicker::captures(pos);
+    let mut moves_searched = 0;
+    while let Some(mv) = picker.next(tables) {
+        moves_searched += 1;
+        if !in_check && !see_ge(pos, &mv, 0) {
+            continue;
+        }
+        let mut new_pos = *pos;
+        new_pos.apply_move(&mv);
+        let (score, leaf) = quiescence(evaluator, tables, &new_pos, -beta, -alpha, ply + 1);
+        if -score > best.0 {
+            best = (-score, leaf);
+            if -score > alpha {
+                alpha = -score;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+    }
+
+    if in_check && moves_searched == 0 {
+        return (-MATE_SCORE, *pos);
+    }
+    best
+}
+
+//Line format: FEN followed by the result, which is 1-0, 0-1, 1/2-1/2 or a number from 0 to 1.
+//The result can be in brackets or quotes, like in EPD files. Move counters of the FEN are optional.
+//Fields separated by | are allowed between them, like the score and the move written by gensfen.
+pub fn parse_position(line: &str) -> Result<(Position, f64), String> {
+    let line = line.trim();
+    let split = match line.rfind(char::is_whitespace) {
+        Some(index) => index,
+        None => return Err("FEN and result are expected".to_string())
+    };
+    let result_token = line[split..].trim().trim_matches(|c| "[]\";".contains(c));
+    let result = match result_token {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        number => match number.parse::<f64>() {
+            Ok(r) if (0.0..=1.0).contains(&r) => r,
+            _ => return Err(format!("Invalid result: {}", result_token))
+        }
+    };
+    let fen_part = line[..split].split('|').next().unwrap_or("");
+    let mut fen = fen_part.trim().trim_end_matches(',').trim().to_string();
+    if fen.split_whitespace().count() == 4 {
+        fen += " 0 1";
+    }
+    parse_fen(&fen).map(|pos| (pos, result))
+}
+
+pub fn load_positions(path: &str) -> Result<Vec<(Position, f64)>, String> {
+    let file = File::open(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut positions = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Can't read {}: {}", path, e))?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        positions.push(parse_position(&line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?);
+    }
+    Ok(positions)
+}
+
+//Entry point for: rchess tune <positions> <output> [--epochs N] [--iterations N] [--learning-rate X]
+//Every epoch runs gradient descent and one pass of local search, parameters are saved after each epoch.
+pub fn run_cli(args: &[String], params: &EvalParams) -> Result<(), String> {
+    let usage = "usage: rchess tune <positions> <output> [--epochs N] [--iterations N] [--learning-rate X]";
+    if args.len() < 2 {
+        return Err(usage.to_string());
+    }
+    let mut epochs = DEFAULT_EPOCHS;
+    let mut iterations = DEFAULT_ITERATIONS;
+    let mut learning_rate = DEFAULT_LEARNING_RATE;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or_else(|| format!("Value expected after {}\n{}", option, usage))?;
+        let invalid = || format!("Invalid value of {}: {}\n{}", option, value, usage);
+        match option.as_str() {
+            "--epochs" => epochs = value.parse().map_err(|_| invalid())?,
+            "--iterations" => iterations = value.parse().map_err(|_| invalid())?,
+            "--learning-rate" => learning_rate = value.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("Unknown option {}\n{}", option, usage))
+        }
+    }
+
+    let positions = load_positions(&args[0])?;
+    println!("Loaded {} positions", positions.len());
+    let mut tuner = Tuner::new(&positions, params.clone());
+    println!("K: {:.3}, error: {:.6}", tuner.k(), tuner.error());
+    for epoch in 1..=epochs {
+        let error = tuner.gradient_descent(iterations, learning_rate, &mut |i, error| {
+            println!("Epoch {} iteration {}: error {:.6}", epoch, i, error);
+        });
+        println!("Epoch {} gradient descent: error {:.6}", epoch, error);
+        let error = tuner.local_search(&mut |name, value| println!("{} {}", name, value));
+        println!("Epoch {} local search: error {:.6}", epoch, error);
+        ::std::fs::write(&args[1], tuner.params().to_text()).map_err(|e| format!("Can't write {}: {}", args[1], e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+use tuner::*;
+
+#[test]
+fn parse_position_test() {
+    let start = parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    assert_eq!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 [0.5]"), Ok((start, 0.5)));
+    assert_eq!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - \"1-0\";"), Ok((start, 1.0)));
+    assert_eq!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 0-1"), Ok((start, 0.0)));
+    assert_eq!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 1/2-1/2"), Ok((start, 0.5)));
+    assert_eq!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 35 | e2e4 | 1-0"), Ok((start, 1.0)));
+    assert!(parse_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 2.0").is_err());
+    assert!(parse_position("1-0").is_err());
+}
+
+#[test]
+fn quiet_position_test() {
+    ::tables::init_tables();
+    let evaluator = SimpleEvaluator::new();
+    let tables = OrderingTables::new();
+    //the hanging knight is taken
+    let pos = parse_fen("k7/3p4/8/4n3/8/8/8/4RK2 w - - 0 1").unwrap();
+    let quiet = quiet_position(&evaluator, &tables, &pos);
+    assert_eq!(quiet.board.get_piece(Square::new(4, 4)), Some(Piece(Rook, White)));
+    assert_eq!(quiet.next_to_move, Black);
+    //the knight defended by the pawn is not taken
+    let pos = parse_fen("k7/8/3p4/4n3/8/8/8/4RK2 w - - 0 1").unwrap();
+    assert_eq!(quiet_position(&evaluator, &tables, &pos), pos);
+}
+
+#[test]
+fn tuner_test() {
+    ::tables::init_tables();
+    //white wins all games with the bishop pair and draws with bishop and knight
+    let fens = [("2k5/ppp5/8/8/8/8/PPP5/2K1BB2 w - - 0 1", 1.0),
+                ("2k5/ppp5/8/8/8/8/PPP5/2K1BN2 w - - 0 1", 0.5),
+                ("4k3/ppp5/8/8/8/8/PPP5/3BBK2 b - - 0 1", 1.0),
+                ("4k3/ppp5/8/8/8/8/PPP5/3NBK2 b - - 0 1", 0.5),
+                ("8/1k6/1pp5/8/8/6P1/5PP1/2BB2K1 w - - 0 1", 1.0),
+                ("8/1k6/1pp5/8/8/6P1/5PP1/2NB2K1 w - - 0 1", 0.5)];
+    let positions: Vec<(Position, f64)> = fens.iter().map(|&(fen, r)| (parse_fen(fen).unwrap(), r)).collect();
+    let mut tuner = Tuner::new(&positions, EvalParams::default());
+    let initial = tuner.error();
+    let mut reports = 0;
+    let error = tuner.gradient_descent(200, 1.0, &mut |_, _| reports += 1);
+    assert_eq!(reports, 2);
+    assert!(error < initial);
+    assert!(tuner.params().bishop_pair.mg > EvalParams::default().bishop_pair.mg);
+    //parameters without coefficients are not changed by gradient descent
+    assert_eq!(tuner.params().king_attackers_scale, EvalParams::default().king_attackers_scale);
+    assert!(tuner.local_search(&mut |_, _| {}) <= error);
+}
+
+}
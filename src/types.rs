@@ -1,5 +1,6 @@
 use std::fmt;
 use bitset::{BitSet, SquareIter};
+use eval::psq_value;
 pub use self::Color::*;
 pub use self::Kind::*;
 pub use self::CastlingRight::*;
@@ -152,6 +153,26 @@ impl CastlingRight {
     }
 }
 
+//files of the rooks that take part in castling, in standard chess they are h and a,
+//in Chess960 they depend on the starting position
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct CastlingFiles {
+    pub king_side : u8,
+    pub queen_side : u8
+}
+
+pub static STANDARD_CASTLING_FILES: CastlingFiles = CastlingFiles { king_side: 7, queen_side: 0 };
+
+//where the king and the rook are before and after castling, the king always goes to the g or c file
+//and the rook next to it to the f or d file, both pieces can stay where they are
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct CastlingSquares {
+    pub king_from : Square,
+    pub king_to : Square,
+    pub rook_from : Square,
+    pub rook_to : Square
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
 pub struct OrdinaryMoveInfo {
     pub from: Square,
@@ -295,6 +316,60 @@ impl Board {
         }
     }
 
+    #[inline]
+    pub fn occupied(self) -> BitSet {
+        self.whites | self.blacks
+    }
+
+    //returns pieces of both colors that attack a given square.
+    //sliders are blocked only by pieces in occupied_set, this way
+    //x-ray attacks can be found by removing pieces from it.
+    pub fn attackers_to(&self, sq:Square, occupied_set:BitSet) -> BitSet {
+        use move_gen::{gen_rook_moves, gen_bishop_moves};
+        use tables::{get_knight_moves_mask, get_king_moves_mask, get_white_pawn_attacks_mask,
+                     get_black_pawn_attacks_mask};
+        let empty = BitSet::empty();
+        //pawns attacking sq are the ones that sq would attack if it was a pawn of the opposite color
+        let white_pawns = get_black_pawn_attacks_mask(sq) & self.pawns & self.whites;
+        let black_pawns = get_white_pawn_attacks_mask(sq) & self.pawns & self.blacks;
+        (gen_rook_moves(occupied_set, empty, sq) & (self.rooks | self.queens))
+            | (gen_bishop_moves(occupied_set, empty, sq) & (self.bishops | self.queens))
+            | (get_knight_moves_mask(sq) & self.knights)
+            | (get_king_moves_mask(sq) & self.kings)
+            | white_pawns | black_pawns
+    }
+
+    //returns all squares attacked by pieces of a given color
+    pub fn attack_map(&self, color:Color) -> BitSet {
+        use move_gen::{gen_rook_moves, gen_bishop_moves, gen_queen_moves, gen_knight_moves, gen_king_moves};
+        use tables::{get_white_pawn_attacks_mask, get_black_pawn_attacks_mask};
+        let occupied_set = self.occupied();
+        let empty = BitSet::empty();
+        let mut result = BitSet::empty();
+        for sq in self.get_pieces(Pawn, color) {
+            result = result | match color {
+                White => get_white_pawn_attacks_mask(sq),
+                Black => get_black_pawn_attacks_mask(sq)
+            };
+        }
+        for sq in self.get_pieces(Knight, color) {
+            result = result | gen_knight_moves(empty, sq);
+        }
+        for sq in self.get_pieces(Bishop, color) {
+            result = result | gen_bishop_moves(occupied_set, empty, sq);
+        }
+        for sq in self.get_pieces(Rook, color) {
+            result = result | gen_rook_moves(occupied_set, empty, sq);
+        }
+        for sq in self.get_pieces(Queen, color) {
+            result = result | gen_queen_moves(occupied_set, empty, sq);
+        }
+        for sq in self.get_pieces(King, color) {
+            result = result | gen_king_moves(empty, sq);
+        }
+        result
+    }
+
     #[inline]
     pub fn get_piece_bitset(self, kind:Kind) -> BitSet {
         match kind {
@@ -335,8 +410,14 @@ pub struct Position {
     pub next_to_move : Color,
     pub white_castling : CastlingRight,
     pub black_castling : CastlingRight,
+    pub white_castling_files : CastlingFiles,
+    pub black_castling_files : CastlingFiles,
     pub en_passant : Option<Square>,
-    pub half_moves_since_action : u8
+    pub half_moves_since_action : u8,
+    //material and piece-square score with the default evaluation parameters, white minus black,
+    //updated by apply_move, so the evaluation doesn't have to loop over all pieces.
+    //Evaluators with other parameters ignore it and compute the score themselves.
+    pub psq : ::eval::TaperedScore
 }
 
 impl Position {
@@ -344,27 +425,86 @@ impl Position {
         ::move_gen::LegalMovesIterator::new(self)
     }
 
+    pub fn gen_moves_with_mode(&self, mode: ::move_gen::GenMode) -> ::move_gen::LegalMovesIterator {
+        ::move_gen::LegalMovesIterator::with_mode(self, mode)
+    }
+
+    //returns true if a move takes an enemy piece (including en passant)
+    pub fn is_capture(&self, mv:&Move) -> bool {
+        match *mv {
+            OrdinaryMove(ref mi) => self.board.get_color_bitset(self.next_to_move.inverse()).get(mi.to)
+                || (mi.kind == Pawn && Some(mi.to) == self.en_passant),
+            _ => false
+        }
+    }
+
+    //returns true if a move is neither a capture nor a promotion
+    pub fn is_quiet(&self, mv:&Move) -> bool {
+        match *mv {
+            OrdinaryMove(ref mi) => mi.promotion.is_none() && !self.is_capture(mv),
+            _ => true
+        }
+    }
+
     pub fn is_checkmate(&self) -> bool {
         self.is_check() && self.gen_moves().count() == 0
     }
 
+    //returns enemy pieces that attack the king of the side to move
+    pub fn checkers(&self) -> BitSet {
+        let color = self.next_to_move;
+        match self.board.get_pieces(King, color).next() {
+            Some(king_sq) => self.board.attackers_to(king_sq, self.board.occupied())
+                             & self.board.get_color_bitset(color.inverse()),
+            None => BitSet::empty()
+        }
+    }
+
     pub fn is_check(&self) -> bool {
-        let test_area = self.board.get_color_bitset(self.next_to_move) & self.board.kings;
-        ::move_gen::is_under_attack(&self.board, self.next_to_move.inverse(), test_area)
+        !self.checkers().is_empty()
+    }
+
+    //the same position seen from the other side: the board is mirrored vertically and colors are swapped,
+    //so a white pawn on e2 becomes a black pawn on e7, black moves instead of white and gets white's castling rights
+    pub fn flip_colors(&self) -> Position {
+        let board = &self.board;
+        Position {
+            board: Board {
+                whites:  board.blacks.swap(),
+                blacks:  board.whites.swap(),
+                pawns:   board.pawns.swap(),
+                bishops: board.bishops.swap(),
+                knights: board.knights.swap(),
+                rooks:   board.rooks.swap(),
+                queens:  board.queens.swap(),
+                kings:   board.kings.swap()
+            },
+            full_moves: self.full_moves,
+            next_to_move: self.next_to_move.inverse(),
+            white_castling: self.black_castling,
+            black_castling: self.white_castling,
+            white_castling_files: self.black_castling_files,
+            black_castling_files: self.white_castling_files,
+            en_passant: self.en_passant.map(|sq| Square(sq.0 ^ 56)),
+            half_moves_since_action: self.half_moves_since_action,
+            psq: -self.psq
+        }
     }
 
     pub fn apply_move(&mut self, mv:&Move) -> Option<Piece> {
-        use squares::*;
         let color = self.next_to_move;
         match *mv {
             OrdinaryMove (ref mi) => {
                 let mut captured_piece = self.board.get_piece(mi.to);
                 debug_assert!(self.board.get_piece(mi.from).expect("src sq is empty").kind()
                     == mi.kind, "move piece is inconsistent with board piece");
-                self.board.clear_square(mi.from);
+                if let Some(piece) = captured_piece {
+                    self.psq -= psq_value(piece, mi.to);
+                }
+                self.remove_piece(mi.from, Piece(mi.kind, color));
                 match mi.kind {
                     Queen | Bishop | Knight | Rook => {
-                        self.board.set_piece(mi.to, Piece(mi.kind, color));
+                        self.put_piece(mi.to, Piece(mi.kind, color));
                         self.update_stats_after_move(captured_piece.is_some());
                         if mi.kind == Rook {
                             self.remove_rook_castling_right(mi.from, color);
@@ -374,7 +514,7 @@ impl Position {
                         let piece_after_move = mi.promotion.unwrap_or(Pawn);
                         debug_assert!(mi.promotion.is_none() || mi.to.rank() == 7 || mi.to.rank() == 0,
                             "promotion before final rank");
-                        self.board.set_piece(mi.to, Piece(piece_after_move, color));
+                        self.put_piece(mi.to, Piece(piece_after_move, color));
 
                         //en passant capture
                         if Some(mi.to) == self.en_passant {
@@ -386,7 +526,7 @@ impl Position {
                             captured_piece = self.board.get_piece(jump_sq);
                             debug_assert!(captured_piece.expect("en passant capture of empty sq")
                                 .kind() == Pawn, "en passant capture of not a pawn");
-                            self.board.clear_square(jump_sq);
+                            self.remove_piece(jump_sq, Piece(Pawn, color.inverse()));
                         }
 
                         self.update_stats_after_move(true);
@@ -399,7 +539,7 @@ impl Position {
                         }
                     }
                     King => {
-                        self.board.set_piece(mi.to, Piece(mi.kind, color));
+                        self.put_piece(mi.to, Piece(mi.kind, color));
                         self.update_stats_after_move(captured_piece.is_some());
                         self.remove_king_castling_right(color);
                     }
@@ -414,34 +554,13 @@ impl Position {
                 }
                 captured_piece
             }
-            CastleQueenSide => {
-                if color == White {
-                    self.board.clear_square(a1);
-                    self.board.clear_square(e1);
-                    self.board.set_piece(c1, Piece(King, White));
-                    self.board.set_piece(d1, Piece(Rook, White));
-                } else {
-                    self.board.clear_square(a8);
-                    self.board.clear_square(e8);
-                    self.board.set_piece(c8, Piece(King, Black));
-                    self.board.set_piece(d8, Piece(Rook, Black));
-                }
-                self.update_stats_after_move(false);
-                self.remove_king_castling_right(color);
-                None
-            }
-            CastleKingSide => {
-                if color == White {
-                    self.board.clear_square(h1);
-                    self.board.clear_square(e1);
-                    self.board.set_piece(g1, Piece(King, White));
-                    self.board.set_piece(f1, Piece(Rook, White));
-                } else {
-                    self.board.clear_square(h8);
-                    self.board.clear_square(e8);
-                    self.board.set_piece(g8, Piece(King, Black));
-                    self.board.set_piece(f8, Piece(Rook, Black));
-                }
+            CastleKingSide | CastleQueenSide => {
+                //both pieces are removed first, in Chess960 the king can go where the rook was and vice versa
+                let cs = self.castling_squares(mv);
+                self.remove_piece(cs.rook_from, Piece(Rook, color));
+                self.remove_piece(cs.king_from, Piece(King, color));
+                self.put_piece(cs.king_to, Piece(King, color));
+                self.put_piece(cs.rook_to, Piece(Rook, color));
                 self.update_stats_after_move(false);
                 self.remove_king_castling_right(color);
                 None
@@ -453,6 +572,18 @@ impl Position {
         }
     }
 
+    #[inline]
+    fn put_piece(&mut self, sq:Square, piece:Piece) {
+        self.board.set_piece(sq, piece);
+        self.psq += psq_value(piece, sq);
+    }
+
+    #[inline]
+    fn remove_piece(&mut self, sq:Square, piece:Piece) {
+        self.board.clear_square(sq);
+        self.psq -= psq_value(piece, sq);
+    }
+
     #[inline]
     fn remove_king_castling_right(&mut self, color:Color) {
         if color == White {
@@ -464,19 +595,42 @@ impl Position {
 
     #[inline]
     fn remove_rook_castling_right(&mut self, rook_sq:Square, color:Color) {
-        use squares::*;
-        if color == White {
-            if rook_sq == a1 {
-                self.white_castling = self.white_castling.remove(QueenCastling);
-            } else if rook_sq == h1 {
-                self.white_castling = self.white_castling.remove(KingCastling);
-            }
-        } else {
-            if rook_sq == a8 {
-                self.black_castling = self.black_castling.remove(QueenCastling);
-            } else if rook_sq == h8 {
-                self.black_castling = self.black_castling.remove(KingCastling);
-            }
+        let back_rank = if color == White { 0 } else { 7 };
+        if rook_sq.rank() != back_rank {
+            return;
+        }
+        let files = self.castling_files(color);
+        let castling = if color == White { &mut self.white_castling } else { &mut self.black_castling };
+        if rook_sq.file() == files.queen_side {
+            *castling = castling.remove(QueenCastling);
+        } else if rook_sq.file() == files.king_side {
+            *castling = castling.remove(KingCastling);
+        }
+    }
+
+    #[inline]
+    pub fn castling_files(&self, color:Color) -> CastlingFiles {
+        if color == White { self.white_castling_files } else { self.black_castling_files }
+    }
+
+    //squares of the king and the rook for a castling move of the side to move,
+    //the king must be on its back rank
+    pub fn castling_squares(&self, mv:&Move) -> CastlingSquares {
+        let color = self.next_to_move;
+        let rank = if color == White { 0 } else { 7 };
+        let king_from = self.board.get_pieces(King, color).find(|sq| sq.rank() == rank)
+            .expect("castling king is not on its back rank");
+        let files = self.castling_files(color);
+        let (rook_file, king_file, rook_to_file) = match *mv {
+            CastleKingSide => (files.king_side, 6, 5),
+            CastleQueenSide => (files.queen_side, 2, 3),
+            _ => panic!("{} is not a castling move", mv)
+        };
+        CastlingSquares {
+            king_from: king_from,
+            king_to: Square::new(king_file, rank),
+            rook_from: Square::new(rook_file, rank),
+            rook_to: Square::new(rook_to_file, rank)
         }
     }
 
@@ -538,4 +692,35 @@ fn get_pieces_test() {
     }
 }
 
+#[test]
+fn attacks_test() {
+    use bitset::BitSet;
+    ::tables::init_tables();
+    let to_bitset = |squares:&[Square]| {
+        let mut result = BitSet::empty();
+        for &sq in squares.iter() {
+            result.set(sq, true);
+        }
+        result
+    };
+
+    let position = parse_fen("3r2k1/2q2ppp/8/1N1p4/2P1P3/8/3R4/3R2K1 b - - 0 1").unwrap();
+    let board = position.board;
+    assert_eq!(board.attackers_to(d5, board.occupied()), to_bitset(&[c4, e4, d2, d8]));
+    assert_eq!(board.attackers_to(d4, board.occupied()), to_bitset(&[b5, d2]));
+    //rook d1 attacks through d2, rook d8 through d5
+    let occupied = board.occupied() & !to_bitset(&[d2, d5]);
+    assert_eq!(board.attackers_to(d4, occupied), to_bitset(&[b5, d2, d1, d8]));
+
+    let white_attacks = board.attack_map(White);
+    assert!(white_attacks.get(d5) && white_attacks.get(d6) && white_attacks.get(a7) && white_attacks.get(h2));
+    assert!(!white_attacks.get(d7) && !white_attacks.get(e5) && !white_attacks.get(a8));
+    assert_eq!(board.attack_map(White) & board.blacks, to_bitset(&[c7, d5]));
+    assert_eq!(position.checkers(), BitSet::empty());
+
+    let position = parse_fen("4k3/8/5N2/8/8/8/8/4R1K1 b - - 0 1").unwrap();
+    assert_eq!(position.checkers(), to_bitset(&[e1, f6]));
+    assert!(position.is_check());
+}
+
 }
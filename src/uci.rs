@@ -4,7 +4,11 @@ use fen::parse_fen;
 use std::str::{Chars, FromStr};
 use std::fmt;
 use std::io::{BufRead, Write};
-use search::search;
+use std::sync::Arc;
+use eval::{Evaluator, Score, SimpleEvaluator};
+use eval_params::EvalParams;
+use nnue::{Network, NnueEvaluator};
+use search::{SearchEngine, SearchLimits, SearchResult, MATE_SCORE, MATE_BOUND};
 use types::*;
 pub use self::SearchOption::*;
 pub use self::Command::*;
@@ -13,15 +17,27 @@ pub use self::Response::*;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct UciMove {
-    from:Square,
-    to:Square,
-    promotion:Option<Kind>
+    pub from:Square,
+    pub to:Square,
+    pub promotion:Option<Kind>
+}
+
+//time left on the clocks and increments in milliseconds
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ClockInfo {
+    wtime: usize,
+    btime: usize,
+    winc: usize,
+    binc: usize,
+    movestogo: Option<usize>
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum SearchOption {
     MovetimeMsc(usize),
     Depth(usize),
+    Nodes(usize),
+    Clock(ClockInfo),
     Infinity
 }
 
@@ -32,8 +48,11 @@ pub enum Command {
     CmdUciNewGame,
     CmdPosition (Position, Vec<UciMove>),
     CmdGo (SearchOption),
+    CmdSetOption (String, String),
     CmdStop,
     CmdQuit,
+    //not a part of UCI, prints the evaluation of the current position split into terms
+    CmdEval,
     CmdUnknown
 }
 
@@ -44,11 +63,23 @@ pub enum Response {
     RspReadyOk,
     RspBestMove (UciMove),
     RspInfo (String),
+    RspOption (String),
+    //free text without a prefix, like the evaluation table
+    RspText (String),
 }
 
-#[derive(Clone, Copy, Debug)]
+static MAX_THREADS: usize = 256;
+
 pub struct UciEngine {
-    position: Position
+    position: Position,
+    //kept between searches, so the transposition table and move ordering tables
+    //filled on earlier moves are reused, they are cleared by ucinewgame
+    search_engine: SearchEngine,
+    eval_params: Arc<EvalParams>,
+    network: Option<Arc<Network>>,
+    use_nnue: bool,
+    //castling moves are written as king takes rook
+    chess960: bool
 }
 
 impl fmt::Display for UciMove {
@@ -69,7 +100,9 @@ impl fmt::Display for Response {
             RspUciOk => write!(f, "uciok"),
             RspReadyOk => write!(f, "readyok"),
             RspInfo(ref info) => write!(f, "info {}", info),
+            RspOption(ref option) => write!(f, "option {}", option),
             RspBestMove(ref mv) => write!(f, "bestmove {}", mv),
+            RspText(ref text) => write!(f, "{}", text),
         }
     }
 }
@@ -77,11 +110,40 @@ impl fmt::Display for Response {
 impl UciEngine {
 
     pub fn new() -> UciEngine {
+        UciEngine::with_eval_params(EvalParams::default())
+    }
+
+    //parameters loaded from a file given on the command line, the EvalFile option can replace them
+    pub fn with_eval_params(params: EvalParams) -> UciEngine {
+        let eval_params = Arc::new(params);
+        let mut search_engine = SearchEngine::new();
+        search_engine.set_eval_params(eval_params.clone());
         UciEngine {
-            position: parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
+            position: parse_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap(),
+            search_engine: search_engine,
+            eval_params: eval_params,
+            network: None,
+            use_nnue: false,
+            chess960: false
         }
     }
 
+    //network loaded from a file given on the command line, it is used instead of the classical evaluation
+    pub fn set_network(&mut self, network: Network) {
+        self.network = Some(Arc::new(network));
+        self.use_nnue = true;
+        self.update_network();
+    }
+
+    fn update_network(&mut self) {
+        let network = self.active_network();
+        self.search_engine.set_network(network);
+    }
+
+    fn active_network(&self) -> Option<Arc<Network>> {
+        if self.use_nnue { self.network.clone() } else { None }
+    }
+
     pub fn std_main_loop(&mut self) {
         use std::io::{stdin, stdout};
         let input = stdin();
@@ -99,25 +161,50 @@ impl UciEngine {
 
             let responses  = match cmd {
                 CmdUci => vec![RspId("name".to_string(), "rchess".to_string()),
-                           RspId("author".to_string(), "EZ".to_string()), RspUciOk],
+                           RspId("author".to_string(), "EZ".to_string()),
+                           RspOption(format!("name Threads type spin default 1 min 1 max {}", MAX_THREADS)),
+                           RspOption("name Deterministic type check default false".to_string()),
+                           RspOption("name EvalFile type string default <empty>".to_string()),
+                           RspOption("name UseNNUE type check default false".to_string()),
+                           RspOption("name NnueFile type string default <empty>".to_string()),
+                           RspOption("name UCI_Chess960 type check default false".to_string()),
+                           RspUciOk],
                 CmdIsReady => vec![RspReadyOk],
-                CmdUciNewGame => vec![],
+                CmdUciNewGame => {
+                    self.search_engine.clear();
+                    vec![]
+                },
                 CmdPosition (ref pos, ref moves) => {
                     self.set_position(pos, moves);
                     vec![]
                 }
                 CmdGo (opt) => {
-                    match self.think(opt) {
+                    match self.think(opt, output) {
                         Some(mv) => {
-                            let uci_move = move_to_uci(&mv, self.position.next_to_move);
-                            vec![RspInfo(format!("currmove {}", uci_move)), RspBestMove(uci_move)]
+                            let uci_move = move_to_uci(&mv, &self.position, self.chess960);
+                            vec![RspBestMove(uci_move)]
                         },
                         None => {
                             vec![RspInfo(format!("string, no moves found!"))]
                         }
                     }
                 },
+                CmdSetOption (ref name, ref value) => {
+                    match self.set_option(name, value) {
+                        Ok(()) => vec![],
+                        Err(e) => vec![RspInfo(format!("string {}", e))]
+                    }
+                },
                 CmdStop => vec![],
+                CmdEval => {
+                    let evaluator = SimpleEvaluator::with_params((*self.eval_params).clone());
+                    let mut text = evaluator.breakdown(&self.position).to_string();
+                    if let Some(network) = self.active_network() {
+                        let score = NnueEvaluator::new(network).eval(&self.position);
+                        text += &format!("\nNNUE evaluation: {} (white side)", score);
+                    }
+                    vec![RspText(text)]
+                },
                 CmdQuit => { break },
                 CmdUnknown => vec![]
             };
@@ -129,69 +216,137 @@ impl UciEngine {
         }
     }
 
-    fn think(&self, opt: SearchOption) -> Option<Move> {
-        let depth = match opt {
-            Depth(d) => d,
-            Infinity => 5,
-            MovetimeMsc(_) => 3,
+    fn think(&mut self, opt: SearchOption, output: &mut dyn Write) -> Option<Move> {
+        let limits = match opt {
+            Depth(d) => SearchLimits::depth(d),
+            Nodes(n) => SearchLimits::nodes(n as u64),
+            Infinity => SearchLimits::depth(5),
+            MovetimeMsc(t) => SearchLimits::movetime(t as u64),
+            Clock(ref clock) => {
+                let (time_left, increment) = if self.position.next_to_move == White {
+                    (clock.wtime, clock.winc)
+                } else {
+                    (clock.btime, clock.binc)
+                };
+                SearchLimits::clock(time_left as u64, increment as u64, clock.movestogo.map(|m| m as u64))
+            }
         };
-        search(&self.position, depth)
+        let (position, deterministic, chess960) = (self.position, self.search_engine.options.deterministic, self.chess960);
+        let result = self.search_engine.search_with_limits(&self.position, &limits, &mut |r: &SearchResult| {
+            writeln!(output, "{}", RspInfo(format_info(r, &position, deterministic, chess960))).ok();
+            output.flush().ok();
+        });
+        result.best_move
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "Threads" => {
+                match usize::from_str(value) {
+                    Ok(n) if n >= 1 && n <= MAX_THREADS => self.search_engine.options.threads = n,
+                    _ => return Err(format!("Threads must be a number from 1 to {}", MAX_THREADS))
+                }
+            },
+            "Deterministic" => {
+                match value {
+                    "true" => self.search_engine.options.deterministic = true,
+                    "false" => self.search_engine.options.deterministic = false,
+                    _ => return Err("Deterministic must be true or false".to_string())
+                }
+            },
+            //empty value restores the default parameters
+            "EvalFile" => {
+                let params = match value {
+                    "" | "<empty>" => EvalParams::default(),
+                    path => EvalParams::load(path)?
+                };
+                self.eval_params = Arc::new(params);
+                self.search_engine.set_eval_params(self.eval_params.clone());
+            },
+            "UseNNUE" => {
+                match value {
+                    "true" if self.network.is_none() => return Err("No network is loaded, set NnueFile first".to_string()),
+                    "true" => self.use_nnue = true,
+                    "false" => self.use_nnue = false,
+                    _ => return Err("UseNNUE must be true or false".to_string())
+                }
+                self.update_network();
+            },
+            "UCI_Chess960" => {
+                match value {
+                    "true" => self.chess960 = true,
+                    "false" => self.chess960 = false,
+                    _ => return Err("UCI_Chess960 must be true or false".to_string())
+                }
+            },
+            //loading a network doesn't select it, an empty value unloads it
+            "NnueFile" => {
+                self.network = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Arc::new(Network::load(path)?))
+                };
+                self.update_network();
+            },
+            _ => return Err(format!("Unknown option {}", name))
+        }
+        Ok(())
     }
 
     fn set_position(&mut self, pos: &Position, moves:&Vec<UciMove>) {
         self.position = *pos;
         for uci_move in moves.iter() {
-            let mv = uci_to_move(&self.position.board, uci_move);
+            let mv = uci_to_move(&self.position, uci_move, self.chess960);
             self.position.apply_move(&mv);
         }
     }
 }
 
-fn move_to_uci(mv: &Move, color: Color) -> UciMove {
-    use squares::*;
+fn format_score(score: Score) -> String {
+    if score > MATE_BOUND {
+        format!("mate {}", (MATE_SCORE - score + 1) / 2)
+    } else if score < -MATE_BOUND {
+        format!("mate -{}", (MATE_SCORE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+//time and nps are left out in deterministic mode, so the output doesn't depend on the machine
+fn format_info(result: &SearchResult, position: &Position, deterministic: bool, chess960: bool) -> String {
+    let mut info = format!("depth {} score {} nodes {}", result.depth, format_score(result.score), result.nodes);
+    if !deterministic {
+        let nps = result.nodes * 1000 / ::std::cmp::max(1, result.time_ms);
+        info += &format!(" nps {} time {}", nps, result.time_ms);
+    }
+    if let Some(mv) = result.best_move {
+        info += &format!(" pv {}", move_to_uci(&mv, position, chess960));
+    }
+    info
+}
+
+//Castling is written as the move of the king two squares to the side, in Chess960 mode
+//the king takes its own rook, so castling can't be confused with other king moves
+pub fn move_to_uci(mv: &Move, position: &Position, chess960: bool) -> UciMove {
     match *mv {
         OrdinaryMove(ref mi) => UciMove {
             from: mi.from,
             to: mi.to,
             promotion: mi.promotion
         },
-        CastleKingSide => {
-            if color == White  {
-                UciMove {
-                    from: e1,
-                    to: g1,
-                    promotion: None
-                }
-            } else {
-                UciMove {
-                    from: e8,
-                    to: g8,
-                    promotion: None
-                }
-            }
-        }
-        CastleQueenSide => {
-            if color == White {
-                UciMove {
-                    from: e1,
-                    to: c1,
-                    promotion: None
-                }
-            } else {
-                UciMove {
-                    from: e8,
-                    to: c8,
-                    promotion: None
-                }
+        CastleKingSide | CastleQueenSide => {
+            let cs = position.castling_squares(mv);
+            UciMove {
+                from: cs.king_from,
+                to: if chess960 { cs.rook_from } else { cs.king_to },
+                promotion: None
             }
         }
         NullMove => panic!("null move is not supposed to get out to uci")
     }
 }
 
-fn uci_to_move(board: &Board, mv: &UciMove) -> Move {
-    use squares::*;
-    let piece = board.get_piece(mv.from);
+fn uci_to_move(position: &Position, mv: &UciMove, chess960: bool) -> Move {
+    let piece = position.board.get_piece(mv.from);
     let piece = match piece {
         Some(p) => p,
         None => {
@@ -200,24 +355,17 @@ fn uci_to_move(board: &Board, mv: &UciMove) -> Move {
         }
     };
 
-    if piece == Piece(King, White) {
-        if mv.from == e1 {
-            if mv.to == g1 {
-                return CastleKingSide;
-            }
-            if mv.to == c1 {
-                return CastleQueenSide;
-            }
-        }
-    }
-    else if piece == Piece(King, Black) {
-        if mv.from == e8 {
-            if mv.to == g8 {
-                return CastleKingSide;
-            }
-            if mv.to == c8 {
-                return CastleQueenSide;
-            }
+    if piece.kind() == King {
+        let castling = if chess960 {
+            //the king takes its own rook
+            position.board.get_piece(mv.to) == Some(Piece(Rook, piece.color()))
+        } else {
+            let back_rank = if piece.color() == White { 0 } else { 7 };
+            mv.from.rank() == back_rank && mv.to.rank() == back_rank
+                && mv.from.file() == 4 && (mv.to.file() == 6 || mv.to.file() == 2)
+        };
+        if castling {
+            return if mv.to.file() > mv.from.file() { CastleKingSide } else { CastleQueenSide };
         }
     }
 
@@ -246,6 +394,9 @@ pub fn parse_command(line: &str) -> Result<Command, String> {
     if line.starts_with("quit") {
         return Ok(CmdQuit);
     }
+    if line.starts_with("eval") {
+        return Ok(CmdEval);
+    }
     if line.starts_with("position") {
         let position_index = match line.find(' ') {
             Some(index) => index + 1,
@@ -272,6 +423,9 @@ pub fn parse_command(line: &str) -> Result<Command, String> {
         };
         return Ok(CmdPosition (position, moves))
     }
+    if line.starts_with("setoption") {
+        return parse_setoption(line);
+    }
     if line.starts_with("go") {
         let option_index = match line.find(' ') {
             Some(index) => index + 1,
@@ -283,7 +437,23 @@ pub fn parse_command(line: &str) -> Result<Command, String> {
     Err(format!("Unexpected command {}", line))
 }
 
+//setoption name <id> [value <x>]
+fn parse_setoption(line: &str) -> Result<Command, String> {
+    let name_index = match line.find("name ") {
+        Some(index) => index + "name ".len(),
+        None => return Err("name is expected after 'setoption' command".to_string())
+    };
+    let (name, value) = match line.find(" value ") {
+        Some(index) if index >= name_index => (&line[name_index..index], &line[index + " value ".len()..]),
+        _ => (&line[name_index..], "")
+    };
+    Ok(CmdSetOption(name.trim().to_string(), value.trim().to_string()))
+}
+
 fn pares_search_option(input: &str) -> Result<SearchOption, String> {
+    if input.contains("wtime") || input.contains("btime") {
+        return parse_clock(input);
+    }
     if input.starts_with("movetime") {
         let num_str = skip_spaces(&input["movetime".len()..input.len()]);
         let time:usize = match FromStr::from_str(num_str) {
@@ -291,6 +461,13 @@ fn pares_search_option(input: &str) -> Result<SearchOption, String> {
             _ => { return Err("Movetime is invalid or not provided".to_string()); }
         };
         return Ok (MovetimeMsc(time));
+    } else if input.starts_with("nodes") {
+        let num_str = skip_spaces(&input["nodes".len()..input.len()]);
+        let nodes:usize = match FromStr::from_str(num_str) {
+            Ok(t) => t,
+            _ => { return Err("Nodes is invalid or not provided".to_string()); }
+        };
+        return Ok (Nodes(nodes));
     } else if input.starts_with("depth") {
         let num_str = skip_spaces(&input["depth".len()..input.len()]);
         let depth:usize = match FromStr::from_str(num_str) {
@@ -303,6 +480,26 @@ fn pares_search_option(input: &str) -> Result<SearchOption, String> {
     }
 }
 
+fn parse_clock(input: &str) -> Result<SearchOption, String> {
+    let mut clock = ClockInfo { wtime: 0, btime: 0, winc: 0, binc: 0, movestogo: None };
+    let mut tokens = input.split_whitespace();
+    while let Some(name) = tokens.next() {
+        let value: usize = match tokens.next().map(FromStr::from_str) {
+            Some(Ok(v)) => v,
+            _ => return Err(format!("Value of {} is invalid or not provided", name))
+        };
+        match name {
+            "wtime" => clock.wtime = value,
+            "btime" => clock.btime = value,
+            "winc" => clock.winc = value,
+            "binc" => clock.binc = value,
+            "movestogo" => clock.movestogo = Some(value),
+            _ => return Err(format!("Unexpected search option {}", name))
+        }
+    }
+    Ok(Clock(clock))
+}
+
 fn skip_spaces<'a>(s: &'a str) ->&'a str {
     let index = s.find(|c: char| !c.is_whitespace());
     match index {
@@ -361,6 +558,12 @@ fn parse_go_command_test() {
     assert_eq!(parse_command("go infinite"), Ok(CmdGo(Infinity)));
     assert_eq!(parse_command("go"), Ok(CmdGo(Infinity)));
     assert_eq!(parse_command("go movetime 123"), Ok(CmdGo(MovetimeMsc(123))));
+    assert_eq!(parse_command("go nodes 10000"), Ok(CmdGo(Nodes(10000))));
+    assert_eq!(parse_command("go wtime 60000 btime 55000 winc 1000 binc 1000"),
+               Ok(CmdGo(Clock(ClockInfo { wtime: 60000, btime: 55000, winc: 1000, binc: 1000, movestogo: None }))));
+    assert_eq!(parse_command("go wtime 1000 btime 2000 movestogo 5"),
+               Ok(CmdGo(Clock(ClockInfo { wtime: 1000, btime: 2000, winc: 0, binc: 0, movestogo: Some(5) }))));
+    assert!(parse_command("go wtime 1000 btime").is_err());
 }
 
 #[test]
@@ -394,6 +597,13 @@ fn parse_position_command_test() {
     assert_eq!(moves.len(), 0);
 }
 
+#[test]
+fn parse_setoption_command_test() {
+    assert_eq!(parse_command("setoption name Threads value 4\n"), Ok(CmdSetOption("Threads".to_string(), "4".to_string())));
+    assert_eq!(parse_command("setoption name Clear Hash"), Ok(CmdSetOption("Clear Hash".to_string(), "".to_string())));
+    assert!(parse_command("setoption Threads").is_err());
+}
+
 #[test]
 fn parse_simple_commands_test() {
     assert_eq!(parse_command("uci\n"), Ok(CmdUci));
@@ -401,8 +611,124 @@ fn parse_simple_commands_test() {
     assert_eq!(parse_command("isready\n"), Ok(CmdIsReady));
     assert_eq!(parse_command("quit\n"), Ok(CmdQuit));
     assert_eq!(parse_command("stop\n"), Ok(CmdStop));
+    assert_eq!(parse_command("eval\n"), Ok(CmdEval));
     assert_eq!(parse_command("go depth 3\n"), Ok(CmdGo(Depth(3))));
 }
 
+#[test]
+fn chess960_moves_test() {
+    ::tables::init_tables();
+    let uci_move = |from, to| UciMove { from: from, to: to, promotion: None };
+    let pos = parse_fen("4k3/8/8/8/8/8/8/RK5R w HA - 0 1").unwrap();
+    assert_eq!(move_to_uci(&CastleQueenSide, &pos, true), uci_move(b1, a1));
+    assert_eq!(move_to_uci(&CastleKingSide, &pos, true), uci_move(b1, h1));
+    assert_eq!(move_to_uci(&CastleKingSide, &pos, false), uci_move(b1, g1));
+    assert_eq!(uci_to_move(&pos, &uci_move(b1, a1), true), CastleQueenSide);
+    assert_eq!(uci_to_move(&pos, &uci_move(b1, h1), true), CastleKingSide);
+    assert_eq!(uci_to_move(&pos, &uci_move(b1, c1), true), Move::new(King, b1, c1, None));
+
+    let pos = parse_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+    assert_eq!(move_to_uci(&CastleKingSide, &pos, false), uci_move(e8, g8));
+    assert_eq!(move_to_uci(&CastleKingSide, &pos, true), uci_move(e8, h8));
+    assert_eq!(uci_to_move(&pos, &uci_move(e8, c8), false), CastleQueenSide);
+    assert_eq!(uci_to_move(&pos, &uci_move(e8, a8), true), CastleQueenSide);
+    assert_eq!(uci_to_move(&pos, &uci_move(e8, d8), false), Move::new(King, e8, d8, None));
+}
+
+fn uci_output(commands: &str) -> String {
+    use std::io::{Read, Cursor};
+    let mut input = Cursor::new(commands.as_bytes().to_vec());
+    let mut output = Cursor::new(Vec::new());
+    UciEngine::new().main_loop(&mut input, &mut output);
+    let mut result = String::new();
+    output.set_position(0);
+    output.read_to_string(&mut result).unwrap();
+    result
+}
+
+#[test]
+fn search_engine_reuse_test() {
+    ::tables::init_tables();
+    //the second search starts with the tables filled by the first one, ucinewgame clears them
+    let go = "position fen r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10\ngo depth 5\n";
+    let output = uci_output(&format!("{}{}ucinewgame\n{}", go, go, go));
+    let nodes: Vec<u64> = output.lines().filter(|line| line.starts_with("info depth 5 "))
+        .map(|line| line.split(" nodes ").nth(1).unwrap().split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(nodes.len(), 3);
+    assert!(nodes[1] < nodes[0]);
+    assert_eq!(nodes[2], nodes[0]);
+}
+
+#[test]
+fn eval_file_option_test() {
+    ::tables::init_tables();
+    let path = ::std::env::temp_dir().join("rchess_eval_file_option_test.txt");
+    let path = path.to_str().unwrap();
+    let go = "setoption name Deterministic value true\n\
+              position fen r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10\n\
+              go depth 4\n";
+    let default = uci_output(go);
+
+    //default parameters saved to a file give the same search
+    ::std::fs::write(path, ::eval_params::EvalParams::default().to_text()).unwrap();
+    assert_eq!(uci_output(&format!("setoption name EvalFile value {}\n{}", path, go)), default);
+
+    //knights worth nothing change the search, an empty value restores the defaults
+    ::std::fs::write(path, "piece_values.mg 100 330 0 500 900 20000\npiece_values.eg 120 320 0 540 950 20000\n").unwrap();
+    let changed = uci_output(&format!("setoption name EvalFile value {}\n{}", path, go));
+    assert!(changed != default);
+    assert_eq!(uci_output(&format!("setoption name EvalFile value {}\nsetoption name EvalFile value <empty>\n{}", path, go)), default);
+    ::std::fs::remove_file(path).ok();
+
+    let error = uci_output("setoption name EvalFile value no/such/file\n");
+    assert!(error.starts_with("info string Can't read no/such/file"));
+    assert!(uci_output("uci\n").contains("option name EvalFile type string default <empty>"));
+}
+
+#[test]
+fn eval_command_test() {
+    ::tables::init_tables();
+    let output = uci_output("position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1\neval\n");
+    let evaluator = ::eval::SimpleEvaluator::new();
+    let pos = parse_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+    assert_eq!(output.trim_end(), evaluator.breakdown(&pos).to_string());
+    assert!(output.contains("King safety"));
+}
+
+#[test]
+fn nnue_option_test() {
+    ::tables::init_tables();
+    let path = ::std::env::temp_dir().join("rchess_nnue_option_test.nnue");
+    let path = path.to_str().unwrap();
+    //one neuron counts the pawns of a side, the output is the difference of the counts in pawns
+    let mut network = ::nnue::Network::new(1, ::nnue::QA * 10);
+    for sq in 0..64 {
+        network.feature_weights[Pawn as usize * 64 + sq] = 10;
+    }
+    network.output_weights = vec![::nnue::QB as i8, -::nnue::QB as i8];
+    network.write(&mut ::std::fs::File::create(path).unwrap()).unwrap();
+
+    let go = "setoption name Deterministic value true\n\
+              position fen 4k3/ppp1pppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1\n\
+              go depth 3\n";
+    let classical = uci_output(go);
+    //a loaded network is not used until it is selected
+    assert_eq!(uci_output(&format!("setoption name NnueFile value {}\n{}", path, go)), classical);
+    let nnue = uci_output(&format!("setoption name NnueFile value {}\nsetoption name UseNNUE value true\n{}", path, go));
+    assert!(nnue != classical);
+    assert!(nnue.contains("score cp 100 "));
+    assert_eq!(uci_output(&format!("setoption name NnueFile value {}\nsetoption name UseNNUE value true\n\
+                                    setoption name UseNNUE value false\n{}", path, go)), classical);
+
+    let eval = uci_output(&format!("setoption name NnueFile value {}\nsetoption name UseNNUE value true\n\
+                                    position fen 4k3/ppp1pppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1\neval\n", path));
+    assert!(eval.trim_end().ends_with("NNUE evaluation: 100 (white side)"));
+    ::std::fs::remove_file(path).ok();
+
+    assert!(uci_output("setoption name UseNNUE value true\n").starts_with("info string No network is loaded"));
+    assert!(uci_output("setoption name NnueFile value no/such/file\n").starts_with("info string Can't read no/such/file"));
+    assert!(uci_output("uci\n").contains("option name UseNNUE type check default false"));
 }
 
+}